@@ -0,0 +1,69 @@
+use std::{fs::File, io::{self, Write}, path::PathBuf};
+
+use clap::{Args, ValueEnum};
+use reqwest::Method;
+
+use crate::{ClientError, Server, parse_time};
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+impl Format {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Ndjson => "ndjson",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+/// Exports the samples of the data sources matching the filters, with their timestamps and metadata.
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(long, value_enum, default_value = "csv")]
+    format: Format,
+    /// Glob pattern the manager id must match.
+    #[arg(long)]
+    manager: Option<String>,
+    /// Glob pattern the data source id must match.
+    #[arg(long)]
+    source: Option<String>,
+    /// Tag the data sources must have, as `key:value_glob`, may be given several times.
+    #[arg(long)]
+    tag: Vec<String>,
+    /// Start of the range, in milliseconds since the unix epoch, or as an RFC 3339 date.
+    #[arg(long, value_parser = parse_time)]
+    from: Option<u64>,
+    /// End of the range, in milliseconds since the unix epoch, or as an RFC 3339 date.
+    #[arg(long, value_parser = parse_time)]
+    to: Option<u64>,
+    /// File the export is written to, instead of standard output.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+pub fn run(server: &Server, args: ExportArgs) -> Result<(), ClientError> {
+    let mut query = vec![("format", args.format.as_str().to_string())];
+    query.extend(args.manager.map(|manager| ("manager", manager)));
+    query.extend(args.source.map(|source| ("source", source)));
+    query.extend(args.tag.into_iter().map(|tag| ("tag", tag)));
+    query.extend(args.from.map(|from| ("from", from.to_string())));
+    query.extend(args.to.map(|to| ("to", to.to_string())));
+
+    let mut response = server.send(server.request(Method::GET, "/data_source/export").query(&query))?;
+
+    // The export is copied as it arrives, so it's never held in memory as a whole.
+    let mut output: Box<dyn Write> = match args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    response.copy_to(&mut output)?;
+    output.flush()?;
+
+    Ok(())
+}
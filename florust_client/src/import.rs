@@ -0,0 +1,137 @@
+use std::{fs::File, io::{self, Read}, path::{Path, PathBuf}};
+
+use clap::{Args, ValueEnum};
+use reqwest::{Method, blocking::Body};
+use serde::Deserialize;
+
+use crate::{ClientError, Server};
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum DuplicatePolicy {
+    Skip,
+    Replace,
+    Error,
+}
+
+/// Imports samples with their own timestamps into the history of a data source.
+#[derive(Args)]
+pub struct ImportArgs {
+    manager_id: String,
+    data_source_id: String,
+    /// CSV or NDJSON file to import, `-` to read standard input.
+    file: PathBuf,
+    /// Format of the file, guessed from its extension if not given.
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Rows hold data for the manager to decode, like uploaded data, rather than values.
+    #[arg(long)]
+    decode: bool,
+    /// Only validate the import, reporting what it would do.
+    #[arg(long)]
+    dry_run: bool,
+    /// What happens to samples whose timestamp is already used.
+    #[arg(long, value_enum, default_value = "skip")]
+    on_duplicate: DuplicatePolicy,
+}
+
+#[derive(Deserialize)]
+struct RowError {
+    line: u64,
+    error: String,
+}
+
+#[derive(Deserialize)]
+struct ImportReport {
+    dry_run: bool,
+    rows: usize,
+    imported: usize,
+    skipped: usize,
+    replaced: usize,
+    evicted: usize,
+    invalid_rows: usize,
+    errors: Vec<RowError>,
+}
+
+/// Error returned by the server when rows are invalid.
+#[derive(Deserialize)]
+enum ImportError {
+    InvalidImport(ImportReport),
+}
+
+fn guess_format(file: &Path) -> Format {
+    match file.extension().and_then(|extension| extension.to_str()) {
+        Some("ndjson" | "jsonl") => Format::Ndjson,
+        _ => Format::Csv,
+    }
+}
+
+pub fn run(server: &Server, args: ImportArgs) -> Result<(), ClientError> {
+    let format = args.format.unwrap_or_else(|| guess_format(&args.file));
+    let (format, content_type) = match format {
+        Format::Csv => ("csv", "text/csv"),
+        Format::Ndjson => ("ndjson", "application/x-ndjson"),
+    };
+    let on_duplicate = match args.on_duplicate {
+        DuplicatePolicy::Skip => "skip",
+        DuplicatePolicy::Replace => "replace",
+        DuplicatePolicy::Error => "error",
+    };
+
+    let body = if args.file.as_os_str() == "-" {
+        let mut body = Vec::new();
+        io::stdin().read_to_end(&mut body)?;
+        Body::from(body)
+    }
+    else {
+        Body::from(File::open(&args.file)?)
+    };
+
+    let request = server.request(Method::POST, &format!("/data_source/import/{}/{}", args.manager_id, args.data_source_id))
+        .query(&[
+            ("format", format),
+            ("on_duplicate", on_duplicate),
+            ("decode", if args.decode { "true" } else { "false" }),
+            ("dry_run", if args.dry_run { "true" } else { "false" }),
+        ])
+        .header("Content-Type", content_type)
+        .body(body);
+
+    let report: ImportReport = match server.send(request) {
+        Ok(response) => response.json()?,
+        Err(ClientError::Server { status, body }) => {
+            let Ok(ImportError::InvalidImport(report)) = serde_json::from_str(&body) else {
+                return Err(ClientError::Server { status, body });
+            };
+
+            for error in &report.errors {
+                eprintln!("line {}: {}", error.line, error.error);
+            }
+            if report.invalid_rows > report.errors.len() {
+                eprintln!("... and {} more invalid rows", report.invalid_rows - report.errors.len());
+            }
+
+            return Err(ClientError::InvalidImport(report.invalid_rows));
+        },
+        Err(err) => return Err(err),
+    };
+
+    println!(
+        "{} {} of {} rows into {}/{}, {} skipped, {} replaced, {} evicted",
+        if report.dry_run { "Would import" } else { "Imported" },
+        report.imported,
+        report.rows,
+        args.manager_id,
+        args.data_source_id,
+        report.skipped,
+        report.replaced,
+        report.evicted
+    );
+
+    Ok(())
+}
@@ -1 +1,124 @@
-fn main() {}
+mod export;
+mod import;
+mod snapshot;
+
+use std::process::ExitCode;
+
+use chrono::DateTime;
+use clap::{Parser, Subcommand};
+use reqwest::{Method, blocking::{Client, RequestBuilder, Response}};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Server responded with status {status}: {body}")]
+    Server {
+        status: u16,
+        body: String,
+    },
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Import has {0} invalid rows, nothing was imported")]
+    InvalidImport(usize),
+}
+
+/// Command line client of a Florust server.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// URL of the Florust server.
+    #[arg(long, env = "FLORUST_SERVER", default_value = "http://127.0.0.1:8000")]
+    server: String,
+    /// Token sent as a bearer token, as issued to a data source, or configured for a principal.
+    #[arg(long, env = "FLORUST_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    Export(export::ExportArgs),
+    Import(import::ImportArgs),
+    Snapshot(snapshot::SnapshotArgs),
+    Restore(snapshot::RestoreArgs),
+}
+
+/// Connection to a Florust server, authenticating every request with the token, if one was given.
+pub struct Server {
+    url: String,
+    token: Option<String>,
+    client: Client,
+}
+
+impl Server {
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.client.request(method, format!("{}{}", self.url.trim_end_matches('/'), path));
+
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Sends a request, turning error statuses into errors.
+    pub fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let response = request.send()?;
+        if !response.status().is_success() {
+            return Err(ClientError::Server {
+                status: response.status().as_u16(),
+                body: response.text().unwrap_or_default(),
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+/// Parses a time given on the command line, either in milliseconds since the unix epoch, or as an RFC 3339
+/// date and time, like `2024-05-01T12:00:00Z`.
+pub fn parse_time(time: &str) -> Result<u64, String> {
+    if let Ok(millis) = time.parse() {
+        return Ok(millis);
+    }
+
+    DateTime::parse_from_rfc3339(time)
+        .map_err(|err| format!("{} is neither milliseconds since the unix epoch, nor an RFC 3339 date: {}", time, err))
+        .and_then(|time| u64::try_from(time.timestamp_millis()).map_err(|_| format!("{} is before the unix epoch", time)))
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // Exports, imports and snapshots may take a while, so requests are only bounded by the server.
+    let client = match Client::builder().timeout(None).build() {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("Failed to create HTTP client: {}", err);
+            return ExitCode::FAILURE;
+        },
+    };
+    let server = Server {
+        url: cli.server,
+        token: cli.token,
+        client,
+    };
+
+    let result = match cli.command {
+        Command::Export(args) => export::run(&server, args),
+        Command::Import(args) => import::run(&server, args),
+        Command::Snapshot(args) => snapshot::snapshot(&server, args),
+        Command::Restore(args) => snapshot::restore(&server, args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -0,0 +1,56 @@
+use std::{fs::File, io::{self, Write}, path::PathBuf};
+
+use clap::Args;
+use reqwest::{Method, blocking::Body};
+use serde::Deserialize;
+
+use crate::{ClientError, Server};
+
+/// Saves every manager's data sources and logged data to a snapshot, which requires an admin token.
+#[derive(Args)]
+pub struct SnapshotArgs {
+    /// File the snapshot is written to, instead of standard output.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+/// Restores a snapshot, which requires an admin token.
+#[derive(Args)]
+pub struct RestoreArgs {
+    /// Snapshot to restore.
+    file: PathBuf,
+    /// Replace data sources which already exist on the server, rather than failing.
+    #[arg(long)]
+    replace: bool,
+}
+
+#[derive(Deserialize)]
+struct RestoreSummary {
+    data_sources: usize,
+    samples: usize,
+}
+
+pub fn snapshot(server: &Server, args: SnapshotArgs) -> Result<(), ClientError> {
+    let mut response = server.send(server.request(Method::GET, "/admin/snapshot"))?;
+
+    let mut output: Box<dyn Write> = match args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    response.copy_to(&mut output)?;
+    output.flush()?;
+
+    Ok(())
+}
+
+pub fn restore(server: &Server, args: RestoreArgs) -> Result<(), ClientError> {
+    let request = server.request(Method::POST, "/admin/restore")
+        .query(&[("replace", if args.replace { "true" } else { "false" })])
+        .header("Content-Type", "application/json")
+        .body(Body::from(File::open(&args.file)?));
+
+    let summary: RestoreSummary = server.send(request)?.json()?;
+    println!("Restored {} data sources, with {} samples", summary.data_sources, summary.samples);
+
+    Ok(())
+}
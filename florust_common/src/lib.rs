@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use rocket::FromForm;
 
 pub mod server;
@@ -9,3 +11,37 @@ use serde::{Serialize, Deserialize};
 pub struct UploadedData {
     pub data: Vec<u8>
 }
+
+/// Sent back to a data source after it successfully registered. The token must be presented as a bearer
+/// token when uploading data for, or unregistering, the data source.
+#[derive(Serialize, Deserialize)]
+pub struct RegisteredDataSource {
+    pub token: String
+}
+
+/// Descriptive information attached to a data source, which Florust stores but doesn't interpret.
+#[derive(FromForm, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct DataSourceMetadata {
+    /// Human readable name of the data source.
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Units the data source's values are in, like `°C` or `%`.
+    #[serde(default)]
+    pub units: Option<String>,
+    /// Free form key/value tags, like `bed = "3"` or `species = "basil"`.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Sent by a data source when registering. The data passed on to the data source manager, the metadata, and
+/// the expected reporting interval are all optional.
+#[derive(FromForm, Serialize, Deserialize)]
+pub struct DataSourceRegistration {
+    pub data: Option<Vec<u8>>,
+    #[serde(default)]
+    pub metadata: DataSourceMetadata,
+    /// How often, in seconds, the data source intends to upload data. The data source is reported as stale
+    /// once it stops doing so.
+    #[serde(default)]
+    pub expected_interval_secs: Option<u64>,
+}
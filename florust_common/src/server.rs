@@ -4,6 +4,8 @@ use rocket::async_trait;
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
 
+use crate::DataSourceMetadata;
+
 #[derive(Serialize, Deserialize, Error, Debug)]
 pub enum FlorustServerPluginError {
     #[error("Attempted to register data source ID ({0}), but it already exists.")]
@@ -21,7 +23,31 @@ pub enum FlorustServerPluginError {
 #[derive(Serialize, Deserialize, Error, Debug)]
 pub enum DataSourceManagerError {
     #[error("DataSourceManager was given invalid data: {0}")]
-    InvalidData(String)
+    InvalidData(String),
+    #[error("DataSourceManager failed to poll the data source: {0}")]
+    PollFailed(String)
+}
+
+/// A data source the data source manager reads from itself, rather than waiting for it to upload data, see
+/// [`DataSourceManager::polled_sources`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PolledSource {
+    pub id: String,
+    pub metadata: DataSourceMetadata,
+    /// How often, in seconds, the data source should be polled, unless the server's configuration says
+    /// otherwise. The server's default interval is used if [`None`].
+    pub interval_secs: Option<u64>,
+}
+
+/// Health of a data source manager, as reported by [`DataSourceManager::health`].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum PluginHealth {
+    Healthy,
+    /// The manager works, but not as well as it should, like a sensor responding slowly.
+    Degraded(String),
+    /// The manager doesn't work, like a device it reads from being unreachable.
+    Unhealthy(String),
 }
 
 /// A specialized [`Result`](result::Result) type for [`DataSourceManager`] operations.
@@ -39,6 +65,16 @@ pub trait DataSourceManager<T>: Sync + Send {
     /// Returns the id associated with the data manager.
     fn manager_id(&self) -> &'static str;
 
+    /// Returns the unit of the values produced by the data manager, like `°C`, `hPa` or `%`.
+    /// 
+    /// Data sources registering without units of their own are assigned this unit, which allows Florust to
+    /// convert their values to other units of the same quantity when they are read. The `unit` key of the
+    /// plugin's `plugin.toml` takes precedence over this method. The default implementation returns [`None`],
+    /// meaning the values have no known unit.
+    fn unit(&self) -> Option<&str> {
+        None
+    }
+
     /// Called when a new data source registers itself to the id belonging to the data source manager.
     /// 
     /// Florust will handle keeping track of what data sources are registered to your data source manager's
@@ -93,6 +129,56 @@ pub trait DataSourceManager<T>: Sync + Send {
     /// 
     /// Returns the value parsed from the data, or a [`DataSourceManagerError`] in case of an error.
     async fn update_data(&self, id: &str, data: &[u8]) -> Result<T>;
+
+    /// Called once when the Florust server has launched, after the data sources of the snapshot it restores,
+    /// if any, were registered.
+    /// 
+    /// This method only exists for data source managers which need to spawn background tasks, or open
+    /// connections, before data sources start uploading data. The default implementation does nothing.
+    async fn on_start(&self) {}
+
+    /// Returns the data sources the data source manager polls, called once after [`on_start`](Self::on_start).
+    /// 
+    /// Florust registers these data sources if they aren't already, and calls [`poll`](Self::poll) for each
+    /// of them on a schedule, logging the values it returns as if the data source had uploaded them. The
+    /// default implementation returns no data sources.
+    async fn polled_sources(&self) -> Vec<PolledSource> {
+        Vec::new()
+    }
+
+    /// Called when a data source returned by [`polled_sources`](Self::polled_sources) is due to be polled.
+    /// 
+    /// Returns the current value of the data source, or a [`DataSourceManagerError`] in case of an error, in
+    /// which case Florust polls the data source less often until it succeeds again. The default
+    /// implementation always fails, as it is only called for data sources the manager declared.
+    async fn poll(&self, id: &str) -> Result<T> {
+        Err(DataSourceManagerError::PollFailed(format!("{} isn't polled by this data source manager", id)))
+    }
+
+    /// Called periodically, every `tick_interval_secs` as set in the server's lifecycle configuration.
+    /// 
+    /// Returns values computed by the data source manager, like values derived from other values, along with
+    /// the ids of the data sources they are logged for, as if those data sources had uploaded them. Values
+    /// of data sources which aren't registered are dropped. The default implementation returns no values.
+    async fn tick(&self) -> Vec<(String, T)> {
+        Vec::new()
+    }
+
+    /// Returns the health of the data source manager, which the Florust server reports to its users.
+    /// 
+    /// This method should return quickly, as it is called whenever health is requested. The default
+    /// implementation returns [`PluginHealth::Healthy`].
+    async fn health(&self) -> PluginHealth {
+        PluginHealth::Healthy
+    }
+
+    /// Called once when the Florust server shuts down, after it stopped accepting uploads, and the uploads
+    /// that were in progress finished.
+    /// 
+    /// This method only exists for data source managers which hold resources, like open files or
+    /// connections, that should be released before the server exits. The default implementation does
+    /// nothing.
+    async fn on_shutdown(&self) {}
 }
 
 /// One of three specialized types of [`DataSourceManager`] that is responsible for producing data of
@@ -107,6 +193,45 @@ pub type UIntegerDataSourceManager = dyn DataSourceManager<u64>;
 /// type [`f64`] from data provided by a data source.
 pub type FloatDataSourceManager = dyn DataSourceManager<f64>;
 
+/// Conversion of the values plugins compute as [`f64`], like scaled sensor readings, to the type of data a
+/// [`DataSourceManager`] produces, for plugins which can produce any of the three types. Integers are
+/// rounded, and values which don't fit in the type are rejected.
+pub trait FromScaled: Sized {
+    fn from_scaled(value: f64) -> result::Result<Self, String>;
+}
+
+impl FromScaled for f64 {
+    fn from_scaled(value: f64) -> result::Result<Self, String> {
+        Ok(value)
+    }
+}
+
+impl FromScaled for i64 {
+    fn from_scaled(value: f64) -> result::Result<Self, String> {
+        let value = value.round();
+        // i64::MAX isn't representable as an f64, and rounds up to 2^63, which doesn't fit.
+        if (i64::MIN as f64..9223372036854775808.0).contains(&value) {
+            Ok(value as i64)
+        }
+        else {
+            Err(format!("{} doesn't fit in an i64", value))
+        }
+    }
+}
+
+impl FromScaled for u64 {
+    fn from_scaled(value: f64) -> result::Result<Self, String> {
+        let value = value.round();
+        // Likewise, u64::MAX rounds up to 2^64.
+        if (0.0..18446744073709551616.0).contains(&value) {
+            Ok(value as u64)
+        }
+        else {
+            Err(format!("{} doesn't fit in a u64", value))
+        }
+    }
+}
+
 /// A type representing a double boxed trait. This type is double boxed as a boxed trait object is a fat
 /// pointer which would be difficult to transport across FFI boundaries. Boxing the box resolves this issue
 /// by making it a normal sized pointer.
@@ -119,4 +244,33 @@ pub type CreateIIntegerDataSourceManager = unsafe extern "C" fn(Box<Option<toml:
 pub type CreateUIntegerDataSourceManager = unsafe extern "C" fn(Box<Option<toml::map::Map<String, toml::Value>>>) -> FFIResult<UIntegerDataSourceManager>;
 
 /// A function that returns a [`FFIBoxTrait`] which contains an [`FloatDataSourceManager`].
-pub type CreateFloatDataSourceManager = unsafe extern "C" fn(Box<Option<toml::map::Map<String, toml::Value>>>) -> FFIResult<FloatDataSourceManager>;
\ No newline at end of file
+pub type CreateFloatDataSourceManager = unsafe extern "C" fn(Box<Option<toml::map::Map<String, toml::Value>>>) -> FFIResult<FloatDataSourceManager>;
+
+#[cfg(test)]
+mod tests {
+    use super::FromScaled;
+
+    #[test]
+    fn scaled_values_are_rounded_to_integers() {
+        assert_eq!(i64::from_scaled(-2.6), Ok(-3));
+        assert_eq!(u64::from_scaled(2.4), Ok(2));
+        assert_eq!(f64::from_scaled(2.4), Ok(2.4));
+    }
+
+    #[test]
+    fn scaled_values_must_fit() {
+        assert!(u64::from_scaled(-1.0).is_err());
+        assert!(i64::from_scaled(f64::NAN).is_err());
+        assert!(i64::from_scaled(1e30).is_err());
+        assert!(u64::from_scaled(f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn scaled_values_may_reach_the_bounds_of_integers() {
+        assert_eq!(i64::from_scaled(-9223372036854775808.0), Ok(i64::MIN));
+        assert_eq!(i64::from_scaled(9223372036854774784.0), Ok(9223372036854774784));
+        assert!(i64::from_scaled(9223372036854775808.0).is_err());
+        assert_eq!(u64::from_scaled(18446744073709549568.0), Ok(18446744073709549568));
+        assert!(u64::from_scaled(18446744073709551616.0).is_err());
+    }
+}
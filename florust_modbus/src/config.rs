@@ -0,0 +1,132 @@
+use florust_common::DataSourceMetadata;
+use serde::Deserialize;
+
+fn default_manager_id() -> String { "FlorustModbus".to_string() }
+fn default_unit_id() -> u8 { 1 }
+fn default_timeout_ms() -> u64 { 1000 }
+fn default_baud_rate() -> u32 { 9600 }
+fn default_data_bits() -> u8 { 8 }
+fn default_stop_bits() -> u8 { 1 }
+fn default_scale() -> f64 { 1.0 }
+
+/// The `modbus` section of the plugin's `plugin.toml`.
+#[derive(Deserialize)]
+pub struct ModbusConfig {
+    /// Id of the manager, which must be unique when the plugin is loaded more than once, like once per
+    /// Modbus device.
+    #[serde(default = "default_manager_id")]
+    pub manager_id: String,
+    pub transport: TransportConfig,
+    /// Unit id of the device, unless a data source says otherwise.
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+    /// Longest wait for the device to connect, or to answer a request, in milliseconds.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransportConfig {
+    /// Modbus TCP, to a device at `address`, like `192.168.1.50:502`.
+    Tcp {
+        address: String,
+    },
+    /// Modbus RTU, over the serial port at `device`, like `/dev/ttyUSB0`.
+    Rtu {
+        device: String,
+        #[serde(default = "default_baud_rate")]
+        baud_rate: u32,
+        #[serde(default)]
+        parity: Parity,
+        #[serde(default = "default_data_bits")]
+        data_bits: u8,
+        #[serde(default = "default_stop_bits")]
+        stop_bits: u8,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+/// The table a data source is read from.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterKind {
+    Coil,
+    DiscreteInput,
+    InputRegister,
+    HoldingRegister,
+}
+
+impl RegisterKind {
+    pub fn is_bit(self) -> bool {
+        matches!(self, RegisterKind::Coil | RegisterKind::DiscreteInput)
+    }
+}
+
+/// How the registers of a data source are decoded. Ignored for coils and discrete inputs, which read as 0
+/// or 1.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterFormat {
+    #[default]
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl RegisterFormat {
+    /// Number of registers holding a value.
+    pub fn registers(self) -> u16 {
+        match self {
+            RegisterFormat::U16 | RegisterFormat::I16 => 1,
+            RegisterFormat::U32 | RegisterFormat::I32 | RegisterFormat::F32 => 2,
+        }
+    }
+}
+
+/// Order of the registers of 32 bit values. Bytes are always big endian within a register.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WordOrder {
+    /// The first register holds the most significant half.
+    #[default]
+    Big,
+    /// The first register holds the least significant half.
+    Little,
+}
+
+/// A data source, read from a coil or register of the device. Its value is the decoded value multiplied by
+/// `scale`, plus `offset`.
+#[derive(Deserialize, Clone)]
+pub struct SourceConfig {
+    pub id: String,
+    pub kind: RegisterKind,
+    /// Address of the coil or first register, starting at 0.
+    pub address: u16,
+    #[serde(default)]
+    pub format: RegisterFormat,
+    #[serde(default)]
+    pub word_order: WordOrder,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+    /// Unit id to read from, overriding the device's.
+    pub unit_id: Option<u8>,
+    /// How often the data source is polled, in seconds, unless the server's configuration says otherwise.
+    pub interval_secs: Option<u64>,
+    #[serde(flatten)]
+    pub metadata: DataSourceMetadata,
+}
@@ -0,0 +1,287 @@
+//! Florust plugin polling the coils and registers of Modbus TCP and RTU devices.
+//!
+//! The plugin is configured through the `modbus` section of its `plugin.toml`, see `docs/plugins/modbus.md`.
+
+mod config;
+mod protocol;
+
+use std::{collections::{BTreeMap, HashMap}, marker::PhantomData, sync::{Arc, Mutex, mpsc}, thread, time::Duration};
+
+use config::{ModbusConfig, RegisterFormat, SourceConfig, WordOrder};
+use florust_common::server::{
+    self,
+    DataSourceManager,
+    DataSourceManagerError,
+    FFIResult,
+    FloatDataSourceManager,
+    FromScaled,
+    IIntegerDataSourceManager,
+    PluginHealth,
+    PolledSource,
+    UIntegerDataSourceManager,
+};
+use protocol::Connection;
+use rocket::{async_trait, futures::channel::oneshot};
+use toml::{Table, Value};
+
+/// Decodes the data read for a data source, before scaling it.
+fn decode(source: &SourceConfig, data: &[u8]) -> f64 {
+    if source.kind.is_bit() {
+        return (data[0] & 1) as f64;
+    }
+
+    let words: Vec<u16> = data.chunks_exact(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect();
+    let double_word = || match source.word_order {
+        WordOrder::Big => (words[0] as u32) << 16 | words[1] as u32,
+        WordOrder::Little => (words[1] as u32) << 16 | words[0] as u32,
+    };
+
+    match source.format {
+        RegisterFormat::U16 => words[0] as f64,
+        RegisterFormat::I16 => words[0] as i16 as f64,
+        RegisterFormat::U32 => double_word() as f64,
+        RegisterFormat::I32 => double_word() as i32 as f64,
+        RegisterFormat::F32 => f32::from_bits(double_word()) as f64,
+    }
+}
+
+/// A poll of a data source, handed to the worker.
+struct Request {
+    source: SourceConfig,
+    reply: oneshot::Sender<Result<f64, String>>,
+}
+
+/// Owns the connection to the device, and carries out polls one at a time, as Modbus devices, and RTU buses
+/// especially, only handle one request at a time. Runs on its own thread, as reads block until the device
+/// answers or times out.
+fn worker(config: Arc<ModbusConfig>, requests: mpsc::Receiver<Request>, health: Arc<Mutex<PluginHealth>>) {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut connection = None;
+    // Why the connection couldn't be opened, if it couldn't the last time it was tried.
+    let mut unreachable = None;
+    // Why the last poll of each data source failed, for those whose last poll failed.
+    let mut failing: BTreeMap<String, String> = BTreeMap::new();
+
+    for request in requests {
+        let source = &request.source;
+        if connection.is_none() {
+            match Connection::open(&config.transport, timeout) {
+                Ok(opened) => {
+                    connection = Some(opened);
+                    unreachable = None;
+                },
+                Err(err) => unreachable = Some(err.to_string()),
+            }
+        }
+
+        let reply = match &mut connection {
+            Some(opened) => {
+                let quantity = if source.kind.is_bit() { 1 } else { source.format.registers() };
+                match opened.read(source.unit_id.unwrap_or(config.unit_id), source.kind, source.address, quantity) {
+                    Ok(data) => {
+                        failing.remove(&source.id);
+                        Ok(decode(source, &data) * source.scale + source.offset)
+                    },
+                    Err(err) => {
+                        if err.is_fatal() {
+                            connection = None;
+                        }
+                        failing.insert(source.id.clone(), err.to_string());
+                        Err(err.to_string())
+                    },
+                }
+            },
+            None => Err(format!("failed to connect: {}", unreachable.as_deref().unwrap_or_default())),
+        };
+
+        if let Ok(mut health) = health.lock() {
+            *health = match &unreachable {
+                Some(reason) => PluginHealth::Unhealthy(format!("failed to connect: {}", reason)),
+                None if failing.is_empty() => PluginHealth::Healthy,
+                None => PluginHealth::Degraded(
+                    failing.iter().map(|(id, error)| format!("{}: {}", id, error)).collect::<Vec<_>>().join(", ")
+                ),
+            };
+        }
+        let _ = request.reply.send(reply);
+    }
+}
+
+pub struct ModbusManager<T> {
+    manager_id: &'static str,
+    config: Arc<ModbusConfig>,
+    sources: HashMap<String, SourceConfig>,
+    /// Sends polls to the worker, from the time the server starts until it shuts down.
+    worker: Mutex<Option<mpsc::Sender<Request>>>,
+    health: Arc<Mutex<PluginHealth>>,
+    data_type: PhantomData<fn() -> T>,
+}
+
+impl<T> ModbusManager<T> {
+    fn new(config: Option<Table>) -> server::Result<ModbusManager<T>> {
+        let invalid = |error: String| DataSourceManagerError::InvalidData(format!("invalid Modbus configuration: {}", error));
+
+        let config: ModbusConfig = config
+            .and_then(|mut config| config.remove("modbus"))
+            .ok_or_else(|| invalid("plugin.toml has no modbus section".to_string()))?
+            .try_into()
+            .map_err(|err: toml::de::Error| invalid(err.to_string()))?;
+
+        let sources = config.sources.iter()
+            .map(|source| (source.id.clone(), source.clone()))
+            .collect();
+
+        Ok(ModbusManager {
+            // The id must outlive the manager, and is only leaked once per loaded plugin.
+            manager_id: Box::leak(config.manager_id.clone().into_boxed_str()),
+            config: Arc::new(config),
+            sources,
+            worker: Mutex::new(None),
+            health: Arc::new(Mutex::new(PluginHealth::Healthy)),
+            data_type: PhantomData,
+        })
+    }
+
+    fn source(&self, id: &str) -> server::Result<&SourceConfig> {
+        self.sources.get(id)
+            .ok_or_else(|| DataSourceManagerError::InvalidData(format!("{} isn't a configured Modbus data source", id)))
+    }
+}
+
+#[async_trait]
+impl<T> DataSourceManager<T> for ModbusManager<T> where T: FromScaled + Send + 'static {
+    fn manager_id(&self) -> &'static str {
+        self.manager_id
+    }
+
+    async fn register(&self, id: String) -> server::Result<()> {
+        self.source(&id).map(|_| ())
+    }
+
+    async fn register_with_data(&self, id: String, _data: &[u8]) -> server::Result<()> {
+        self.source(&id).map(|_| ())
+    }
+
+    async fn deregister(&self, _id: &str) -> server::Result<()> {
+        Ok(())
+    }
+
+    async fn deregister_with_data(&self, _id: &str, _data: &[u8]) -> server::Result<()> {
+        Ok(())
+    }
+
+    async fn update_data(&self, id: &str, _data: &[u8]) -> server::Result<T> {
+        Err(DataSourceManagerError::InvalidData(format!("{} is polled from the Modbus device, and can't be uploaded", id)))
+    }
+
+    async fn on_start(&self) {
+        let (sender, receiver) = mpsc::channel();
+        let (config, health) = (Arc::clone(&self.config), Arc::clone(&self.health));
+        thread::spawn(move || worker(config, receiver, health));
+
+        if let Ok(mut worker) = self.worker.lock() {
+            *worker = Some(sender);
+        }
+    }
+
+    async fn polled_sources(&self) -> Vec<PolledSource> {
+        self.config.sources.iter()
+            .map(|source| PolledSource {
+                id: source.id.clone(),
+                metadata: source.metadata.clone(),
+                interval_secs: source.interval_secs,
+            })
+            .collect()
+    }
+
+    async fn poll(&self, id: &str) -> server::Result<T> {
+        let (reply, response) = oneshot::channel();
+        let request = Request { source: self.source(id)?.clone(), reply };
+
+        let sent = match self.worker.lock() {
+            Ok(worker) => worker.as_ref().is_some_and(|worker| worker.send(request).is_ok()),
+            Err(_) => false,
+        };
+        if !sent {
+            return Err(DataSourceManagerError::PollFailed("the Modbus worker isn't running".to_string()));
+        }
+
+        let value = response.await
+            .map_err(|_| DataSourceManagerError::PollFailed("the Modbus worker stopped".to_string()))?
+            .map_err(DataSourceManagerError::PollFailed)?;
+
+        T::from_scaled(value).map_err(DataSourceManagerError::InvalidData)
+    }
+
+    async fn health(&self) -> PluginHealth {
+        match self.health.lock() {
+            Ok(health) => health.clone(),
+            Err(_) => PluginHealth::Unhealthy("the Modbus worker panicked".to_string()),
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        // The worker stops, closing the connection, once it no longer receives polls.
+        if let Ok(mut worker) = self.worker.lock() {
+            worker.take();
+        }
+    }
+}
+
+fn create<T>(config: Option<Table>) -> server::Result<ModbusManager<T>> {
+    ModbusManager::new(config)
+}
+
+#[no_mangle]
+pub extern "C" fn create_iinteger_data_source_manager(config: Box<Option<toml::map::Map<String, Value>>>) -> FFIResult<IIntegerDataSourceManager> {
+    Box::new(create::<i64>(*config).map(|manager| Box::new(manager) as Box<IIntegerDataSourceManager>))
+}
+
+#[no_mangle]
+pub extern "C" fn create_uinteger_data_source_manager(config: Box<Option<toml::map::Map<String, Value>>>) -> FFIResult<UIntegerDataSourceManager> {
+    Box::new(create::<u64>(*config).map(|manager| Box::new(manager) as Box<UIntegerDataSourceManager>))
+}
+
+#[no_mangle]
+pub extern "C" fn create_float_data_source_manager(config: Box<Option<toml::map::Map<String, Value>>>) -> FFIResult<FloatDataSourceManager> {
+    Box::new(create::<f64>(*config).map(|manager| Box::new(manager) as Box<FloatDataSourceManager>))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{config::SourceConfig, decode};
+
+    fn source(kind: &str, format: &str, word_order: &str) -> SourceConfig {
+        toml::from_str(&format!(
+            "id = \"source\"\nkind = \"{}\"\naddress = 0\nformat = \"{}\"\nword_order = \"{}\"",
+            kind, format, word_order
+        )).unwrap()
+    }
+
+    #[test]
+    fn bits_decode_to_0_or_1() {
+        assert_eq!(decode(&source("coil", "u16", "big"), &[0b11]), 1.0);
+        assert_eq!(decode(&source("discrete_input", "u16", "big"), &[0b10]), 0.0);
+    }
+
+    #[test]
+    fn single_registers_are_decoded() {
+        assert_eq!(decode(&source("input_register", "u16", "big"), &[0xFF, 0xFE]), 65534.0);
+        assert_eq!(decode(&source("input_register", "i16", "big"), &[0xFF, 0xFE]), -2.0);
+    }
+
+    #[test]
+    fn double_registers_follow_the_word_order() {
+        let big = [0x00, 0x01, 0x00, 0x02];
+        assert_eq!(decode(&source("holding_register", "u32", "big"), &big), 65538.0);
+        assert_eq!(decode(&source("holding_register", "u32", "little"), &big), 131073.0);
+
+        let minus_two = [0xFF, 0xFF, 0xFF, 0xFE];
+        assert_eq!(decode(&source("holding_register", "i32", "big"), &minus_two), -2.0);
+        assert_eq!(decode(&source("holding_register", "i32", "little"), &[0xFF, 0xFE, 0xFF, 0xFF]), -2.0);
+
+        let [hi, lo] = [21.5f32.to_bits() >> 16, 21.5f32.to_bits() & 0xFFFF].map(|word| (word as u16).to_be_bytes());
+        assert_eq!(decode(&source("holding_register", "f32", "big"), &[hi, lo].concat()), 21.5);
+        assert_eq!(decode(&source("holding_register", "f32", "little"), &[lo, hi].concat()), 21.5);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{io::{self, Read, Write}, net::{TcpStream, ToSocketAddrs}, time::Duration};
+
+use serialport::{ClearBuffer, DataBits, SerialPort, StopBits};
+use thiserror::Error;
+
+use crate::config::{Parity, RegisterKind, TransportConfig};
+
+#[derive(Error, Debug)]
+pub enum ModbusError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Serial(#[from] serialport::Error),
+    #[error("device answered with exception code {0}")]
+    Exception(u8),
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+}
+
+impl ModbusError {
+    /// Whether the connection can't be trusted after the error, like after a timeout, after which the
+    /// response may still arrive and be mistaken for the response to the next request.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, ModbusError::Exception(_))
+    }
+}
+
+type Result<T> = std::result::Result<T, ModbusError>;
+
+/// CRC of RTU frames, sent least significant byte first.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+
+    crc
+}
+
+fn function_code(kind: RegisterKind) -> u8 {
+    match kind {
+        RegisterKind::Coil => 0x01,
+        RegisterKind::DiscreteInput => 0x02,
+        RegisterKind::HoldingRegister => 0x03,
+        RegisterKind::InputRegister => 0x04,
+    }
+}
+
+/// Checks the PDU of a response to a read, returning the data it holds.
+fn response_data(pdu: &[u8], function: u8, expected_bytes: usize) -> Result<Vec<u8>> {
+    match pdu {
+        [code, exception, ..] if *code == function | 0x80 => Err(ModbusError::Exception(*exception)),
+        [code, count, data @ ..] if *code == function => {
+            if *count as usize != expected_bytes || data.len() != expected_bytes {
+                return Err(ModbusError::InvalidResponse(format!("expected {} bytes, got {}", expected_bytes, data.len())));
+            }
+
+            Ok(data.to_vec())
+        },
+        _ => Err(ModbusError::InvalidResponse("unexpected function code".to_string())),
+    }
+}
+
+pub enum Connection {
+    Tcp {
+        stream: TcpStream,
+        transaction_id: u16,
+    },
+    Rtu(Box<dyn SerialPort>),
+}
+
+impl Connection {
+    pub fn open(transport: &TransportConfig, timeout: Duration) -> Result<Connection> {
+        match transport {
+            TransportConfig::Tcp { address } => {
+                let address = address.to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't resolve to an address", address)))?;
+                let stream = TcpStream::connect_timeout(&address, timeout)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                stream.set_nodelay(true)?;
+
+                Ok(Connection::Tcp { stream, transaction_id: 0 })
+            },
+            TransportConfig::Rtu { device, baud_rate, parity, data_bits, stop_bits } => {
+                let data_bits = match data_bits {
+                    5 => DataBits::Five,
+                    6 => DataBits::Six,
+                    7 => DataBits::Seven,
+                    _ => DataBits::Eight,
+                };
+                let parity = match parity {
+                    Parity::None => serialport::Parity::None,
+                    Parity::Even => serialport::Parity::Even,
+                    Parity::Odd => serialport::Parity::Odd,
+                };
+                let stop_bits = if *stop_bits == 2 { StopBits::Two } else { StopBits::One };
+
+                let port = serialport::new(device, *baud_rate)
+                    .data_bits(data_bits)
+                    .parity(parity)
+                    .stop_bits(stop_bits)
+                    .timeout(timeout)
+                    .open()?;
+
+                Ok(Connection::Rtu(port))
+            },
+        }
+    }
+
+    /// Reads `quantity` coils or registers starting at `address`, returning the data of the response, with
+    /// bits packed least significant first, or registers as big endian words.
+    pub fn read(&mut self, unit_id: u8, kind: RegisterKind, address: u16, quantity: u16) -> Result<Vec<u8>> {
+        let function = function_code(kind);
+        let [address_hi, address_lo] = address.to_be_bytes();
+        let [quantity_hi, quantity_lo] = quantity.to_be_bytes();
+        let pdu = [function, address_hi, address_lo, quantity_hi, quantity_lo];
+        let expected_bytes = if kind.is_bit() { (quantity as usize).div_ceil(8) } else { quantity as usize * 2 };
+
+        match self {
+            Connection::Tcp { stream, transaction_id } => {
+                *transaction_id = transaction_id.wrapping_add(1);
+                let [id_hi, id_lo] = transaction_id.to_be_bytes();
+                let mut request = vec![id_hi, id_lo, 0, 0, 0, pdu.len() as u8 + 1, unit_id];
+                request.extend_from_slice(&pdu);
+                stream.write_all(&request)?;
+
+                let mut header = [0; 7];
+                stream.read_exact(&mut header)?;
+                let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+                if length < 2 {
+                    return Err(ModbusError::InvalidResponse(format!("length of {} is too short", length)));
+                }
+
+                let mut response = vec![0; length - 1];
+                stream.read_exact(&mut response)?;
+
+                if header[0..2] != [id_hi, id_lo] || header[2..4] != [0, 0] || header[6] != unit_id {
+                    return Err(ModbusError::InvalidResponse("header doesn't match the request".to_string()));
+                }
+
+                response_data(&response, function, expected_bytes)
+            },
+            Connection::Rtu(port) => {
+                let mut request = vec![unit_id];
+                request.extend_from_slice(&pdu);
+                request.extend_from_slice(&crc16(&request).to_le_bytes());
+
+                // Leftovers of an earlier response would otherwise be read as the start of this one.
+                port.clear(ClearBuffer::Input)?;
+                port.write_all(&request)?;
+
+                let mut response = vec![0; 3];
+                port.read_exact(&mut response)?;
+                let remaining = if response[1] & 0x80 != 0 { 2 } else { response[2] as usize + 2 };
+                response.resize(3 + remaining, 0);
+                port.read_exact(&mut response[3..])?;
+
+                let (frame, crc) = response.split_at(response.len() - 2);
+                if crc16(frame).to_le_bytes() != crc {
+                    return Err(ModbusError::InvalidResponse("CRC mismatch".to_string()));
+                }
+                if frame[0] != unit_id {
+                    return Err(ModbusError::InvalidResponse(format!("response from unit {}", frame[0])));
+                }
+
+                response_data(&frame[1..], function, expected_bytes)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::TcpListener, thread, time::Duration};
+
+    use super::{Connection, ModbusError, crc16, response_data};
+    use crate::config::{RegisterKind, TransportConfig};
+
+    #[test]
+    fn crc_matches_the_specification() {
+        // Reading 10 holding registers of unit 1, as framed by the Modbus over serial line specification.
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]).to_le_bytes(), [0xC5, 0xCD]);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    #[test]
+    fn response_data_is_checked() {
+        assert_eq!(response_data(&[0x03, 0x02, 0x12, 0x34], 0x03, 2).unwrap(), [0x12, 0x34]);
+
+        assert!(matches!(response_data(&[0x83, 0x02], 0x03, 2), Err(ModbusError::Exception(0x02))));
+        // A response too short for the count it announces, or announcing another count.
+        assert!(matches!(response_data(&[0x03, 0x02, 0x12], 0x03, 2), Err(ModbusError::InvalidResponse(_))));
+        assert!(matches!(response_data(&[0x03, 0x01, 0x12], 0x03, 2), Err(ModbusError::InvalidResponse(_))));
+        // A response to another function, or no response at all.
+        assert!(matches!(response_data(&[0x04, 0x02, 0x12, 0x34], 0x03, 2), Err(ModbusError::InvalidResponse(_))));
+        assert!(matches!(response_data(&[0x03], 0x03, 2), Err(ModbusError::InvalidResponse(_))));
+        assert!(matches!(response_data(&[], 0x03, 2), Err(ModbusError::InvalidResponse(_))));
+    }
+
+    #[test]
+    fn exceptions_keep_the_connection() {
+        assert!(!ModbusError::Exception(2).is_fatal());
+        assert!(ModbusError::InvalidResponse("CRC mismatch".to_string()).is_fatal());
+    }
+
+    #[test]
+    fn registers_are_read_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let device = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut request = [0; 12];
+            stream.read_exact(&mut request).unwrap();
+            // Transaction 1, protocol 0, 6 bytes following, unit 7, holding registers 16 and 17.
+            assert_eq!(request, [0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x07, 0x03, 0x00, 0x10, 0x00, 0x02]);
+            stream.write_all(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x07, 0x07, 0x03, 0x04, 0x12, 0x34, 0x56, 0x78]).unwrap();
+
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request[..2], [0x00, 0x02]);
+            stream.write_all(&[0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x07, 0x83, 0x02]).unwrap();
+        });
+
+        let transport = TransportConfig::Tcp { address };
+        let mut connection = Connection::open(&transport, Duration::from_secs(5)).unwrap();
+        assert_eq!(connection.read(7, RegisterKind::HoldingRegister, 16, 2).unwrap(), [0x12, 0x34, 0x56, 0x78]);
+        assert!(matches!(connection.read(7, RegisterKind::HoldingRegister, 16, 2), Err(ModbusError::Exception(0x02))));
+
+        device.join().unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use florust_common::DataSourceMetadata;
+use serde::Deserialize;
+
+fn default_manager_id() -> String { "FlorustSerial".to_string() }
+fn default_baud_rate() -> u32 { 9600 }
+fn default_reconnect_secs() -> u64 { 5 }
+fn default_max_age_secs() -> u64 { 60 }
+fn default_key_value_separator() -> String { "=".to_string() }
+fn default_delimiter() -> String { ",".to_string() }
+fn default_scale() -> f64 { 1.0 }
+
+/// The `serial` section of the plugin's `plugin.toml`.
+#[derive(Deserialize)]
+pub struct SerialConfig {
+    /// Id of the manager, which must be unique when the plugin is loaded more than once, like once per
+    /// device.
+    #[serde(default = "default_manager_id")]
+    pub manager_id: String,
+    /// Path of the serial device, like `/dev/ttyACM0`.
+    pub device: String,
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+    /// How long to wait before reopening the device after it failed to open, or was unplugged, in seconds.
+    #[serde(default = "default_reconnect_secs")]
+    pub reconnect_secs: u64,
+    /// Oldest reading a poll returns, in seconds. Polls fail when the latest reading is older.
+    #[serde(default = "default_max_age_secs")]
+    pub max_age_secs: u64,
+    #[serde(default)]
+    pub format: LineFormat,
+    pub fields: Vec<FieldConfig>,
+}
+
+/// How the lines printed by the device are split into fields.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LineFormat {
+    /// Pairs of keys and values, like `temp=21.5 humidity=40`.
+    KeyValue {
+        /// Separator between pairs, any whitespace if not set.
+        pair_separator: Option<String>,
+        #[serde(default = "default_key_value_separator")]
+        key_value_separator: String,
+    },
+    /// Values only, like `21.5,40`, whose keys are given by their position.
+    Delimited {
+        #[serde(default = "default_delimiter")]
+        delimiter: String,
+        columns: Vec<String>,
+    },
+}
+
+impl Default for LineFormat {
+    fn default() -> Self {
+        LineFormat::KeyValue {
+            pair_separator: None,
+            key_value_separator: default_key_value_separator(),
+        }
+    }
+}
+
+impl LineFormat {
+    /// Splits a line into its keys and values, skipping the parts which don't fit the format.
+    pub fn parse<'a>(&'a self, line: &'a str) -> Vec<(&'a str, &'a str)> {
+        match self {
+            LineFormat::KeyValue { pair_separator, key_value_separator } => {
+                let pairs: Box<dyn Iterator<Item = &str>> = match pair_separator {
+                    Some(separator) => Box::new(line.split(separator.as_str())),
+                    None => Box::new(line.split_whitespace()),
+                };
+
+                pairs
+                    .filter_map(|pair| pair.split_once(key_value_separator.as_str()))
+                    .map(|(key, value)| (key.trim(), value.trim()))
+                    .collect()
+            },
+            LineFormat::Delimited { delimiter, columns } => columns.iter()
+                .map(String::as_str)
+                .zip(line.split(delimiter.as_str()).map(str::trim))
+                .collect(),
+        }
+    }
+}
+
+/// A field of the lines, logged as a data source. Its value is the field's value multiplied by `scale`, plus
+/// `offset`.
+#[derive(Deserialize, Clone)]
+pub struct FieldConfig {
+    /// Key of the field in the lines.
+    pub key: String,
+    /// Id of the data source, the key if not set.
+    pub id: Option<String>,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+    /// How often the latest reading is logged, in seconds, unless the server's configuration says otherwise.
+    pub interval_secs: Option<u64>,
+    #[serde(flatten)]
+    pub metadata: DataSourceMetadata,
+}
+
+impl FieldConfig {
+    pub fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineFormat;
+
+    fn format(format: &str) -> LineFormat {
+        toml::from_str(format).unwrap()
+    }
+
+    #[test]
+    fn key_value_lines_are_split_on_whitespace() {
+        let format = LineFormat::default();
+        assert_eq!(format.parse("temp=21.5  humidity=40\t banner"), [("temp", "21.5"), ("humidity", "40")]);
+        assert_eq!(format.parse(""), []);
+    }
+
+    #[test]
+    fn key_value_separators_can_be_set() {
+        let format = format("type = \"key_value\"\npair_separator = \";\"\nkey_value_separator = \":\"");
+        assert_eq!(format.parse("temp: 21.5;humidity:40;"), [("temp", "21.5"), ("humidity", "40")]);
+    }
+
+    #[test]
+    fn delimited_lines_are_named_by_position() {
+        let format = format("type = \"delimited\"\ncolumns = [\"temp\", \"humidity\"]");
+        assert_eq!(format.parse("21.5, 40"), [("temp", "21.5"), ("humidity", "40")]);
+        // Missing columns are left out, and extra ones ignored.
+        assert_eq!(format.parse("21.5"), [("temp", "21.5")]);
+        assert_eq!(format.parse("21.5,40,7"), [("temp", "21.5"), ("humidity", "40")]);
+
+        let format = self::format("type = \"delimited\"\ndelimiter = \"|\"\ncolumns = [\"temp\"]");
+        assert_eq!(format.parse("21.5|40"), [("temp", "21.5")]);
+    }
+}
@@ -0,0 +1,353 @@
+//! Florust plugin reading sensors which print their readings as lines over a serial port, like an Arduino
+//! printing `temp=21.5 humidity=40` over USB.
+//!
+//! The plugin is configured through the `serial` section of its `plugin.toml`, see `docs/plugins/serial.md`.
+
+mod config;
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader},
+    marker::PhantomData,
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    thread,
+    time::{Duration, Instant},
+};
+
+use config::{FieldConfig, SerialConfig};
+use florust_common::server::{
+    self,
+    DataSourceManager,
+    DataSourceManagerError,
+    FFIResult,
+    FloatDataSourceManager,
+    FromScaled,
+    IIntegerDataSourceManager,
+    PluginHealth,
+    PolledSource,
+    UIntegerDataSourceManager,
+};
+use rocket::async_trait;
+use toml::{Table, Value};
+
+/// How long a read waits for data before checking whether the plugin is shutting down.
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What the reader shares with the manager.
+#[derive(Default)]
+struct Readings {
+    /// Latest value of each field, by key, along with when it was read.
+    values: HashMap<String, (f64, Instant)>,
+    /// Why the device isn't open, while it isn't.
+    disconnected: Option<String>,
+    /// The last line, and why it couldn't be parsed, if it couldn't.
+    invalid_line: Option<String>,
+}
+
+/// Reads lines from the device until the plugin shuts down, reopening the device whenever it fails to open,
+/// or stops working, like when it is unplugged. Runs on its own thread, as reads block.
+fn reader(config: Arc<SerialConfig>, readings: Arc<Mutex<Readings>>, stop: Arc<AtomicBool>) {
+    let update = |update: &dyn Fn(&mut Readings)| {
+        if let Ok(mut readings) = readings.lock() {
+            update(&mut readings);
+        }
+    };
+
+    while !stop.load(Ordering::SeqCst) {
+        let port = match serialport::new(&config.device, config.baud_rate).timeout(READ_TIMEOUT).open() {
+            Ok(port) => port,
+            Err(err) => {
+                update(&|readings| readings.disconnected = Some(err.to_string()));
+                sleep_unless_stopped(Duration::from_secs(config.reconnect_secs), &stop);
+                continue;
+            },
+        };
+        update(&|readings| readings.disconnected = None);
+
+        let mut lines = BufReader::new(port);
+        let mut line = Vec::new();
+        let error = loop {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+
+            match lines.read_until(b'\n', &mut line) {
+                // The device is gone when reads return nothing, rather than time out.
+                Ok(0) => break "the device was closed".to_string(),
+                Ok(_) if line.ends_with(b"\n") => {
+                    let now = Instant::now();
+                    let text = String::from_utf8_lossy(&line);
+                    update(&|readings| read_line(&config, text.trim(), now, readings));
+                    line.clear();
+                },
+                // A partial line, whose end is yet to come.
+                Ok(_) => (),
+                Err(err) if matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => (),
+                Err(err) => break err.to_string(),
+            }
+        };
+
+        update(&|readings| readings.disconnected = Some(error.clone()));
+        sleep_unless_stopped(Duration::from_secs(config.reconnect_secs), &stop);
+    }
+}
+
+fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let until = Instant::now() + duration;
+    while !stop.load(Ordering::SeqCst) && Instant::now() < until {
+        thread::sleep(READ_TIMEOUT.min(until.saturating_duration_since(Instant::now())));
+    }
+}
+
+/// Stores the values of a line's fields. Lines without any valid field, like a banner printed when the
+/// device boots, are reported through the plugin's health.
+fn read_line(config: &SerialConfig, line: &str, now: Instant, readings: &mut Readings) {
+    if line.is_empty() {
+        return;
+    }
+
+    let mut read = false;
+    let mut invalid = None;
+    for (key, value) in config.format.parse(line) {
+        if !config.fields.iter().any(|field| field.key == key) {
+            continue;
+        }
+
+        match value.parse::<f64>() {
+            Ok(value) => {
+                readings.values.insert(key.to_string(), (value, now));
+                read = true;
+            },
+            Err(_) => invalid = Some(format!("{} isn't a number", value)),
+        }
+    }
+
+    readings.invalid_line = match (read, invalid) {
+        (_, Some(reason)) => Some(format!("{}: {}", line, reason)),
+        (false, None) => Some(format!("{}: no configured field", line)),
+        (true, None) => None,
+    };
+}
+
+pub struct SerialManager<T> {
+    manager_id: &'static str,
+    config: Arc<SerialConfig>,
+    /// Fields, by the id of their data source.
+    fields: HashMap<String, FieldConfig>,
+    readings: Arc<Mutex<Readings>>,
+    /// Tells the reader to stop, once the server shuts down.
+    stop: Arc<AtomicBool>,
+    data_type: PhantomData<fn() -> T>,
+}
+
+impl<T> SerialManager<T> {
+    fn new(config: Option<Table>) -> server::Result<SerialManager<T>> {
+        let invalid = |error: String| DataSourceManagerError::InvalidData(format!("invalid serial configuration: {}", error));
+
+        let config: SerialConfig = config
+            .and_then(|mut config| config.remove("serial"))
+            .ok_or_else(|| invalid("plugin.toml has no serial section".to_string()))?
+            .try_into()
+            .map_err(|err: toml::de::Error| invalid(err.to_string()))?;
+
+        let fields = config.fields.iter()
+            .map(|field| (field.id().to_string(), field.clone()))
+            .collect();
+
+        Ok(SerialManager {
+            // The id must outlive the manager, and is only leaked once per loaded plugin.
+            manager_id: Box::leak(config.manager_id.clone().into_boxed_str()),
+            config: Arc::new(config),
+            fields,
+            readings: Arc::new(Mutex::new(Readings::default())),
+            stop: Arc::new(AtomicBool::new(false)),
+            data_type: PhantomData,
+        })
+    }
+
+    fn field(&self, id: &str) -> server::Result<&FieldConfig> {
+        self.fields.get(id)
+            .ok_or_else(|| DataSourceManagerError::InvalidData(format!("{} isn't a configured field", id)))
+    }
+}
+
+#[async_trait]
+impl<T> DataSourceManager<T> for SerialManager<T> where T: FromScaled + Send + 'static {
+    fn manager_id(&self) -> &'static str {
+        self.manager_id
+    }
+
+    async fn register(&self, id: String) -> server::Result<()> {
+        self.field(&id).map(|_| ())
+    }
+
+    async fn register_with_data(&self, id: String, _data: &[u8]) -> server::Result<()> {
+        self.field(&id).map(|_| ())
+    }
+
+    async fn deregister(&self, _id: &str) -> server::Result<()> {
+        Ok(())
+    }
+
+    async fn deregister_with_data(&self, _id: &str, _data: &[u8]) -> server::Result<()> {
+        Ok(())
+    }
+
+    async fn update_data(&self, id: &str, _data: &[u8]) -> server::Result<T> {
+        Err(DataSourceManagerError::InvalidData(format!("{} is read from the serial device, and can't be uploaded", id)))
+    }
+
+    async fn on_start(&self) {
+        let (config, readings, stop) = (Arc::clone(&self.config), Arc::clone(&self.readings), Arc::clone(&self.stop));
+        thread::spawn(move || reader(config, readings, stop));
+    }
+
+    async fn polled_sources(&self) -> Vec<PolledSource> {
+        self.config.fields.iter()
+            .map(|field| PolledSource {
+                id: field.id().to_string(),
+                metadata: field.metadata.clone(),
+                interval_secs: field.interval_secs,
+            })
+            .collect()
+    }
+
+    /// Returns the latest reading of the field, failing if there is none recent enough. Readings are sampled
+    /// by the polls, so those in between two polls aren't logged, and a reading may be logged by more than one
+    /// poll.
+    async fn poll(&self, id: &str) -> server::Result<T> {
+        let field = self.field(id)?;
+        let max_age = Duration::from_secs(self.config.max_age_secs);
+
+        let reading = self.readings.lock()
+            .map_err(|_| DataSourceManagerError::PollFailed("the serial reader panicked".to_string()))?
+            .values
+            .get(&field.key)
+            .copied();
+
+        let value = match reading {
+            Some((value, read_at)) if read_at.elapsed() <= max_age => value,
+            _ => return Err(DataSourceManagerError::PollFailed(
+                format!("{} wasn't read in the last {} seconds", field.key, self.config.max_age_secs)
+            )),
+        };
+
+        T::from_scaled(value * field.scale + field.offset).map_err(DataSourceManagerError::InvalidData)
+    }
+
+    async fn health(&self) -> PluginHealth {
+        let Ok(readings) = self.readings.lock() else {
+            return PluginHealth::Unhealthy("the serial reader panicked".to_string());
+        };
+
+        match (&readings.disconnected, &readings.invalid_line) {
+            (Some(reason), _) => PluginHealth::Unhealthy(format!("{} isn't available: {}", self.config.device, reason)),
+            (None, Some(line)) => PluginHealth::Degraded(format!("the last line couldn't be read: {}", line)),
+            (None, None) => PluginHealth::Healthy,
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn create<T>(config: Option<Table>) -> server::Result<SerialManager<T>> {
+    SerialManager::new(config)
+}
+
+#[no_mangle]
+pub extern "C" fn create_iinteger_data_source_manager(config: Box<Option<toml::map::Map<String, Value>>>) -> FFIResult<IIntegerDataSourceManager> {
+    Box::new(create::<i64>(*config).map(|manager| Box::new(manager) as Box<IIntegerDataSourceManager>))
+}
+
+#[no_mangle]
+pub extern "C" fn create_uinteger_data_source_manager(config: Box<Option<toml::map::Map<String, Value>>>) -> FFIResult<UIntegerDataSourceManager> {
+    Box::new(create::<u64>(*config).map(|manager| Box::new(manager) as Box<UIntegerDataSourceManager>))
+}
+
+#[no_mangle]
+pub extern "C" fn create_float_data_source_manager(config: Box<Option<toml::map::Map<String, Value>>>) -> FFIResult<FloatDataSourceManager> {
+    Box::new(create::<f64>(*config).map(|manager| Box::new(manager) as Box<FloatDataSourceManager>))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+
+    use super::{Readings, reader, read_line};
+    use crate::config::SerialConfig;
+
+    fn config(device: &str) -> SerialConfig {
+        toml::from_str(&format!(
+            "device = \"{}\"\nreconnect_secs = 1\n[[fields]]\nkey = \"temp\"\n[[fields]]\nkey = \"humidity\"",
+            device
+        )).unwrap()
+    }
+
+    #[test]
+    fn lines_update_the_configured_fields() {
+        let config = config("/dev/null");
+        let mut readings = Readings::default();
+        let now = Instant::now();
+
+        read_line(&config, "temp=21.5 humidity=40 pressure=1013", now, &mut readings);
+        assert_eq!(readings.values["temp"].0, 21.5);
+        assert_eq!(readings.values["humidity"].0, 40.0);
+        assert!(!readings.values.contains_key("pressure"));
+        assert!(readings.invalid_line.is_none());
+
+        // Fields which aren't numbers are reported, while the others of the line are still read.
+        read_line(&config, "temp=22 humidity=high", now, &mut readings);
+        assert_eq!(readings.values["temp"].0, 22.0);
+        assert_eq!(readings.values["humidity"].0, 40.0);
+        assert!(readings.invalid_line.as_deref().is_some_and(|line| line.contains("high isn't a number")));
+
+        read_line(&config, "booting...", now, &mut readings);
+        assert!(readings.invalid_line.as_deref().is_some_and(|line| line.contains("no configured field")));
+
+        // Empty lines are ignored.
+        read_line(&config, "", now, &mut readings);
+        assert!(readings.invalid_line.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lines_are_read_from_the_device() {
+        use std::io::Write;
+
+        use serialport::{SerialPort, TTYPort};
+
+        let (mut device, port) = TTYPort::pair().unwrap();
+        let path = port.name().unwrap();
+        // The reader opens the port itself.
+        drop(port);
+
+        let readings = Arc::new(Mutex::new(Readings::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let reading = {
+            let (config, readings, stop) = (Arc::new(config(&path)), Arc::clone(&readings), Arc::clone(&stop));
+            thread::spawn(move || reader(config, readings, stop))
+        };
+
+        // Lines are printed until one is read, as the reader may not have opened the port yet.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let read = loop {
+            let _ = device.write_all(b"temp=21.5\r\nhumidity=");
+            let _ = device.write_all(b"40\r\n");
+            let read = readings.lock().unwrap().values.get("humidity").map(|(value, _)| *value);
+            if read.is_some() || Instant::now() > deadline {
+                break read;
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        stop.store(true, Ordering::SeqCst);
+        reading.join().unwrap();
+
+        assert_eq!(read, Some(40.0));
+        let readings = readings.lock().unwrap();
+        assert_eq!(readings.values["temp"].0, 21.5);
+        assert!(readings.disconnected.is_none());
+    }
+}
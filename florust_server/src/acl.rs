@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use rocket::serde::{Serialize, Deserialize};
+use wildmatch::WildMatch;
+
+/// The roles a token can be granted through the ACL. Each role includes the permissions of the roles
+/// before it.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Role {
+    /// May read the data of matching data sources.
+    Reader,
+    /// May read the data of, register, unregister and upload data to matching data sources.
+    Writer,
+    /// May do anything, including using the management routes.
+    Admin,
+}
+
+/// The permission a route requires from the principal making the request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Permission {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Role {
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::Read => true,
+            Permission::Write => *self >= Role::Writer,
+            Permission::Admin => *self == Role::Admin,
+        }
+    }
+}
+
+/// A glob pattern of the form `<manager_glob>/<data_source_glob>`, matching data sources by their manager
+/// id and data source id. A pattern without a `/` matches every data source of the matching managers.
+pub struct SourcePattern {
+    manager: WildMatch,
+    data_source: WildMatch,
+}
+
+impl SourcePattern {
+    pub fn new(pattern: &str) -> SourcePattern {
+        let (manager, data_source) = pattern.split_once('/').unwrap_or((pattern, "*"));
+
+        SourcePattern {
+            manager: WildMatch::new(manager),
+            data_source: WildMatch::new(data_source),
+        }
+    }
+
+    pub fn matches(&self, manager_id: &str, data_source_id: &str) -> bool {
+        self.manager.matches(manager_id) && self.data_source.matches(data_source_id)
+    }
+}
+
+fn match_all() -> Vec<String> { vec!["*/*".to_string()] }
+
+/// A single entry of the ACL, granting a role over a set of data sources to whoever presents the token.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AclEntryConfig {
+    /// SHA-256 hash (hex encoded) of the token this entry applies to.
+    pub token: String,
+    pub role: Role,
+    /// Patterns of the data sources the role applies to, see [`SourcePattern`].
+    #[serde(default = "match_all")]
+    pub sources: Vec<String>,
+}
+
+fn default_certificate_role() -> Role { Role::Writer }
+
+/// An entry of the ACL granting a role over a set of data sources to clients presenting a certificate with
+/// a matching name, when mutual TLS is enabled.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CertificateEntryConfig {
+    /// Name matched against the common name, and DNS subject alternative names of the client certificate.
+    pub name: String,
+    #[serde(default = "default_certificate_role")]
+    pub role: Role,
+    /// Patterns of the data sources the role applies to, see [`SourcePattern`].
+    #[serde(default = "match_all")]
+    pub sources: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct AclConfig {
+    /// Role given to requests that don't carry a token, requests without a token are rejected if none is
+    /// given.
+    #[serde(default)]
+    pub anonymous: Option<Role>,
+    /// Whether any request may register a data source which doesn't exist yet, rather than requiring the
+    /// write permission.
+    #[serde(default)]
+    pub open_registration: bool,
+    #[serde(default)]
+    pub principals: Vec<AclEntryConfig>,
+    #[serde(default)]
+    pub certificates: Vec<CertificateEntryConfig>,
+}
+
+/// A role and the data sources it applies to.
+pub struct Grant {
+    role: Role,
+    sources: Vec<SourcePattern>,
+}
+
+impl Grant {
+    pub fn new(role: Role, sources: &[String]) -> Grant {
+        Grant {
+            role,
+            sources: sources.iter().map(|pattern| SourcePattern::new(pattern)).collect(),
+        }
+    }
+
+    pub fn admin() -> Grant {
+        Grant::new(Role::Admin, &match_all())
+    }
+
+    /// Checks whether the grant allows the permission. If the permission is checked for a specific data
+    /// source, the data source must also match one of the grant's patterns.
+    pub fn allows(&self, permission: Permission, source: Option<(&str, &str)>) -> bool {
+        if !self.role.allows(permission) {
+            return false;
+        }
+
+        match source {
+            Some((manager_id, data_source_id)) => self.sources
+                .iter()
+                .any(|pattern| pattern.matches(manager_id, data_source_id)),
+            None => true,
+        }
+    }
+}
+
+/// The ACL the server enforces, built from the [`AclConfig`] and the configured admin tokens.
+pub struct Acl {
+    grants: HashMap<String, Grant>,
+    certificate_grants: HashMap<String, Grant>,
+    anonymous: Option<Role>,
+    open_registration: bool,
+}
+
+impl Acl {
+    pub fn new(config: AclConfig, admin_tokens: Vec<String>) -> Acl {
+        let mut grants = HashMap::new();
+        for entry in config.principals {
+            grants.insert(entry.token.to_ascii_lowercase(), Grant::new(entry.role, &entry.sources));
+        }
+
+        for token in admin_tokens {
+            grants.insert(token.to_ascii_lowercase(), Grant::admin());
+        }
+
+        let certificate_grants = config.certificates
+            .into_iter()
+            .map(|entry| (entry.name, Grant::new(entry.role, &entry.sources)))
+            .collect();
+
+        Acl {
+            grants,
+            certificate_grants,
+            anonymous: config.anonymous,
+            open_registration: config.open_registration,
+        }
+    }
+
+    /// Returns the grant belonging to a hashed token, if there is one.
+    pub fn grant(&self, token_hash: &str) -> Option<&Grant> {
+        self.grants.get(token_hash)
+    }
+
+    /// Returns the grant belonging to the first of the names that has one. The names are expected to be the
+    /// names a client certificate was issued for.
+    pub fn certificate_grant<'a>(&self, mut names: impl Iterator<Item = &'a str>) -> Option<&Grant> {
+        names.find_map(|name| self.certificate_grants.get(name))
+    }
+
+    pub fn anonymous(&self) -> Option<Role> {
+        self.anonymous
+    }
+
+    pub fn open_registration(&self) -> bool {
+        self.open_registration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::serde::json::{self, json};
+
+    use super::{Acl, AclConfig, Role};
+
+    #[test]
+    fn access_is_closed_by_default() {
+        let acl = Acl::new(json::from_value::<AclConfig>(json!({})).unwrap(), Vec::new());
+        assert_eq!(acl.anonymous(), None);
+        assert!(!acl.open_registration());
+
+        let acl = Acl::new(AclConfig::default(), Vec::new());
+        assert_eq!(acl.anonymous(), None);
+        assert!(!acl.open_registration());
+    }
+
+    #[test]
+    fn access_may_be_opened() {
+        let config = json::from_value(json!({ "anonymous": "reader", "open_registration": true })).unwrap();
+        let acl = Acl::new(config, Vec::new());
+
+        assert_eq!(acl.anonymous(), Some(Role::Reader));
+        assert!(acl.open_registration());
+    }
+}
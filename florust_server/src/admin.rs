@@ -0,0 +1,83 @@
+use florust_common::RegisteredDataSource;
+use log::info;
+use rocket::{delete, get, post, State, data::{Data, Limits, ToByteUnit}};
+
+use crate::{FlorustState, acl::Permission, auth::Principal, data_source::{DataSourceError, OkResponder, state_op_to_responder}, lifecycle::HealthReport, manager_and_data::ManagerAndDataError, snapshot::{RestoreSummary, Snapshot}};
+
+/// Size limit of restored snapshots in mebibytes, unless Rocket's `limits.snapshot` says otherwise.
+const DEFAULT_SNAPSHOT_LIMIT_MIB: u64 = 64;
+
+#[post("/rotate_token/<manager_id>/<data_source_id>")]
+pub async fn rotate_token(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String
+) -> Result<OkResponder<RegisteredDataSource>, DataSourceError> {
+    state.authorize(&principal, Permission::Admin, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(
+        state.rotate_token(&manager_id, &data_source_id).await
+            .map(|token| RegisteredDataSource { token })
+    )
+}
+
+#[delete("/data/<manager_id>/<data_source_id>")]
+pub async fn purge_data(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String
+) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Admin, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(state.purge_data(&manager_id, &data_source_id).await)
+}
+
+/// Returns a snapshot of every manager's data sources and logged data, to be restored on another server.
+#[get("/snapshot")]
+pub async fn snapshot(
+    state: &State<FlorustState>,
+    principal: Principal<'_>
+) -> Result<OkResponder<Snapshot>, DataSourceError> {
+    state.authorize(&principal, Permission::Admin, None)?;
+
+    state_op_to_responder(Ok(state.snapshot().await))
+}
+
+/// Restores a snapshot taken with [`snapshot`]. Data sources which already exist are only replaced if
+/// `replace` is true, otherwise the restore fails without changing anything.
+#[post("/restore?<replace>", data = "<data>")]
+pub async fn restore(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    replace: Option<bool>,
+    limits: &Limits,
+    data: Data<'_>
+) -> Result<OkResponder<RestoreSummary>, DataSourceError> {
+    state.authorize(&principal, Permission::Admin, None)?;
+
+    let limit = limits.get("snapshot").unwrap_or(DEFAULT_SNAPSHOT_LIMIT_MIB.mebibytes());
+    let body = data.open(limit).into_bytes().await
+        .map_err(|err| ManagerAndDataError::InvalidSnapshot(format!("failed to read the snapshot: {}", err)))?;
+    if !body.is_complete() {
+        return Err(ManagerAndDataError::BodyTooLarge { max_size: limit.as_u64() }.into());
+    }
+
+    let snapshot = Snapshot::parse(&body)?;
+    let summary = state.restore(snapshot, replace.unwrap_or(false)).await?;
+    info!("Restored {} data sources, with {} samples, from a snapshot", summary.data_sources, summary.samples);
+
+    state_op_to_responder(Ok(summary))
+}
+
+/// Returns the health of every manager, as reported by its plugin.
+#[get("/health")]
+pub async fn health(
+    state: &State<FlorustState>,
+    principal: Principal<'_>
+) -> Result<OkResponder<HealthReport>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    state_op_to_responder(Ok(state.health().await))
+}
@@ -0,0 +1,272 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use rocket::serde::{Serialize, Deserialize};
+
+use crate::{manager_and_data::{ManagerAndDataError, Sample, Series}, rollup::{Resolution, Rollup}};
+
+/// Most buckets a single aggregation query may be split into.
+pub const MAX_BUCKETS: u64 = 10_000;
+
+/// A function computed over the samples of a data source.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Aggregation {
+    Min,
+    Max,
+    Mean,
+    Sum,
+    Count,
+    /// Population standard deviation.
+    StdDev,
+    /// Change per second between the first and last sample.
+    RateOfChange,
+    /// Percentile between 0 and 100, using linear interpolation between the closest ranks.
+    Percentile(f64),
+}
+
+impl FromStr for Aggregation {
+    type Err = ManagerAndDataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let aggregation = match s {
+            "min" => Aggregation::Min,
+            "max" => Aggregation::Max,
+            "mean" | "avg" => Aggregation::Mean,
+            "sum" => Aggregation::Sum,
+            "count" => Aggregation::Count,
+            "stddev" => Aggregation::StdDev,
+            "rate" => Aggregation::RateOfChange,
+            _ => {
+                let percentile = s.strip_prefix('p')
+                    .and_then(|percentile| percentile.parse::<f64>().ok())
+                    .filter(|percentile| (0.0..=100.0).contains(percentile))
+                    .ok_or_else(|| ManagerAndDataError::InvalidQuery(format!("unknown aggregation function: {}", s)))?;
+
+                Aggregation::Percentile(percentile)
+            }
+        };
+
+        Ok(aggregation)
+    }
+}
+
+/// Parses a comma separated list of aggregation functions, like `min,max,p95`.
+pub fn parse_aggregations(list: &str) -> Result<Vec<(String, Aggregation)>, ManagerAndDataError> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| Ok((name.to_string(), name.parse()?)))
+        .collect()
+}
+
+impl Aggregation {
+    /// Computes the aggregation over samples sorted by timestamp. Returns [`None`] if the aggregation isn't
+    /// defined for the samples, like the mean of no samples.
+    pub fn compute(&self, samples: &[Sample<f64>]) -> Option<f64> {
+        if samples.is_empty() {
+            return match self {
+                Aggregation::Count | Aggregation::Sum => Some(0.0),
+                _ => None,
+            };
+        }
+
+        let values = samples.iter().map(|sample| sample.value);
+        let count = samples.len() as f64;
+
+        match self {
+            Aggregation::Min => values.reduce(f64::min),
+            Aggregation::Max => values.reduce(f64::max),
+            Aggregation::Mean => Some(values.sum::<f64>() / count),
+            Aggregation::Sum => Some(values.sum()),
+            Aggregation::Count => Some(count),
+            Aggregation::StdDev => {
+                let mean = values.clone().sum::<f64>() / count;
+                let variance = values.map(|value| (value - mean).powi(2)).sum::<f64>() / count;
+                Some(variance.sqrt())
+            },
+            Aggregation::RateOfChange => {
+                let (first, last) = (samples.first()?, samples.last()?);
+                if last.timestamp == first.timestamp {
+                    return None;
+                }
+
+                let seconds = (last.timestamp - first.timestamp) as f64 / 1000.0;
+                Some((last.value - first.value) / seconds)
+            },
+            Aggregation::Percentile(percentile) => {
+                let mut sorted: Vec<f64> = values.collect();
+                sorted.sort_by(f64::total_cmp);
+
+                let rank = percentile / 100.0 * (sorted.len() - 1) as f64;
+                let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+                Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64))
+            },
+        }
+    }
+
+    /// Computes the aggregation over rollups sorted by start, for ranges the samples themselves no longer
+    /// cover. The rate of change is taken between the last samples of the first and last rollups. Returns
+    /// [`None`] for aggregations which need the samples themselves, like percentiles.
+    pub fn compute_rollups(&self, rollups: &[Rollup]) -> Option<f64> {
+        if rollups.is_empty() {
+            return match self {
+                Aggregation::Count | Aggregation::Sum => Some(0.0),
+                _ => None,
+            };
+        }
+
+        let count = rollups.iter().map(|rollup| rollup.count).sum::<u64>() as f64;
+        let sum = rollups.iter().map(|rollup| rollup.mean * rollup.count as f64).sum::<f64>();
+
+        match self {
+            Aggregation::Min => rollups.iter().map(|rollup| rollup.min).reduce(f64::min),
+            Aggregation::Max => rollups.iter().map(|rollup| rollup.max).reduce(f64::max),
+            Aggregation::Mean => Some(sum / count),
+            Aggregation::Sum => Some(sum),
+            Aggregation::Count => Some(count),
+            Aggregation::RateOfChange => {
+                let (first, last) = (rollups.first()?, rollups.last()?);
+                if last.start == first.start {
+                    return None;
+                }
+
+                let seconds = (last.start - first.start) as f64 / 1000.0;
+                Some((last.last - first.last) / seconds)
+            },
+            Aggregation::StdDev | Aggregation::Percentile(_) => None,
+        }
+    }
+}
+
+/// The aggregations computed over a bucket of time.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AggregateBucket {
+    /// Start of the bucket, inclusive, in milliseconds since the unix epoch.
+    pub start: u64,
+    /// End of the bucket, exclusive, in milliseconds since the unix epoch.
+    pub end: u64,
+    /// Resolution of the rollups the values were computed from, when the samples no longer cover the range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<Resolution>,
+    pub values: BTreeMap<String, Option<f64>>,
+}
+
+/// Start of aggregations which don't say where their range starts: the oldest data of the data source, but
+/// no further back than [`MAX_BUCKETS`] buckets before `to`.
+pub fn default_from(oldest: Option<u64>, to: u64, bucket_width: Option<u64>) -> u64 {
+    let from = oldest.unwrap_or(to).min(to);
+
+    match bucket_width {
+        Some(width) if width > 0 => from.max(to.saturating_sub(width.saturating_mul(MAX_BUCKETS - 1))),
+        _ => from,
+    }
+}
+
+/// Computes the aggregations over the data between `from` and `to`, either samples or rollups, sorted by
+/// time. The range is split into buckets of `bucket_width` milliseconds starting at `from` if a width is
+/// given, or treated as a single bucket otherwise. Rollups fall in the bucket their start falls in, or in the
+/// first bucket if they start before `from`.
+pub fn aggregate(
+    series: &Series,
+    aggregations: &[(String, Aggregation)],
+    from: u64,
+    to: u64,
+    bucket_width: Option<u64>
+) -> Result<Vec<AggregateBucket>, ManagerAndDataError> {
+    if from > to {
+        return Err(ManagerAndDataError::InvalidQuery("from must not be after to".to_string()));
+    }
+
+    let width = match bucket_width {
+        Some(0) => return Err(ManagerAndDataError::InvalidQuery("bucket width must be positive".to_string())),
+        Some(width) => width,
+        None => (to - from).saturating_add(1),
+    };
+
+    if (to - from) / width >= MAX_BUCKETS {
+        return Err(ManagerAndDataError::InvalidQuery(format!("query would produce more than {} buckets", MAX_BUCKETS)));
+    }
+
+    let samples: Vec<Sample<f64>> = match series {
+        Series::Raw(samples) => samples.iter()
+            .map(|sample| Sample { timestamp: sample.timestamp, value: sample.value.as_f64() })
+            .collect(),
+        Series::Rollup { .. } => Vec::new(),
+    };
+
+    let mut buckets = Vec::new();
+    let mut start = from;
+    while start <= to {
+        let end = start.saturating_add(width);
+        let bucket_end = end.min(to.saturating_add(1));
+
+        let (resolution, values) = match series {
+            Series::Raw(_) => {
+                let first = samples.partition_point(|sample| sample.timestamp < start);
+                let last = samples.partition_point(|sample| sample.timestamp < bucket_end);
+                let values = aggregations.iter()
+                    .map(|(name, aggregation)| (name.clone(), aggregation.compute(&samples[first..last])))
+                    .collect();
+
+                (None, values)
+            },
+            Series::Rollup { resolution, buckets: rollups } => {
+                // The rollup `from` falls in starts before it, but still belongs to the first bucket.
+                let first = rollups.partition_point(|rollup| rollup.start.max(from) < start);
+                let last = rollups.partition_point(|rollup| rollup.start.max(from) < bucket_end);
+                let values = aggregations.iter()
+                    .map(|(name, aggregation)| (name.clone(), aggregation.compute_rollups(&rollups[first..last])))
+                    .collect();
+
+                (Some(*resolution), values)
+            },
+        };
+
+        buckets.push(AggregateBucket { start, end, resolution, values });
+
+        // The end saturates on ranges ending at the end of time, in which case no further bucket would start.
+        if end >= to.saturating_add(1) {
+            break;
+        }
+        start = end;
+    }
+
+    Ok(buckets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aggregation, aggregate};
+    use crate::manager_and_data::{DataType, Sample, Series};
+
+    fn series(samples: &[(u64, f64)]) -> Series {
+        Series::Raw(samples.iter().map(|(timestamp, value)| Sample { timestamp: *timestamp, value: DataType::Float(*value) }).collect())
+    }
+
+    fn counts(from: u64, to: u64, bucket_width: Option<u64>) -> Vec<(u64, u64, Option<f64>)> {
+        let series = series(&[(1000, 1.0), (2500, 2.0), (u64::MAX - 1, 3.0)]);
+        aggregate(&series, &[("count".to_string(), Aggregation::Count)], from, to, bucket_width).unwrap()
+            .into_iter()
+            .map(|bucket| (bucket.start, bucket.end, bucket.values["count"]))
+            .collect()
+    }
+
+    #[test]
+    fn ranges_are_split_into_buckets() {
+        assert_eq!(counts(0, 2999, Some(1000)), [(0, 1000, Some(0.0)), (1000, 2000, Some(1.0)), (2000, 3000, Some(1.0))]);
+        assert_eq!(counts(0, 2999, None), [(0, 3000, Some(2.0))]);
+        assert!(aggregate(&series(&[]), &[], 0, 1000, Some(0)).is_err());
+        assert!(aggregate(&series(&[]), &[], 1000, 0, None).is_err());
+    }
+
+    #[test]
+    fn ranges_may_end_at_the_end_of_time() {
+        assert_eq!(counts(0, u64::MAX, None), [(0, u64::MAX, Some(3.0))]);
+        assert_eq!(counts(u64::MAX - 2500, u64::MAX, Some(1000)), [
+            (u64::MAX - 2500, u64::MAX - 1500, Some(0.0)),
+            (u64::MAX - 1500, u64::MAX - 500, Some(0.0)),
+            (u64::MAX - 500, u64::MAX, Some(1.0)),
+        ]);
+        assert!(aggregate(&series(&[]), &[], 0, u64::MAX, Some(1000)).is_err());
+    }
+}
@@ -0,0 +1,495 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::Arc, time::Duration};
+
+use florust_common::DataSourceMetadata;
+use log::{info, warn};
+use rocket::{fairing::AdHoc, serde::{Serialize, Deserialize}, tokio::{self, sync::Mutex, time::interval}};
+
+use crate::{BoxedManagerAndData, FlorustState, circular_vec::CircularVec, manager_and_data::{ManagerAndDataError, now_millis}, query::Selector};
+
+fn match_all() -> String { "*".to_string() }
+
+/// The condition under which an alert rule fires. Thresholds are in the units of the data source.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// Samples are below the threshold for at least `for_secs` seconds.
+    Below {
+        threshold: f64,
+        #[serde(default)]
+        for_secs: u64,
+    },
+    /// Samples are above the threshold for at least `for_secs` seconds.
+    Above {
+        threshold: f64,
+        #[serde(default)]
+        for_secs: u64,
+    },
+    /// The change per second between consecutive samples is below the threshold for at least `for_secs`
+    /// seconds. A negative threshold catches values dropping quickly.
+    RateBelow {
+        threshold: f64,
+        #[serde(default)]
+        for_secs: u64,
+    },
+    /// The change per second between consecutive samples is above the threshold for at least `for_secs`
+    /// seconds.
+    RateAbove {
+        threshold: f64,
+        #[serde(default)]
+        for_secs: u64,
+    },
+    /// The data source hasn't uploaded a sample for `for_secs` seconds.
+    NoData {
+        for_secs: u64,
+    },
+}
+
+impl Condition {
+    pub(crate) fn for_millis(&self) -> u64 {
+        let for_secs = match self {
+            Condition::Below { for_secs, .. }
+                | Condition::Above { for_secs, .. }
+                | Condition::RateBelow { for_secs, .. }
+                | Condition::RateAbove { for_secs, .. }
+                | Condition::NoData { for_secs } => *for_secs,
+        };
+
+        for_secs.saturating_mul(1000)
+    }
+
+    /// Whether a sample violates the condition, given the sample before it, if any. Returns [`None`] if the
+    /// condition can't be decided from samples.
+    pub(crate) fn violated_by(&self, sample: (u64, f64), previous: Option<(u64, f64)>) -> Option<bool> {
+        let rate = || {
+            let (timestamp, value) = sample;
+            let (previous_timestamp, previous_value) = previous?;
+            let seconds = timestamp.checked_sub(previous_timestamp).filter(|millis| *millis > 0)? as f64 / 1000.0;
+            Some((value - previous_value) / seconds)
+        };
+
+        match self {
+            Condition::Below { threshold, .. } => Some(sample.1 < *threshold),
+            Condition::Above { threshold, .. } => Some(sample.1 > *threshold),
+            Condition::RateBelow { threshold, .. } => rate().map(|rate| rate < *threshold),
+            Condition::RateAbove { threshold, .. } => rate().map(|rate| rate > *threshold),
+            Condition::NoData { .. } => Some(false),
+        }
+    }
+}
+
+/// A rule raising an alert for every data source matching its selector which meets its condition.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct AlertRule {
+    /// Glob pattern the manager id must match.
+    #[serde(default = "match_all")]
+    pub manager: String,
+    /// Glob pattern the data source id must match.
+    #[serde(default = "match_all")]
+    pub source: String,
+    /// Tags the data source must have, with glob patterns their values must match.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    pub condition: Condition,
+}
+
+impl AlertRule {
+    fn selector(&self) -> Selector {
+        self.tags.iter().fold(
+            Selector::new(&self.manager, &self.source),
+            |selector, (key, value)| selector.with_tag(key, value)
+        )
+    }
+
+    fn matches(&self, manager_id: &str, data_source_id: &str, metadata: &DataSourceMetadata) -> bool {
+        let selector = self.selector();
+        selector.matches_manager(manager_id) && selector.matches_source(data_source_id) && selector.matches_tags(&metadata.tags)
+    }
+}
+
+fn default_history_size() -> usize { 1000 }
+
+fn default_check_interval_secs() -> u64 { 30 }
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct AlertsConfig {
+    /// Rules present at startup, by name. Rules can be added, replaced and removed at runtime.
+    #[serde(default)]
+    pub rules: BTreeMap<String, AlertRule>,
+    /// How many alert state changes are kept in the history.
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    /// How often, in seconds, alerts that depend on the passing of time, rather than on samples, are checked.
+    #[serde(default = "default_check_interval_secs")]
+    pub check_interval_secs: u64,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        AlertsConfig {
+            rules: BTreeMap::new(),
+            history_size: default_history_size(),
+            check_interval_secs: default_check_interval_secs(),
+        }
+    }
+}
+
+/// The states of an alert. Alerts not meeting their condition are inactive, which isn't a state of its own,
+/// but an alert that stops firing is resolved.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum AlertStatus {
+    /// The condition is met, but hasn't been for long enough yet.
+    Pending,
+    Firing,
+    Resolved,
+}
+
+/// An alert which is currently pending or firing.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct ActiveAlert {
+    pub rule: String,
+    pub manager_id: String,
+    pub data_source_id: String,
+    pub status: AlertStatus,
+    /// When the alert entered its status, in milliseconds since the unix epoch.
+    pub since: u64,
+    /// The most recent sample that met the condition, if the condition is about samples.
+    pub value: Option<f64>,
+}
+
+/// A change of an alert's status, as kept in the history.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct AlertEvent {
+    pub rule: String,
+    pub manager_id: String,
+    pub data_source_id: String,
+    pub status: AlertStatus,
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub value: Option<f64>,
+}
+
+/// Identifies an alert: the rule, the manager id and the data source id.
+type AlertKey = (String, String, String);
+
+struct StoredRule {
+    rule: AlertRule,
+    /// When the rule was added, used as the last upload of data sources which haven't uploaded since.
+    added_at: u64,
+}
+
+struct Alerts {
+    rules: BTreeMap<String, StoredRule>,
+    active: HashMap<AlertKey, ActiveAlert>,
+    history: CircularVec<Option<AlertEvent>>,
+    /// The most recent sample of every data source, as a timestamp and value, kept even while no rule
+    /// watches the data source, so rules added later don't compare against an older sample.
+    last_samples: HashMap<(String, String), (u64, f64)>,
+}
+
+impl Alerts {
+    fn record(&mut self, alert: &ActiveAlert, status: AlertStatus, timestamp: u64) {
+        match status {
+            AlertStatus::Firing => warn!("Alert {} is firing for {}/{}", alert.rule, alert.manager_id, alert.data_source_id),
+            AlertStatus::Resolved => info!("Alert {} resolved for {}/{}", alert.rule, alert.manager_id, alert.data_source_id),
+            AlertStatus::Pending => (),
+        }
+
+        self.history.append(Some(AlertEvent {
+            rule: alert.rule.clone(),
+            manager_id: alert.manager_id.clone(),
+            data_source_id: alert.data_source_id.clone(),
+            status,
+            timestamp,
+            value: alert.value,
+        }));
+    }
+
+    /// Moves an alert along its states, depending on whether its condition is met at `timestamp`.
+    fn transition(&mut self, key: AlertKey, violated: bool, for_millis: u64, timestamp: u64, value: Option<f64>) {
+        match (self.active.remove(&key), violated) {
+            (None, true) => {
+                let (rule, manager_id, data_source_id) = key.clone();
+                let status = if for_millis == 0 { AlertStatus::Firing } else { AlertStatus::Pending };
+                let alert = ActiveAlert { rule, manager_id, data_source_id, status, since: timestamp, value };
+
+                self.record(&alert, status, timestamp);
+                self.active.insert(key, alert);
+            },
+            (Some(mut alert), true) => {
+                alert.value = value.or(alert.value);
+                if alert.status == AlertStatus::Pending && timestamp.saturating_sub(alert.since) >= for_millis {
+                    alert.status = AlertStatus::Firing;
+                    alert.since = timestamp;
+                    self.record(&alert, AlertStatus::Firing, timestamp);
+                }
+                self.active.insert(key, alert);
+            },
+            (Some(mut alert), false) => {
+                alert.value = value.or(alert.value);
+                // Pending alerts never fired, so there is nothing to resolve.
+                if alert.status == AlertStatus::Firing {
+                    self.record(&alert, AlertStatus::Resolved, timestamp);
+                }
+            },
+            (None, false) => (),
+        }
+    }
+
+    fn forget_rule(&mut self, name: &str) {
+        self.active.retain(|(rule, _, _), _| rule != name);
+    }
+
+    /// Resolves the alerts of data sources which are no longer registered, and forgets the samples of data
+    /// sources which no longer exist.
+    fn prune(&mut self, existing: &HashSet<(String, String)>, registered: &HashSet<(String, String)>, now: u64) {
+        let unregistered: Vec<AlertKey> = self.active.keys()
+            .filter(|(_, manager_id, data_source_id)| !registered.contains(&(manager_id.clone(), data_source_id.clone())))
+            .cloned()
+            .collect();
+        for key in unregistered {
+            self.transition(key, false, 0, now, None);
+        }
+
+        self.last_samples.retain(|data_source, _| existing.contains(data_source));
+    }
+}
+
+/// Evaluates the alert rules, and keeps track of the alerts they raise.
+pub struct AlertEngine {
+    alerts: Mutex<Alerts>,
+    check_interval_secs: u64,
+}
+
+impl AlertEngine {
+    pub fn new(config: AlertsConfig) -> AlertEngine {
+        let now = now_millis();
+
+        AlertEngine {
+            alerts: Mutex::new(Alerts {
+                rules: config.rules.into_iter()
+                    .map(|(name, rule)| (name, StoredRule { rule, added_at: now }))
+                    .collect(),
+                active: HashMap::new(),
+                history: CircularVec::new(config.history_size.max(1), None),
+                last_samples: HashMap::new(),
+            }),
+            check_interval_secs: config.check_interval_secs,
+        }
+    }
+
+    /// Records a sample a data source just uploaded as its most recent, returning the sample before it, if any.
+    pub async fn record_sample(&self, manager_id: &str, data_source_id: &str, timestamp: u64, value: f64) -> Option<(u64, f64)> {
+        self.alerts.lock().await.last_samples.insert((manager_id.to_string(), data_source_id.to_string()), (timestamp, value))
+    }
+
+    /// Evaluates the rules matching a data source against a sample it just uploaded, given the sample before
+    /// it, as returned by [`AlertEngine::record_sample`].
+    pub async fn observe(
+        &self,
+        manager_id: &str,
+        data_source_id: &str,
+        metadata: &DataSourceMetadata,
+        (timestamp, value): (u64, f64),
+        previous: Option<(u64, f64)>
+    ) {
+        let mut alerts = self.alerts.lock().await;
+
+        let evaluations: Vec<(String, Option<bool>, u64)> = alerts.rules.iter()
+            .filter(|(_, stored)| stored.rule.matches(manager_id, data_source_id, metadata))
+            .map(|(name, stored)| {
+                let condition = stored.rule.condition;
+                (name.clone(), condition.violated_by((timestamp, value), previous), condition.for_millis())
+            })
+            .collect();
+
+        for (rule, violated, for_millis) in evaluations {
+            if let Some(violated) = violated {
+                let key = (rule, manager_id.to_string(), data_source_id.to_string());
+                alerts.transition(key, violated, for_millis, timestamp, Some(value));
+            }
+        }
+    }
+
+    /// Checks the alerts that depend on the passing of time: pending alerts whose condition has now been met
+    /// for long enough, and data sources that stopped uploading. Alerts of data sources which were deregistered
+    /// are resolved.
+    pub async fn check(&self, managers_and_data: &HashMap<&'static str, BoxedManagerAndData>, now: u64) {
+        let (mut existing, mut registered) = (HashSet::new(), HashSet::new());
+        let mut data_sources = Vec::new();
+        for (manager_id, manager) in managers_and_data.iter() {
+            for (data_source_id, metadata) in manager.data_sources().await {
+                let key = (manager_id.to_string(), data_source_id.clone());
+                if manager.is_registered(&data_source_id).await {
+                    registered.insert(key.clone());
+                    data_sources.push((manager_id.to_string(), data_source_id, metadata));
+                }
+                existing.insert(key);
+            }
+        }
+
+        let mut alerts = self.alerts.lock().await;
+        alerts.prune(&existing, &registered, now);
+
+        let pending: Vec<(AlertKey, u64)> = alerts.active.iter()
+            .filter(|(_, alert)| alert.status == AlertStatus::Pending)
+            .filter_map(|(key, _)| Some((key.clone(), alerts.rules.get(&key.0)?.rule.condition.for_millis())))
+            .collect();
+        for (key, for_millis) in pending {
+            alerts.transition(key, true, for_millis, now, None);
+        }
+
+        let mut evaluations = Vec::new();
+        for (name, stored) in alerts.rules.iter() {
+            let Condition::NoData { .. } = stored.rule.condition else {
+                continue;
+            };
+
+            for (manager_id, data_source_id, metadata) in &data_sources {
+                if !stored.rule.matches(manager_id, data_source_id, metadata) {
+                    continue;
+                }
+
+                let last_upload = alerts.last_samples
+                    .get(&(manager_id.clone(), data_source_id.clone()))
+                    .map_or(stored.added_at, |(timestamp, _)| *timestamp);
+                let violated = now.saturating_sub(last_upload) >= stored.rule.condition.for_millis();
+
+                evaluations.push(((name.clone(), manager_id.clone(), data_source_id.clone()), violated));
+            }
+        }
+
+        for (key, violated) in evaluations {
+            alerts.transition(key, violated, 0, now, None);
+        }
+    }
+
+    pub async fn rules(&self) -> BTreeMap<String, AlertRule> {
+        self.alerts.lock().await.rules.iter()
+            .map(|(name, stored)| (name.clone(), stored.rule.clone()))
+            .collect()
+    }
+
+    /// Adds a rule, or replaces the rule with the same name, dropping the alerts the previous rule raised.
+    pub async fn set_rule(&self, name: String, rule: AlertRule) {
+        let mut alerts = self.alerts.lock().await;
+        alerts.forget_rule(&name);
+        alerts.rules.insert(name, StoredRule { rule, added_at: now_millis() });
+    }
+
+    /// Removes a rule, along with the alerts it raised.
+    pub async fn remove_rule(&self, name: &str) -> Result<(), ManagerAndDataError> {
+        let mut alerts = self.alerts.lock().await;
+        alerts.rules.remove(name).ok_or_else(|| ManagerAndDataError::AlertRuleDoesntExist(name.to_string()))?;
+        alerts.forget_rule(name);
+
+        Ok(())
+    }
+
+    pub async fn active(&self) -> Vec<ActiveAlert> {
+        let mut active: Vec<ActiveAlert> = self.alerts.lock().await.active.values().cloned().collect();
+        active.sort_by_key(|alert| alert.since);
+        active
+    }
+
+    /// Returns the alert state changes kept in the history, oldest first.
+    pub async fn history(&self) -> Vec<AlertEvent> {
+        self.alerts.lock().await.history.iter().flatten().cloned().collect()
+    }
+
+    pub async fn has_rules(&self) -> bool {
+        !self.alerts.lock().await.rules.is_empty()
+    }
+}
+
+/// Fairing that spawns a background task periodically checking the alerts that depend on the passing of
+/// time, see [`AlertEngine::check`].
+pub fn checker() -> AdHoc {
+    AdHoc::on_liftoff("Alert checker", |rocket| Box::pin(async move {
+        let Some(state) = rocket.state::<FlorustState>() else {
+            return;
+        };
+
+        let engine = Arc::clone(&state.alerts);
+        let managers_and_data = state.managers_and_data.clone();
+        info!("Starting alert checker, running every {} seconds", engine.check_interval_secs);
+
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(engine.check_interval_secs.max(1)));
+            loop {
+                interval.tick().await;
+                engine.check(&managers_and_data, now_millis()).await;
+            }
+        });
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use florust_common::DataSourceMetadata;
+
+    use super::{AlertEngine, AlertRule, AlertStatus, AlertsConfig, Condition};
+
+    fn rule(condition: Condition) -> AlertRule {
+        AlertRule {
+            manager: "*".to_string(),
+            source: "*".to_string(),
+            tags: Default::default(),
+            condition,
+        }
+    }
+
+    async fn upload(engine: &AlertEngine, data_source_id: &str, sample: (u64, f64)) {
+        let previous = engine.record_sample("manager", data_source_id, sample.0, sample.1).await;
+        engine.observe("manager", data_source_id, &DataSourceMetadata::default(), sample, previous).await;
+    }
+
+    fn data_sources(ids: &[&str]) -> HashSet<(String, String)> {
+        ids.iter().map(|id| ("manager".to_string(), id.to_string())).collect()
+    }
+
+    #[rocket::async_test]
+    async fn rates_compare_against_samples_uploaded_before_the_rule() {
+        let engine = AlertEngine::new(AlertsConfig::default());
+        upload(&engine, "source", (0, 10.0)).await;
+
+        engine.set_rule("rising".to_string(), rule(Condition::RateAbove { threshold: 1.0, for_secs: 0 })).await;
+        upload(&engine, "source", (60_000, 20.0)).await;
+        assert!(engine.active().await.is_empty());
+
+        upload(&engine, "source", (61_000, 30.0)).await;
+        let active = engine.active().await;
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].status, AlertStatus::Firing);
+    }
+
+    #[rocket::async_test]
+    async fn alerts_of_deregistered_data_sources_are_resolved() {
+        let engine = AlertEngine::new(AlertsConfig::default());
+        engine.set_rule("hot".to_string(), rule(Condition::Above { threshold: 30.0, for_secs: 0 })).await;
+        upload(&engine, "kept", (0, 40.0)).await;
+        upload(&engine, "deregistered", (0, 40.0)).await;
+        upload(&engine, "removed", (0, 40.0)).await;
+
+        engine.alerts.lock().await.prune(&data_sources(&["kept", "deregistered"]), &data_sources(&["kept"]), 1_000);
+
+        let active = engine.active().await;
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].data_source_id, "kept");
+
+        let resolved: HashSet<String> = engine.history().await.into_iter()
+            .filter(|event| event.status == AlertStatus::Resolved)
+            .map(|event| event.data_source_id)
+            .collect();
+        assert_eq!(resolved, HashSet::from(["deregistered".to_string(), "removed".to_string()]));
+
+        let last_samples: HashSet<_> = engine.alerts.lock().await.last_samples.keys().cloned().collect();
+        assert_eq!(last_samples, data_sources(&["kept", "deregistered"]));
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use rocket::{delete, get, put, State, serde::json::Json};
+
+use crate::{FlorustState, acl::Permission, alerting::{ActiveAlert, AlertEvent, AlertRule}, auth::Principal, data_source::{DataSourceError, OkResponder, state_op_to_responder}};
+
+/// Returns the alerts which are currently pending or firing, for the data sources the requester may read.
+#[get("/")]
+pub async fn list(
+    state: &State<FlorustState>,
+    principal: Principal<'_>
+) -> Result<OkResponder<Vec<ActiveAlert>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    let alerts = state.alerts.active().await
+        .into_iter()
+        .filter(|alert| state.authorize(&principal, Permission::Read, Some((&alert.manager_id, &alert.data_source_id))).is_ok())
+        .collect();
+
+    state_op_to_responder(Ok(alerts))
+}
+
+/// Returns the history of alert state changes, oldest first, optionally only those raised by one rule.
+#[get("/history?<rule>")]
+pub async fn history(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    rule: Option<String>
+) -> Result<OkResponder<Vec<AlertEvent>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    let history = state.alerts.history().await
+        .into_iter()
+        .filter(|event| match &rule {
+            Some(rule) => *rule == event.rule,
+            None => true,
+        })
+        .filter(|event| state.authorize(&principal, Permission::Read, Some((&event.manager_id, &event.data_source_id))).is_ok())
+        .collect();
+
+    state_op_to_responder(Ok(history))
+}
+
+#[get("/rules")]
+pub async fn rules(
+    state: &State<FlorustState>,
+    principal: Principal<'_>
+) -> Result<OkResponder<BTreeMap<String, AlertRule>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    state_op_to_responder(Ok(state.alerts.rules().await))
+}
+
+/// Adds a rule, or replaces the existing rule with the same name.
+#[put("/rules/<name>", format = "json", data = "<rule>")]
+pub async fn set_rule(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    name: String,
+    rule: Json<AlertRule>
+) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Admin, None)?;
+
+    state.alerts.set_rule(name, rule.into_inner()).await;
+    state_op_to_responder(Ok(()))
+}
+
+#[delete("/rules/<name>")]
+pub async fn remove_rule(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    name: String
+) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Admin, None)?;
+
+    state_op_to_responder(state.alerts.remove_rule(&name).await)
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use rand::{RngCore, rngs::OsRng};
+use rocket::{
+    async_trait, catch, http::Status, mtls::{Certificate, x509::GeneralName}, request::{FromRequest, Outcome},
+    serde::{Serialize, Deserialize, json::Json}, tokio::sync::RwLock, Request
+};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{FlorustState, acl::{Acl, Grant, Permission}};
+
+/// Number of random bytes used to generate a token.
+const TOKEN_BYTES: usize = 32;
+
+#[derive(Serialize, Deserialize, Error, Debug, Clone)]
+#[serde(crate = "rocket::serde")]
+pub enum AuthError {
+    #[error("Request requires a bearer token, but none was provided")]
+    MissingToken,
+    #[error("The provided token isn't valid")]
+    InvalidToken,
+    #[error("The provided token isn't allowed to perform the requested action")]
+    Forbidden,
+}
+
+/// Generates a new random token, returned hex encoded.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes a token with SHA-256, returning the hash hex encoded. This is the form tokens are stored and
+/// configured in, so the server never has to keep the tokens themselves around.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// A token issued to a data source, as saved in a snapshot.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TokenSnapshot {
+    pub token_hash: String,
+    pub manager_id: String,
+    pub data_source_id: String,
+}
+
+/// Keeps track of the hashed tokens issued to data sources.
+#[derive(Default)]
+pub struct TokenStore {
+    source_tokens: RwLock<HashMap<String, (String, String)>>,
+}
+
+impl TokenStore {
+    /// Issues a new token for a data source, replacing any token that was previously issued for it.
+    /// Returns the token in plain text, this is the only time it is available in that form.
+    pub async fn issue(&self, manager_id: &str, data_source_id: &str) -> String {
+        let token = generate_token();
+        let mut lock = self.source_tokens.write().await;
+        lock.retain(|_, (manager, data_source)| manager != manager_id || data_source != data_source_id);
+        lock.insert(hash_token(&token), (manager_id.to_string(), data_source_id.to_string()));
+
+        token
+    }
+
+    /// Revokes the token issued for a data source, if there is one.
+    pub async fn revoke(&self, manager_id: &str, data_source_id: &str) {
+        self.source_tokens.write().await
+            .retain(|_, (manager, data_source)| manager != manager_id || data_source != data_source_id);
+    }
+
+    pub async fn snapshot(&self) -> Vec<TokenSnapshot> {
+        self.source_tokens.read().await
+            .iter()
+            .map(|(token_hash, (manager_id, data_source_id))| TokenSnapshot {
+                token_hash: token_hash.clone(),
+                manager_id: manager_id.clone(),
+                data_source_id: data_source_id.clone(),
+            })
+            .collect()
+    }
+
+    /// Restores a token from a snapshot, replacing any token that was previously issued for its data source.
+    pub async fn restore(&self, token: TokenSnapshot) {
+        let mut lock = self.source_tokens.write().await;
+        lock.retain(|_, (manager, data_source)| *manager != token.manager_id || *data_source != token.data_source_id);
+        lock.insert(token.token_hash, (token.manager_id, token.data_source_id));
+    }
+
+    /// Returns the manager id and data source id of the data source a hashed token was issued to.
+    pub async fn source_for(&self, token_hash: &str) -> Option<(String, String)> {
+        self.source_tokens.read().await
+            .get(token_hash)
+            .cloned()
+    }
+}
+
+/// Who a request was made by, as determined by the bearer token it carries.
+pub enum Principal<'r> {
+    /// The request carried no token.
+    Anonymous,
+    /// The request carried a token issued to a data source at registration.
+    DataSource {
+        manager_id: String,
+        data_source_id: String,
+    },
+    /// The request carried a token configured in the ACL, an admin token, or was made with a client
+    /// certificate mapped to a role in the ACL.
+    Granted(&'r Grant),
+}
+
+impl Principal<'_> {
+    /// Checks whether the principal has a permission, either over a specific data source or in general.
+    pub fn authorize(&self, acl: &Acl, permission: Permission, source: Option<(&str, &str)>) -> Result<(), AuthError> {
+        let allowed = match self {
+            Principal::Anonymous => match acl.anonymous() {
+                Some(role) => role.allows(permission),
+                None => return Err(AuthError::MissingToken),
+            },
+            Principal::DataSource { manager_id, data_source_id } => {
+                permission != Permission::Admin
+                    && source.is_some_and(|source| source == (manager_id.as_str(), data_source_id.as_str()))
+            },
+            Principal::Granted(grant) => grant.allows(permission, source),
+        };
+
+        if allowed {
+            Ok(())
+        }
+        else {
+            Err(AuthError::Forbidden)
+        }
+    }
+}
+
+/// The reason a request failed authentication, cached on the request so the catcher can report it.
+struct AuthFailure(Option<AuthError>);
+
+fn fail<S>(req: &Request<'_>, error: AuthError) -> Outcome<S, AuthError> {
+    req.local_cache(|| AuthFailure(Some(error.clone())));
+    Outcome::Failure((Status::Unauthorized, error))
+}
+
+fn bearer_token<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    req.headers()
+        .get_one("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Returns the names a client certificate was issued for, its common names followed by its DNS subject
+/// alternative names.
+fn certificate_names<'a>(certificate: &'a Certificate<'_>) -> Vec<&'a str> {
+    let mut names: Vec<&str> = certificate.subject().common_names().collect();
+    if let Ok(Some(san)) = certificate.subject_alternative_name() {
+        names.extend(san.value.general_names.iter().filter_map(|name| match name {
+            GeneralName::DNSName(name) => Some(*name),
+            _ => None,
+        }));
+    }
+
+    names
+}
+
+/// Request guard resolving the [`Principal`] behind a request. A bearer token takes precedence over a client
+/// certificate, and requests with neither, or with a certificate that isn't mapped to a role, are anonymous.
+/// The guard only fails if the request carries a token that the server doesn't know, whether the principal
+/// is allowed to do what it's requesting is up to the route to check.
+#[async_trait]
+impl<'r> FromRequest<'r> for Principal<'r> {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(state) = req.rocket().state::<FlorustState>() else {
+            return Outcome::Failure((Status::InternalServerError, AuthError::InvalidToken));
+        };
+
+        let Some(token) = bearer_token(req) else {
+            let Outcome::Success(certificate) = req.guard::<Certificate<'r>>().await else {
+                return Outcome::Success(Principal::Anonymous);
+            };
+
+            return match state.acl.certificate_grant(certificate_names(&certificate).into_iter()) {
+                Some(grant) => Outcome::Success(Principal::Granted(grant)),
+                None => Outcome::Success(Principal::Anonymous),
+            };
+        };
+
+        let hash = hash_token(token);
+        if let Some(grant) = state.acl.grant(&hash) {
+            return Outcome::Success(Principal::Granted(grant));
+        }
+
+        match state.tokens.source_for(&hash).await {
+            Some((manager_id, data_source_id)) => Outcome::Success(Principal::DataSource { manager_id, data_source_id }),
+            None => fail(req, AuthError::InvalidToken),
+        }
+    }
+}
+
+#[catch(401)]
+pub fn unauthorized(req: &Request<'_>) -> Json<AuthError> {
+    Json(
+        req.local_cache(|| AuthFailure(None)).0
+            .clone()
+            .unwrap_or(AuthError::MissingToken)
+    )
+}
@@ -0,0 +1,592 @@
+use std::{collections::{BTreeMap, HashMap}, process::Stdio, sync::Arc};
+
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use florust_common::DataSourceMetadata;
+use log::{info, warn};
+use rocket::{serde::{Serialize, Deserialize, json::Value}, tokio::{self, sync::Mutex}};
+use tera::{Context, Tera};
+
+use crate::{BoxedManagerAndData, alerting::Condition, circular_vec::CircularVec, command_queue::{CommandsConfig, NewCommand}, manager_and_data::ManagerAndDataError, query::Selector};
+
+fn match_all() -> String { "*".to_string() }
+
+/// (De)serializes cron expressions. Expressions may leave out the seconds field, like the classic five field
+/// cron expressions do, in which case their weekdays are numbered like those of classic expressions too.
+mod cron_expression {
+    use std::str::FromStr;
+
+    use cron::Schedule;
+    use rocket::serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    /// Renumbers the weekdays of a classic day of week field, from 0 or 7 for Sunday through 6 for Saturday, to
+    /// the numbering of the `cron` crate, from 1 for Sunday through 7 for Saturday. Weekdays given by name,
+    /// like `MON-FRI`, are left as they are.
+    pub(super) fn classic_weekdays(field: &str) -> Result<String, String> {
+        if field == "*" || field == "?" || field.chars().any(|c| c.is_ascii_alphabetic()) {
+            return Ok(field.to_string());
+        }
+
+        let number = |value: &str| match value.parse::<u8>() {
+            Ok(day) if day <= 7 => Ok(day),
+            _ => Err(format!("{} isn't a day of the week", value)),
+        };
+
+        let mut days = Vec::new();
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => {
+                    let step = step.parse::<usize>().ok()
+                        .filter(|step| *step > 0)
+                        .ok_or_else(|| format!("{} is an invalid step", step))?;
+                    (range, Some(step))
+                },
+                None => (item, None),
+            };
+
+            let (first, last) = match range.split_once('-') {
+                _ if range == "*" => (0, 6),
+                Some((first, last)) => (number(first)?, number(last)?),
+                None if step.is_some() => (number(range)?, 6),
+                None => (number(range)?, number(range)?),
+            };
+            if first > last {
+                return Err(format!("{} is an invalid range of days", range));
+            }
+
+            days.extend((first..=last).step_by(step.unwrap_or(1)).map(|day| day % 7 + 1));
+        }
+
+        days.sort_unstable();
+        days.dedup();
+        Ok(days.iter().map(u8::to_string).collect::<Vec<_>>().join(","))
+    }
+
+    pub fn serialize<S: Serializer>(schedule: &Option<Schedule>, serializer: S) -> Result<S::Ok, S::Error> {
+        match schedule {
+            Some(schedule) => serializer.collect_str(schedule),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Schedule>, D::Error> {
+        let Some(expression) = Option::<String>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let expression = if let [minutes, hours, days, months, weekdays] = fields[..] {
+            let weekdays = classic_weekdays(weekdays)
+                .map_err(|err| D::Error::custom(format!("invalid cron expression {}: {}", expression, err)))?;
+            format!("* {} {} {} {} {}", minutes, hours, days, months, weekdays)
+        }
+        else {
+            expression
+        };
+
+        Schedule::from_str(&expression)
+            .map(Some)
+            .map_err(|err| D::Error::custom(format!("invalid cron expression {}: {}", expression, err)))
+    }
+}
+
+/// Something an automation rule does when it triggers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// POSTs the trigger to a URL, as JSON unless a body template is given.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        /// Tera template of the body, given the fields of the trigger.
+        body: Option<String>,
+    },
+    /// Runs a local command, passing the trigger in environment variables.
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Enqueues a command for a data source, the one which triggered the rule unless another is given.
+    Enqueue {
+        manager: Option<String>,
+        source: Option<String>,
+        payload: Value,
+        ttl_secs: Option<u64>,
+    },
+}
+
+/// Replaces header values and command arguments of actions shown to principals which may not manage rules.
+const REDACTED: &str = "[redacted]";
+
+impl Action {
+    /// Returns the action with its header values and command arguments redacted, as they may hold
+    /// credentials, like the API key of a pump controller.
+    pub fn redacted(&self) -> Action {
+        match self.clone() {
+            Action::Webhook { url, headers, body } => Action::Webhook {
+                url,
+                headers: headers.into_keys().map(|name| (name, REDACTED.to_string())).collect(),
+                body,
+            },
+            Action::Command { program, args } => Action::Command {
+                program,
+                args: args.iter().map(|_| REDACTED.to_string()).collect(),
+            },
+            action @ Action::Enqueue { .. } => action,
+        }
+    }
+}
+
+/// A rule carrying out actions whenever a data source matching its selector meets its condition.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct AutomationRule {
+    /// Glob pattern the manager id must match.
+    #[serde(default = "match_all")]
+    pub manager: String,
+    /// Glob pattern the data source id must match.
+    #[serde(default = "match_all")]
+    pub source: String,
+    /// Tags the data source must have, with glob patterns their values must match.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    pub condition: Condition,
+    /// The rule doesn't trigger again for the same data source until this many seconds passed.
+    #[serde(default)]
+    pub cooldown_secs: u64,
+    /// Cron expression of the local times the rule may trigger at, the rule may always trigger if none is
+    /// given.
+    #[serde(default, with = "cron_expression")]
+    pub active: Option<Schedule>,
+    pub actions: Vec<Action>,
+    /// Whether actions are only recorded in the audit log, rather than carried out.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl AutomationRule {
+    /// Returns the rule with its actions redacted, see [`Action::redacted`].
+    pub fn redacted(&self) -> AutomationRule {
+        AutomationRule {
+            actions: self.actions.iter().map(Action::redacted).collect(),
+            ..self.clone()
+        }
+    }
+
+    fn matches(&self, manager_id: &str, data_source_id: &str, metadata: &DataSourceMetadata) -> bool {
+        let selector = self.tags.iter().fold(
+            Selector::new(&self.manager, &self.source),
+            |selector, (key, value)| selector.with_tag(key, value)
+        );
+
+        selector.matches_manager(manager_id) && selector.matches_source(data_source_id) && selector.matches_tags(&metadata.tags)
+    }
+
+    fn is_active(&self, time: DateTime<Local>) -> bool {
+        match &self.active {
+            Some(schedule) => schedule.includes(time),
+            None => true,
+        }
+    }
+}
+
+fn default_audit_size() -> usize { 1000 }
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct AutomationsConfig {
+    /// Rules present at startup, by name. Rules can be added, replaced and removed at runtime.
+    #[serde(default)]
+    pub rules: BTreeMap<String, AutomationRule>,
+    /// How many triggered actions are kept in the audit log.
+    #[serde(default = "default_audit_size")]
+    pub audit_size: usize,
+    /// Programs which rules set at runtime may run with `command` actions. Rules of the configuration may run
+    /// any program.
+    #[serde(default)]
+    pub allowed_programs: Vec<String>,
+}
+
+impl Default for AutomationsConfig {
+    fn default() -> Self {
+        AutomationsConfig {
+            rules: BTreeMap::new(),
+            audit_size: default_audit_size(),
+            allowed_programs: Vec::new(),
+        }
+    }
+}
+
+/// The sample which triggered a rule, passed to its actions.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Trigger {
+    pub rule: String,
+    pub manager_id: String,
+    pub data_source_id: String,
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub value: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    /// The rule is a dry run, so the action wasn't carried out.
+    DryRun,
+    Succeeded,
+    Failed {
+        error: String,
+    },
+}
+
+/// A triggered action, as kept in the audit log.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub trigger: Trigger,
+    pub action: Action,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+/// Where a rule stands for one data source.
+#[derive(Default)]
+struct RuleState {
+    /// Timestamp of the first of the consecutive samples meeting the condition.
+    held_since: Option<u64>,
+    /// When the rule last triggered.
+    last_triggered: Option<u64>,
+}
+
+struct Automations {
+    rules: BTreeMap<String, AutomationRule>,
+    /// Keyed by the rule, the manager id and the data source id.
+    states: HashMap<(String, String, String), RuleState>,
+    /// The most recent sample of every data source, as a timestamp and value, kept even while no rule
+    /// watches the data source, so rules added later don't compare against an older sample.
+    last_samples: HashMap<(String, String), (u64, f64)>,
+    audit: CircularVec<Option<AuditEntry>>,
+}
+
+/// Evaluates the automation rules as samples arrive, and carries out the actions of the rules that trigger.
+pub struct AutomationEngine {
+    automations: Mutex<Automations>,
+    /// The managers commands are enqueued with.
+    managers_and_data: Arc<HashMap<&'static str, BoxedManagerAndData>>,
+    commands: CommandsConfig,
+    client: reqwest::Client,
+    /// Programs which rules set at runtime may run.
+    allowed_programs: Vec<String>,
+}
+
+impl AutomationEngine {
+    pub fn new(
+        config: AutomationsConfig,
+        commands: CommandsConfig,
+        managers_and_data: Arc<HashMap<&'static str, BoxedManagerAndData>>
+    ) -> AutomationEngine {
+        AutomationEngine {
+            automations: Mutex::new(Automations {
+                rules: config.rules,
+                states: HashMap::new(),
+                last_samples: HashMap::new(),
+                audit: CircularVec::new(config.audit_size.max(1), None),
+            }),
+            managers_and_data,
+            commands,
+            client: reqwest::Client::new(),
+            allowed_programs: config.allowed_programs,
+        }
+    }
+
+    /// Records a sample a data source just uploaded as its most recent, returning the sample before it, if any.
+    pub async fn record_sample(&self, manager_id: &str, data_source_id: &str, timestamp: u64, value: f64) -> Option<(u64, f64)> {
+        self.automations.lock().await.last_samples.insert((manager_id.to_string(), data_source_id.to_string()), (timestamp, value))
+    }
+
+    /// Evaluates the rules matching a data source against a sample it just uploaded, given the sample before
+    /// it, as returned by [`AutomationEngine::record_sample`]. Returns the actions of the rules that triggered,
+    /// except for dry runs, which are only recorded in the audit log.
+    pub async fn observe(
+        &self,
+        manager_id: &str,
+        data_source_id: &str,
+        metadata: &DataSourceMetadata,
+        (timestamp, value): (u64, f64),
+        previous: Option<(u64, f64)>
+    ) -> Vec<(Trigger, Action)> {
+        let mut automations = self.automations.lock().await;
+        let automations = &mut *automations;
+        let local_time = Local::now();
+
+        let mut triggered = Vec::new();
+        for (name, rule) in automations.rules.iter() {
+            if !rule.matches(manager_id, data_source_id, metadata) {
+                continue;
+            }
+
+            let key = (name.clone(), manager_id.to_string(), data_source_id.to_string());
+            let state = automations.states.entry(key).or_default();
+
+            match rule.condition.violated_by((timestamp, value), previous) {
+                Some(true) => {
+                    state.held_since.get_or_insert(timestamp);
+                },
+                Some(false) => {
+                    state.held_since = None;
+                    continue;
+                },
+                None => continue,
+            }
+
+            let held = state.held_since.is_some_and(|since| timestamp.saturating_sub(since) >= rule.condition.for_millis());
+            let cooled_down = match state.last_triggered {
+                Some(last) => timestamp.saturating_sub(last) >= rule.cooldown_secs.saturating_mul(1000),
+                None => true,
+            };
+            if !held || !cooled_down || !rule.is_active(local_time) {
+                continue;
+            }
+
+            state.last_triggered = Some(timestamp);
+            info!("Automation rule {} triggered for {}/{}", name, manager_id, data_source_id);
+
+            let trigger = Trigger {
+                rule: name.clone(),
+                manager_id: manager_id.to_string(),
+                data_source_id: data_source_id.to_string(),
+                timestamp,
+                value,
+            };
+            for action in &rule.actions {
+                if rule.dry_run {
+                    automations.audit.append(Some(AuditEntry { trigger: trigger.clone(), action: action.clone(), outcome: Outcome::DryRun }));
+                }
+                else {
+                    triggered.push((trigger.clone(), action.clone()));
+                }
+            }
+        }
+
+        triggered
+    }
+
+    async fn record(&self, trigger: Trigger, action: Action, result: Result<(), String>) {
+        let outcome = match result {
+            Ok(()) => Outcome::Succeeded,
+            Err(error) => {
+                warn!("Action of automation rule {} failed: {}", trigger.rule, error);
+                Outcome::Failed { error }
+            },
+        };
+
+        self.automations.lock().await.audit.append(Some(AuditEntry { trigger, action, outcome }));
+    }
+
+    /// Carries out the actions of the rules that triggered in the background, recording their outcome in the
+    /// audit log once they're done. Actions aren't retried, as repeating them may not be harmless.
+    pub fn spawn(self: &Arc<Self>, triggered: Vec<(Trigger, Action)>) {
+        if triggered.is_empty() {
+            return;
+        }
+
+        let engine = Arc::clone(self);
+        tokio::spawn(async move {
+            for (trigger, action) in triggered {
+                let result = engine.run(&trigger, &action).await;
+                engine.record(trigger, action, result).await;
+            }
+        });
+    }
+
+    async fn run(&self, trigger: &Trigger, action: &Action) -> Result<(), String> {
+        match action {
+            Action::Webhook { url, headers, body } => {
+                let mut request = self.client.post(url);
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+
+                let request = match body {
+                    Some(template) => {
+                        let context = Context::from_serialize(trigger).map_err(|err| err.to_string())?;
+                        request.body(Tera::one_off(template, &context, false).map_err(|err| err.to_string())?)
+                    },
+                    None => request.json(trigger),
+                };
+
+                let response = request.send().await.map_err(|err| err.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("webhook responded with status {}", response.status().as_u16()));
+                }
+
+                Ok(())
+            },
+            Action::Command { program, args } => {
+                let status = tokio::process::Command::new(program)
+                    .args(args)
+                    .env("FLORUST_RULE", &trigger.rule)
+                    .env("FLORUST_MANAGER_ID", &trigger.manager_id)
+                    .env("FLORUST_DATA_SOURCE_ID", &trigger.data_source_id)
+                    .env("FLORUST_TIMESTAMP", trigger.timestamp.to_string())
+                    .env("FLORUST_VALUE", trigger.value.to_string())
+                    .stdin(Stdio::null())
+                    .status()
+                    .await
+                    .map_err(|err| err.to_string())?;
+
+                if !status.success() {
+                    return Err(format!("command exited with {}", status));
+                }
+
+                Ok(())
+            },
+            Action::Enqueue { manager, source, payload, ttl_secs } => {
+                let manager_id = manager.as_deref().unwrap_or(&trigger.manager_id);
+                let data_source_id = source.as_deref().unwrap_or(&trigger.data_source_id);
+                let manager = self.managers_and_data.get(manager_id)
+                    .ok_or_else(|| format!("data source manager {} doesn't exist", manager_id))?;
+
+                manager.enqueue_command(data_source_id, NewCommand { payload: payload.clone(), ttl_secs: *ttl_secs }, &self.commands).await
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            },
+        }
+    }
+
+    pub async fn rules(&self) -> BTreeMap<String, AutomationRule> {
+        self.automations.lock().await.rules.clone()
+    }
+
+    /// Adds a rule at runtime, or replaces the rule with the same name, forgetting where the previous rule
+    /// stood. Fails if the rule runs a program which
+    /// isn't allowed, as anyone able to set rules could otherwise run anything on the server.
+    pub async fn set_rule(&self, name: String, rule: AutomationRule) -> Result<(), ManagerAndDataError> {
+        for action in &rule.actions {
+            if let Action::Command { program, .. } = action {
+                if !self.allowed_programs.contains(program) {
+                    return Err(ManagerAndDataError::ProgramNotAllowed(program.clone()));
+                }
+            }
+        }
+
+        let mut automations = self.automations.lock().await;
+        automations.states.retain(|(rule, _, _), _| *rule != name);
+        automations.rules.insert(name, rule);
+
+        Ok(())
+    }
+
+    pub async fn remove_rule(&self, name: &str) -> Result<(), ManagerAndDataError> {
+        let mut automations = self.automations.lock().await;
+        automations.rules.remove(name).ok_or_else(|| ManagerAndDataError::AutomationRuleDoesntExist(name.to_string()))?;
+        automations.states.retain(|(rule, _, _), _| rule != name);
+
+        Ok(())
+    }
+
+    /// Returns the triggered actions kept in the audit log, oldest first.
+    pub async fn audit(&self) -> Vec<AuditEntry> {
+        self.automations.lock().await.audit.iter().flatten().cloned().collect()
+    }
+
+    pub async fn has_rules(&self) -> bool {
+        !self.automations.lock().await.rules.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+    use rocket::serde::json::{self, json};
+
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::{AutomationEngine, AutomationRule, AutomationsConfig, cron_expression::classic_weekdays};
+    use crate::command_queue::CommandsConfig;
+
+    #[test]
+    fn classic_weekdays_are_renumbered() {
+        assert_eq!(classic_weekdays("0").unwrap(), "1");
+        assert_eq!(classic_weekdays("7").unwrap(), "1");
+        assert_eq!(classic_weekdays("1-5").unwrap(), "2,3,4,5,6");
+        assert_eq!(classic_weekdays("6,7").unwrap(), "1,7");
+        assert_eq!(classic_weekdays("5-7").unwrap(), "1,6,7");
+        assert_eq!(classic_weekdays("*/2").unwrap(), "1,3,5,7");
+        assert_eq!(classic_weekdays("1/3").unwrap(), "2,5");
+    }
+
+    #[test]
+    fn named_and_wildcard_weekdays_are_kept() {
+        assert_eq!(classic_weekdays("*").unwrap(), "*");
+        assert_eq!(classic_weekdays("MON-FRI").unwrap(), "MON-FRI");
+    }
+
+    #[test]
+    fn invalid_weekdays_are_rejected() {
+        assert!(classic_weekdays("8").is_err());
+        assert!(classic_weekdays("5-1").is_err());
+        assert!(classic_weekdays("*/0").is_err());
+    }
+
+    #[test]
+    fn weekend_rule_is_active_on_weekends_only() {
+        let rule: AutomationRule = json::from_value(json!({
+            "condition": { "type": "below", "threshold": 1.0 },
+            "active": "* * * * 6,7",
+            "actions": [],
+        })).unwrap();
+
+        // 2024-06-07 is a Friday.
+        let friday = Local.with_ymd_and_hms(2024, 6, 7, 12, 30, 15).unwrap();
+        let saturday = Local.with_ymd_and_hms(2024, 6, 8, 12, 30, 15).unwrap();
+        let sunday = Local.with_ymd_and_hms(2024, 6, 9, 12, 30, 15).unwrap();
+        assert!(!rule.is_active(friday));
+        assert!(rule.is_active(saturday));
+        assert!(rule.is_active(sunday));
+    }
+
+    fn command_rule(program: &str) -> AutomationRule {
+        json::from_value(json!({
+            "condition": { "type": "below", "threshold": 1.0 },
+            "actions": [{ "type": "command", "program": program }],
+        })).unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn rules_set_at_runtime_only_run_allowed_programs() {
+        let config = AutomationsConfig {
+            allowed_programs: vec!["/usr/local/bin/pump".to_string()],
+            ..Default::default()
+        };
+        let engine = AutomationEngine::new(config, CommandsConfig::default(), Arc::new(HashMap::new()));
+
+        assert!(engine.set_rule("pump".to_string(), command_rule("/usr/local/bin/pump")).await.is_ok());
+        assert!(engine.set_rule("shell".to_string(), command_rule("/bin/sh")).await.is_err());
+        assert_eq!(engine.rules().await.into_keys().collect::<Vec<_>>(), ["pump"]);
+    }
+
+    async fn upload(engine: &AutomationEngine, sample: (u64, f64)) -> usize {
+        let previous = engine.record_sample("manager", "source", sample.0, sample.1).await;
+        engine.observe("manager", "source", &Default::default(), sample, previous).await.len()
+    }
+
+    #[rocket::async_test]
+    async fn rates_compare_against_samples_uploaded_before_the_rule() {
+        let engine = AutomationEngine::new(AutomationsConfig::default(), CommandsConfig::default(), Arc::new(HashMap::new()));
+        upload(&engine, (0, 10.0)).await;
+
+        let rule = json::from_value(json!({
+            "condition": { "type": "rate_above", "threshold": 1.0 },
+            "actions": [{ "type": "enqueue", "payload": "open" }],
+        })).unwrap();
+        engine.set_rule("rising".to_string(), rule).await.unwrap();
+
+        assert_eq!(upload(&engine, (60_000, 20.0)).await, 0);
+        assert_eq!(upload(&engine, (61_000, 30.0)).await, 1);
+    }
+}
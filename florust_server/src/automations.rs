@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use rocket::{delete, get, put, State, serde::json::Json};
+
+use crate::{FlorustState, acl::{Acl, Permission}, automation::{AuditEntry, AutomationRule}, auth::Principal, data_source::{DataSourceError, OkResponder, state_op_to_responder}};
+
+/// Returns the rules as the principal may see them, redacted unless the principal may manage them.
+fn visible_rules(acl: &Acl, principal: &Principal<'_>, rules: BTreeMap<String, AutomationRule>) -> BTreeMap<String, AutomationRule> {
+    if principal.authorize(acl, Permission::Admin, None).is_ok() {
+        return rules;
+    }
+
+    rules.into_iter()
+        .map(|(name, rule)| (name, rule.redacted()))
+        .collect()
+}
+
+/// Returns an audit log entry as the principal may see it, redacted unless the principal may manage rules.
+fn visible_entry(acl: &Acl, principal: &Principal<'_>, entry: AuditEntry) -> AuditEntry {
+    if principal.authorize(acl, Permission::Admin, None).is_ok() {
+        return entry;
+    }
+
+    AuditEntry {
+        action: entry.action.redacted(),
+        ..entry
+    }
+}
+
+/// Returns the rules, with the header values and command arguments of their actions redacted, unless
+/// requested by an admin.
+#[get("/rules")]
+pub async fn rules(
+    state: &State<FlorustState>,
+    principal: Principal<'_>
+) -> Result<OkResponder<BTreeMap<String, AutomationRule>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    state_op_to_responder(Ok(visible_rules(&state.acl, &principal, state.automations.rules().await)))
+}
+
+/// Adds a rule, or replaces the existing rule with the same name.
+#[put("/rules/<name>", format = "json", data = "<rule>")]
+pub async fn set_rule(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    name: String,
+    rule: Json<AutomationRule>
+) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Admin, None)?;
+
+    state_op_to_responder(state.automations.set_rule(name, rule.into_inner()).await)
+}
+
+#[delete("/rules/<name>")]
+pub async fn remove_rule(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    name: String
+) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Admin, None)?;
+
+    state_op_to_responder(state.automations.remove_rule(&name).await)
+}
+
+/// Returns the actions rules triggered, oldest first, optionally only those of one rule. Actions are redacted
+/// like those of the rules.
+#[get("/audit?<rule>")]
+pub async fn audit(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    rule: Option<String>
+) -> Result<OkResponder<Vec<AuditEntry>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    let audit = state.automations.audit().await
+        .into_iter()
+        .filter(|entry| match &rule {
+            Some(rule) => *rule == entry.trigger.rule,
+            None => true,
+        })
+        .filter(|entry| {
+            let source = (entry.trigger.manager_id.as_str(), entry.trigger.data_source_id.as_str());
+            state.authorize(&principal, Permission::Read, Some(source)).is_ok()
+        })
+        .map(|entry| visible_entry(&state.acl, &principal, entry))
+        .collect();
+
+    state_op_to_responder(Ok(audit))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rocket::serde::json::{self, json};
+
+    use super::{visible_entry, visible_rules};
+    use crate::{acl::{Acl, AclConfig, Grant, Role}, automation::{AuditEntry, AutomationRule}, auth::Principal};
+
+    fn rules() -> BTreeMap<String, AutomationRule> {
+        let rule = json::from_value(json!({
+            "condition": { "type": "below", "threshold": 20.0 },
+            "actions": [
+                { "type": "webhook", "url": "http://pump.local/on", "headers": { "Authorization": "Bearer secret" } },
+                { "type": "command", "program": "/usr/local/bin/pump", "args": ["--key", "secret"] },
+                { "type": "enqueue", "payload": "open" },
+            ],
+        })).unwrap();
+
+        BTreeMap::from([("water".to_string(), rule)])
+    }
+
+    fn actions(rules: &BTreeMap<String, AutomationRule>) -> json::Value {
+        json::to_value(&rules["water"].actions).unwrap()
+    }
+
+    #[test]
+    fn readers_see_redacted_rules() {
+        let acl = Acl::new(AclConfig::default(), Vec::new());
+        let reader = Grant::new(Role::Reader, &["*/*".to_string()]);
+
+        let redacted = json!([
+            { "type": "webhook", "url": "http://pump.local/on", "headers": { "Authorization": "[redacted]" }, "body": null },
+            { "type": "command", "program": "/usr/local/bin/pump", "args": ["[redacted]", "[redacted]"] },
+            { "type": "enqueue", "manager": null, "source": null, "payload": "open", "ttl_secs": null },
+        ]);
+        assert_eq!(actions(&visible_rules(&acl, &Principal::Granted(&reader), rules())), redacted);
+        assert_eq!(actions(&visible_rules(&acl, &Principal::Anonymous, rules())), redacted);
+
+        let admin = Grant::admin();
+        assert_eq!(actions(&visible_rules(&acl, &Principal::Granted(&admin), rules())), actions(&rules()));
+    }
+
+    #[test]
+    fn readers_see_redacted_audit_entries() {
+        let acl = Acl::new(AclConfig::default(), Vec::new());
+        let reader = Grant::new(Role::Reader, &["*/*".to_string()]);
+        let entry: AuditEntry = json::from_value(json!({
+            "rule": "water",
+            "manager_id": "manager",
+            "data_source_id": "bed",
+            "timestamp": 1000,
+            "value": 15.0,
+            "action": { "type": "webhook", "url": "http://pump.local/on", "headers": { "Authorization": "Bearer secret" } },
+            "status": "succeeded",
+        })).unwrap();
+
+        let entry = json::to_value(visible_entry(&acl, &Principal::Granted(&reader), entry)).unwrap();
+        assert_eq!(entry["action"]["headers"]["Authorization"], "[redacted]");
+        assert_eq!(entry["rule"], "water");
+    }
+}
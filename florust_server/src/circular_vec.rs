@@ -1,7 +1,7 @@
 pub struct CircularVec<T> {
     vec: Vec<T>,
     start: usize,
-    end: usize,
+    len: usize,
     max_size: usize,
 }
 
@@ -13,41 +13,148 @@ impl<T> CircularVec<T> {
         CircularVec {
             vec: vec![default; max_size],
             start: 0,
-            end: 0,
+            len: 0,
             max_size
         }
     }
 
+    /// Appends a value, overwriting the oldest value if the vec is full.
     pub fn append(&mut self, val: T) {
-        self.vec[self.end] = val;
-        self.increment_range();
+        let end = self.wrap(self.start + self.len);
+        self.vec[end] = val;
+
+        if self.len == self.max_size {
+            self.start = self.wrap(self.start + 1);
+        }
+        else {
+            self.len += 1;
+        }
     }
 
+    /// Gets a value by its index, where the oldest value has index 0.
     pub fn get(&self, index: usize) -> Option<&T> {
-        let index = self.start + index;
-        let index = if index >= self.max_size {
+        if index >= self.len {
+            return None;
+        }
+
+        self.vec.get(self.wrap(self.start + index))
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    /// Returns the most recently appended value.
+    pub fn back(&self) -> Option<&T> {
+        self.len.checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    /// Returns the most recently appended value.
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let index = self.wrap(self.start + self.len - 1);
+        self.vec.get_mut(index)
+    }
+
+    /// Iterates over the values, from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len).filter_map(|index| self.get(index))
+    }
+
+    /// Removes the oldest value. Does nothing if the vec is empty.
+    pub fn pop_front(&mut self) {
+        if self.len > 0 {
+            self.start = self.wrap(self.start + 1);
+            self.len -= 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns how many values the vec holds before overwriting the oldest.
+    pub fn capacity(&self) -> usize {
+        self.max_size
+    }
+
+    fn wrap(&self, index: usize) -> usize {
+        if index >= self.max_size {
             index - self.max_size
         }
         else {
             index
-        };
+        }
+    }
+}
 
-        self.vec.get(index)
+#[cfg(test)]
+mod tests {
+    use super::CircularVec;
+
+    fn values(vec: &CircularVec<u32>) -> Vec<u32> {
+        vec.iter().copied().collect()
     }
 
-    fn increment_range(&mut self) {
-        self.end += 1;
+    #[test]
+    fn appends_until_full() {
+        let mut vec = CircularVec::new(3, 0);
+        assert!(vec.is_empty());
+        assert_eq!(vec.front(), None);
+        assert_eq!(vec.back(), None);
+
+        vec.append(1);
+        vec.append(2);
+        assert_eq!(vec.len(), 2);
+        assert_eq!(vec.capacity(), 3);
+        assert_eq!(values(&vec), [1, 2]);
+        assert_eq!(vec.get(2), None);
+    }
 
-        if self.end == self.max_size {
-            self.end = 0;
+    #[test]
+    fn overwrites_the_oldest_values_once_full() {
+        let mut vec = CircularVec::new(3, 0);
+        for value in 1..=5 {
+            vec.append(value);
         }
 
-        if self.end == self.start {
-            self.start += 1;
+        assert_eq!(vec.len(), 3);
+        assert_eq!(values(&vec), [3, 4, 5]);
+        assert_eq!(vec.front(), Some(&3));
+        assert_eq!(vec.back(), Some(&5));
+        assert_eq!(vec.get(3), None);
+
+        *vec.back_mut().unwrap() = 6;
+        assert_eq!(values(&vec), [3, 4, 6]);
+    }
 
-            if self.start == self.max_size {
-                self.start = 0;
-            }
+    #[test]
+    fn pops_from_the_front_across_the_wrap() {
+        let mut vec = CircularVec::new(3, 0);
+        for value in 1..=4 {
+            vec.append(value);
         }
+
+        vec.pop_front();
+        assert_eq!(values(&vec), [3, 4]);
+        vec.append(5);
+        vec.append(6);
+        assert_eq!(values(&vec), [4, 5, 6]);
+
+        for _ in 0..4 {
+            vec.pop_front();
+        }
+        assert!(vec.is_empty());
+        assert_eq!(vec.back_mut(), None);
+
+        vec.append(7);
+        assert_eq!(values(&vec), [7]);
     }
 }
@@ -0,0 +1,378 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use rocket::{serde::{Serialize, Deserialize, json::Value}, tokio::sync::Notify};
+
+use crate::manager_and_data::{ManagerAndDataError, now_millis};
+
+fn default_history_size() -> usize { 100 }
+
+fn default_ack_timeout_secs() -> u64 { 60 }
+
+fn default_max_wait_secs() -> u64 { 30 }
+
+fn default_max_outstanding() -> usize { 100 }
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct CommandsConfig {
+    /// Number of completed commands kept per data source.
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    /// Commands delivered, but not acknowledged within this many seconds, are delivered again.
+    #[serde(default = "default_ack_timeout_secs")]
+    pub ack_timeout_secs: u64,
+    /// Longest a data source may wait for commands when polling.
+    #[serde(default = "default_max_wait_secs")]
+    pub max_wait_secs: u64,
+    /// Number of pending or delivered commands kept per data source. Once reached, enqueueing a command
+    /// expires the oldest one.
+    #[serde(default = "default_max_outstanding")]
+    pub max_outstanding: usize,
+}
+
+impl Default for CommandsConfig {
+    fn default() -> Self {
+        CommandsConfig {
+            history_size: default_history_size(),
+            ack_timeout_secs: default_ack_timeout_secs(),
+            max_wait_secs: default_max_wait_secs(),
+            max_outstanding: default_max_outstanding(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum CommandStatus {
+    /// Waiting for the data source to poll it.
+    Pending,
+    /// Handed to the data source, which hasn't acknowledged it yet.
+    Delivered,
+    Succeeded,
+    Failed,
+    /// Expired before the data source acknowledged it.
+    Expired,
+}
+
+/// A command enqueued for a data source, like turning on a pump.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Command {
+    /// Identifies the command among the commands of its data source.
+    pub id: u64,
+    /// What the data source should do, Florust doesn't interpret it.
+    pub payload: Value,
+    pub status: CommandStatus,
+    /// Timestamps are in milliseconds since the unix epoch.
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub delivered_at: Option<u64>,
+    pub completed_at: Option<u64>,
+    /// Result reported by the data source when acknowledging the command.
+    pub result: Option<Value>,
+}
+
+/// A command, as enqueued by an operator or rule.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct NewCommand {
+    pub payload: Value,
+    /// The command expires if it isn't acknowledged within this many seconds.
+    pub ttl_secs: Option<u64>,
+}
+
+/// Sent by a data source once it carried out a command, or failed to.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct CommandAck {
+    pub success: bool,
+    #[serde(default)]
+    pub result: Option<Value>,
+}
+
+/// The commands of a data source, both outstanding and completed.
+pub struct CommandQueue {
+    next_id: u64,
+    /// Commands which are pending or delivered, oldest first.
+    outstanding: VecDeque<Command>,
+    /// Completed commands, oldest first.
+    history: VecDeque<Command>,
+    /// Wakes up data sources waiting for commands when one is enqueued.
+    notify: Arc<Notify>,
+}
+
+impl CommandQueue {
+    pub fn new() -> CommandQueue {
+        CommandQueue {
+            next_id: 1,
+            outstanding: VecDeque::new(),
+            history: VecDeque::new(),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Returns what data sources waiting for commands are woken up by.
+    pub fn notify(&self) -> Arc<Notify> {
+        Arc::clone(&self.notify)
+    }
+
+    /// Adds a command for the data source to carry out. If the data source already has the configured maximum
+    /// of outstanding commands, the oldest of them expires.
+    pub fn enqueue(&mut self, command: NewCommand, config: &CommandsConfig) -> Command {
+        let created_at = now_millis();
+        self.expire(created_at, config);
+        while self.outstanding.len() >= config.max_outstanding {
+            let Some(oldest) = self.outstanding.pop_front() else {
+                break;
+            };
+            self.complete(oldest, CommandStatus::Expired, created_at, config);
+        }
+
+        let command = Command {
+            id: self.next_id,
+            payload: command.payload,
+            status: CommandStatus::Pending,
+            created_at,
+            expires_at: command.ttl_secs.map(|ttl| created_at.saturating_add(ttl.saturating_mul(1000))),
+            delivered_at: None,
+            completed_at: None,
+            result: None,
+        };
+
+        self.next_id += 1;
+        self.outstanding.push_back(command.clone());
+        self.notify.notify_waiters();
+
+        command
+    }
+
+    fn complete(&mut self, mut command: Command, status: CommandStatus, now: u64, config: &CommandsConfig) -> Command {
+        command.status = status;
+        command.completed_at = Some(now);
+
+        self.history.push_back(command.clone());
+        while self.history.len() > config.history_size {
+            self.history.pop_front();
+        }
+
+        command
+    }
+
+    /// Moves the commands which expired to the history.
+    fn expire(&mut self, now: u64, config: &CommandsConfig) {
+        let (expired, outstanding) = std::mem::take(&mut self.outstanding)
+            .into_iter()
+            .partition::<Vec<_>, _>(|command| command.expires_at.is_some_and(|expires_at| expires_at <= now));
+
+        self.outstanding = outstanding.into();
+        for command in expired {
+            self.complete(command, CommandStatus::Expired, now, config);
+        }
+    }
+
+    /// Returns the commands the data source should carry out, marking them as delivered. These are the
+    /// pending commands, and those delivered earlier but not acknowledged in time.
+    pub fn take(&mut self, now: u64, config: &CommandsConfig) -> Vec<Command> {
+        self.expire(now, config);
+
+        let redeliver_before = now.saturating_sub(config.ack_timeout_secs.saturating_mul(1000));
+        self.outstanding.iter_mut()
+            .filter(|command| match command.status {
+                CommandStatus::Pending => true,
+                CommandStatus::Delivered => command.delivered_at.is_some_and(|delivered_at| delivered_at <= redeliver_before),
+                _ => false,
+            })
+            .map(|command| {
+                command.status = CommandStatus::Delivered;
+                command.delivered_at = Some(now);
+                command.clone()
+            })
+            .collect()
+    }
+
+    pub fn acknowledge(&mut self, id: u64, ack: CommandAck, now: u64, config: &CommandsConfig) -> Result<Command, ManagerAndDataError> {
+        self.expire(now, config);
+
+        let Some(index) = self.outstanding.iter().position(|command| command.id == id) else {
+            return Err(if self.history.iter().any(|command| command.id == id) {
+                ManagerAndDataError::CommandAlreadyCompleted(id)
+            }
+            else {
+                ManagerAndDataError::CommandDoesntExist(id)
+            });
+        };
+
+        let mut command = self.outstanding.remove(index).expect("index was just found");
+        command.result = ack.result;
+        let status = if ack.success { CommandStatus::Succeeded } else { CommandStatus::Failed };
+
+        Ok(self.complete(command, status, now, config))
+    }
+
+    /// Creates a queue holding commands returned by [`history`](CommandQueue::history), like commands restored
+    /// from a snapshot.
+    pub fn from_commands(commands: Vec<Command>) -> CommandQueue {
+        let mut queue = CommandQueue::new();
+        queue.next_id = commands.iter().map(|command| command.id + 1).max().unwrap_or(1);
+
+        for command in commands {
+            match command.status {
+                CommandStatus::Pending | CommandStatus::Delivered => queue.outstanding.push_back(command),
+                _ => queue.history.push_back(command),
+            }
+        }
+
+        queue
+    }
+
+    /// Returns every command, completed ones first, oldest first.
+    pub fn history(&self) -> Vec<Command> {
+        self.history.iter().chain(self.outstanding.iter()).cloned().collect()
+    }
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        CommandQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::{serde::json::json, tokio::{pin, time::timeout}};
+
+    use super::{Command, CommandAck, CommandQueue, CommandStatus, CommandsConfig, NewCommand};
+    use crate::manager_and_data::{ManagerAndDataError, now_millis};
+
+    fn command(payload: &str, ttl_secs: Option<u64>) -> NewCommand {
+        NewCommand { payload: json!(payload), ttl_secs }
+    }
+
+    fn ids(commands: &[Command]) -> Vec<u64> {
+        commands.iter().map(|command| command.id).collect()
+    }
+
+    fn ack(success: bool) -> CommandAck {
+        CommandAck { success, result: Some(json!("done")) }
+    }
+
+    #[test]
+    fn unacknowledged_commands_are_delivered_again() {
+        let config = CommandsConfig { ack_timeout_secs: 10, ..Default::default() };
+        let mut queue = CommandQueue::new();
+        let now = now_millis();
+
+        queue.enqueue(command("open", None), &config);
+        let taken = queue.take(now, &config);
+        assert_eq!(ids(&taken), [1]);
+        assert_eq!(taken[0].status, CommandStatus::Delivered);
+        assert_eq!(taken[0].delivered_at, Some(now));
+
+        queue.enqueue(command("close", None), &config);
+        assert_eq!(ids(&queue.take(now + 1_000, &config)), [2]);
+        assert!(queue.take(now + 5_000, &config).is_empty());
+        assert_eq!(ids(&queue.take(now + 10_000, &config)), [1]);
+        assert_eq!(ids(&queue.take(now + 11_000, &config)), [2]);
+    }
+
+    #[test]
+    fn acknowledged_commands_are_completed() {
+        let config = CommandsConfig::default();
+        let mut queue = CommandQueue::new();
+        let now = now_millis();
+        queue.enqueue(command("open", None), &config);
+        queue.enqueue(command("close", None), &config);
+        queue.take(now, &config);
+
+        let succeeded = queue.acknowledge(1, ack(true), now + 1_000, &config).unwrap();
+        assert_eq!(succeeded.status, CommandStatus::Succeeded);
+        assert_eq!(succeeded.completed_at, Some(now + 1_000));
+        assert_eq!(succeeded.result, Some(json!("done")));
+        assert_eq!(queue.acknowledge(2, ack(false), now + 1_000, &config).unwrap().status, CommandStatus::Failed);
+
+        assert!(matches!(queue.acknowledge(1, ack(true), now, &config), Err(ManagerAndDataError::CommandAlreadyCompleted(1))));
+        assert!(matches!(queue.acknowledge(3, ack(true), now, &config), Err(ManagerAndDataError::CommandDoesntExist(3))));
+        assert!(queue.take(now + 120_000, &config).is_empty());
+    }
+
+    #[test]
+    fn commands_expire_after_their_ttl() {
+        let config = CommandsConfig::default();
+        let mut queue = CommandQueue::new();
+        let expires_at = queue.enqueue(command("open", Some(5)), &config).expires_at.unwrap();
+        queue.enqueue(command("close", None), &config);
+
+        assert_eq!(ids(&queue.take(expires_at, &config)), [2]);
+        assert!(matches!(queue.acknowledge(1, ack(true), expires_at, &config), Err(ManagerAndDataError::CommandAlreadyCompleted(1))));
+
+        let history = queue.history();
+        assert_eq!(history[0].status, CommandStatus::Expired);
+        assert_eq!(history[0].completed_at, Some(expires_at));
+    }
+
+    #[test]
+    fn only_the_latest_completed_commands_are_kept() {
+        let config = CommandsConfig { history_size: 2, ..Default::default() };
+        let mut queue = CommandQueue::new();
+        let now = now_millis();
+
+        for _ in 0..4 {
+            let id = queue.enqueue(command("open", None), &config).id;
+            queue.acknowledge(id, ack(true), now, &config).unwrap();
+        }
+        queue.enqueue(command("close", None), &config);
+
+        assert_eq!(ids(&queue.history()), [3, 4, 5]);
+    }
+
+    #[test]
+    fn the_oldest_outstanding_command_expires_once_the_maximum_is_reached() {
+        let config = CommandsConfig { max_outstanding: 2, ..Default::default() };
+        let mut queue = CommandQueue::new();
+        let now = now_millis();
+
+        for _ in 0..3 {
+            queue.enqueue(command("open", None), &config);
+        }
+
+        assert_eq!(ids(&queue.take(now, &config)), [2, 3]);
+        assert!(matches!(queue.acknowledge(1, ack(true), now, &config), Err(ManagerAndDataError::CommandAlreadyCompleted(1))));
+
+        let history = queue.history();
+        assert_eq!(ids(&history), [1, 2, 3]);
+        assert_eq!(history[0].status, CommandStatus::Expired);
+    }
+
+    #[test]
+    fn queues_are_restored_from_their_history() {
+        let config = CommandsConfig::default();
+        let mut queue = CommandQueue::new();
+        let now = now_millis();
+        for _ in 0..3 {
+            queue.enqueue(command("open", None), &config);
+        }
+        queue.take(now, &config);
+        queue.acknowledge(2, ack(true), now, &config).unwrap();
+
+        let mut restored = CommandQueue::from_commands(queue.history());
+        assert_eq!(ids(&restored.history()), [2, 1, 3]);
+        assert_eq!(restored.enqueue(command("close", None), &config).id, 4);
+        assert!(matches!(restored.acknowledge(2, ack(true), now, &config), Err(ManagerAndDataError::CommandAlreadyCompleted(2))));
+        assert_eq!(restored.acknowledge(3, ack(true), now, &config).unwrap().status, CommandStatus::Succeeded);
+    }
+
+    #[rocket::async_test]
+    async fn enqueueing_wakes_up_waiting_data_sources() {
+        let config = CommandsConfig::default();
+        let mut queue = CommandQueue::new();
+        let notify = queue.notify();
+        let notified = notify.notified();
+        pin!(notified);
+        notified.as_mut().enable();
+
+        queue.enqueue(command("open", None), &config);
+        assert!(timeout(Duration::from_secs(1), notified).await.is_ok());
+    }
+}
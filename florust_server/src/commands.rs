@@ -0,0 +1,60 @@
+use rocket::{get, post, State, serde::json::Json};
+
+use crate::{FlorustState, acl::Permission, auth::Principal, command_queue::{Command, CommandAck, NewCommand}, data_source::{DataSourceError, OkResponder, state_op_to_responder}};
+
+/// Enqueues a command for a data source to carry out.
+#[post("/<manager_id>/<data_source_id>", format = "json", data = "<command>")]
+pub async fn enqueue(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String,
+    command: Json<NewCommand>
+) -> Result<OkResponder<Command>, DataSourceError> {
+    state.authorize(&principal, Permission::Write, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(state.enqueue_command(&manager_id, &data_source_id, command.into_inner()).await)
+}
+
+/// Returns the commands the data source should carry out, waiting up to `wait` seconds for one to be
+/// enqueued if there are none.
+#[get("/<manager_id>/<data_source_id>/poll?<wait>")]
+pub async fn poll(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String,
+    wait: Option<u64>
+) -> Result<OkResponder<Vec<Command>>, DataSourceError> {
+    state.authorize(&principal, Permission::Write, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(state.poll_commands(&manager_id, &data_source_id, wait.unwrap_or(0)).await)
+}
+
+/// Reports that the data source carried out a command, or failed to.
+#[post("/<manager_id>/<data_source_id>/<command_id>/ack", format = "json", data = "<ack>")]
+pub async fn acknowledge(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String,
+    command_id: u64,
+    ack: Json<CommandAck>
+) -> Result<OkResponder<Command>, DataSourceError> {
+    state.authorize(&principal, Permission::Write, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(state.acknowledge_command(&manager_id, &data_source_id, command_id, ack.into_inner()).await)
+}
+
+/// Returns the commands of a data source, completed ones first, oldest first.
+#[get("/<manager_id>/<data_source_id>")]
+pub async fn history(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String
+) -> Result<OkResponder<Vec<Command>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(state.commands(&manager_id, &data_source_id).await)
+}
@@ -0,0 +1,56 @@
+use rocket::{figment::Figment, serde::{Serialize, Deserialize}};
+
+use crate::{acl::AclConfig, alerting::AlertsConfig, automation::AutomationsConfig, command_queue::CommandsConfig, heartbeat::HeartbeatConfig, lifecycle::LifecycleConfig, notifications::NotificationsConfig, polling::PollingConfig, rate_limit::LimitsConfig, retention::RetentionConfig, rollup::RollupConfig};
+#[cfg(feature = "sysfs_plugin")]
+use crate::sysfs_plugin::SysfsConfig;
+
+/// Server wide configuration, read from the `florust` key of Rocket's configuration (`Rocket.toml`, or
+/// `ROCKET_FLORUST` environment variables).
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct FlorustConfig {
+    /// SHA-256 hashes (hex encoded) of the tokens which are allowed to use the management routes.
+    #[serde(default)]
+    pub admin_tokens: Vec<String>,
+    #[serde(default)]
+    pub acl: AclConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub rollups: RollupConfig,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub commands: CommandsConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub automations: AutomationsConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub lifecycle: LifecycleConfig,
+    #[serde(default)]
+    pub polling: PollingConfig,
+    #[cfg(feature = "sysfs_plugin")]
+    #[serde(default)]
+    pub sysfs: SysfsConfig,
+}
+
+impl FlorustConfig {
+    /// Extracts the Florust configuration from Rocket's figment, falling back to the default configuration
+    /// if no `florust` section is present. Fails if the configuration can't be parsed, or is invalid.
+    pub fn from_figment(figment: &Figment) -> Result<FlorustConfig, Box<rocket::figment::Error>> {
+        if figment.find_value("florust").is_ok() {
+            let config: FlorustConfig = figment.extract_inner("florust").map_err(Box::new)?;
+            config.limits.validate().map_err(|err| Box::new(err.into()))?;
+
+            Ok(config)
+        }
+        else {
+            Ok(FlorustConfig::default())
+        }
+    }
+}
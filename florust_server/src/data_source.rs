@@ -1,18 +1,30 @@
-use florust_common::{UploadedData, server::FlorustServerPluginError};
-use rocket::{form::Form, post, put, get, Responder, State, serde::json::Json};
+use florust_common::{DataSourceMetadata, DataSourceRegistration, UploadedData, RegisteredDataSource, server::FlorustServerPluginError};
+use std::net::IpAddr;
 
-use crate::{FlorustState, manager_and_data::{ManagerAndDataError, DataType, self}};
+use rocket::{data::{Data, Limits}, form::Form, post, put, get, http::Header, FromForm, Responder, State, serde::json::Json};
+
+use crate::{FlorustState, acl::Permission, aggregate::{AggregateBucket, parse_aggregations}, heartbeat::HeartbeatListing, auth::{AuthError, Principal}, manager_and_data::{ManagerAndDataError, DataType, DataSourceInfo, DataSourceListing, Series, now_millis, self}, query::{DEFAULT_BUCKETS, QuerySeries, Selector}};
 
 #[derive(Responder)]
 pub enum DataSourceError {
     #[response(status = 400, content_type = "json")]
     BadRequest(Json<ManagerAndDataError>),
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<AuthError>),
+    #[response(status = 403, content_type = "json")]
+    Forbidden(Json<AuthError>),
     #[response(status = 404, content_type = "json")]
     NotFound(Json<ManagerAndDataError>),
     #[response(status = 409, content_type = "json")]
     Conflict(Json<ManagerAndDataError>),
+    #[response(status = 413, content_type = "json")]
+    PayloadTooLarge(Json<ManagerAndDataError>),
+    #[response(status = 429, content_type = "json")]
+    TooManyRequests(Json<ManagerAndDataError>, Header<'static>),
     #[response(status = 500, content_type = "json")]
-    InternalError(Json<ManagerAndDataError>)
+    InternalError(Json<ManagerAndDataError>),
+    #[response(status = 503, content_type = "json")]
+    ServiceUnavailable(Json<ManagerAndDataError>)
 }
 
 impl From<ManagerAndDataError> for DataSourceError {
@@ -35,6 +47,45 @@ impl From<ManagerAndDataError> for DataSourceError {
             ManagerAndDataError::IndexOutOfBounds => Self::InternalError(
                 Json(value)
             ),
+            ManagerAndDataError::RateLimited { retry_after } => {
+                let retry_after = Header::new("Retry-After", retry_after.to_string());
+                Self::TooManyRequests(Json(value), retry_after)
+            },
+            ManagerAndDataError::PayloadTooLarge { .. } => Self::PayloadTooLarge(
+                Json(value)
+            ),
+            ManagerAndDataError::InvalidUpload(_)
+                | ManagerAndDataError::InvalidQuery(_)
+                | ManagerAndDataError::InvalidUnit(_)
+                | ManagerAndDataError::ProgramNotAllowed(_) => Self::BadRequest(
+                Json(value)
+            ),
+            ManagerAndDataError::AlertRuleDoesntExist(_)
+                | ManagerAndDataError::AutomationRuleDoesntExist(_)
+                | ManagerAndDataError::CommandDoesntExist(_) => Self::NotFound(
+                Json(value)
+            ),
+            ManagerAndDataError::CommandAlreadyCompleted(_) => Self::Conflict(
+                Json(value)
+            ),
+            ManagerAndDataError::InvalidImport(_) | ManagerAndDataError::InvalidSnapshot(_) => Self::BadRequest(
+                Json(value)
+            ),
+            ManagerAndDataError::BodyTooLarge { .. } => Self::PayloadTooLarge(
+                Json(value)
+            ),
+            ManagerAndDataError::ShuttingDown => Self::ServiceUnavailable(
+                Json(value)
+            ),
+        }
+    }
+}
+
+impl From<AuthError> for DataSourceError {
+    fn from(value: AuthError) -> Self {
+        match value {
+            AuthError::MissingToken | AuthError::InvalidToken => Self::Unauthorized(Json(value)),
+            AuthError::Forbidden => Self::Forbidden(Json(value)),
         }
     }
 }
@@ -43,33 +94,47 @@ impl From<ManagerAndDataError> for DataSourceError {
 #[response(status = 200)]
 pub struct OkResponder<T>(Json<T>) where T: Send + Sync;
 
-fn state_op_to_responder<T: Send + Sync>(op_result: manager_and_data::Result<T>) -> Result<OkResponder<T>, DataSourceError> {
+pub fn state_op_to_responder<T: Send + Sync>(op_result: manager_and_data::Result<T>) -> Result<OkResponder<T>, DataSourceError> {
     op_result.map(|value| OkResponder(Json(value)))
         .map_err(DataSourceError::from)
 }
 
-#[post("/register/<manager_id>/<data_source_id>", data = "<data>")]
+/// Registers a data source, optionally with data for its manager and metadata, sent as a form with the
+/// fields of [`DataSourceRegistration`], like `metadata.units=%25&metadata.tags[bed]=3`.
+#[post("/register/<manager_id>/<data_source_id>", data = "<registration>")]
 pub async fn register(
     state: &State<FlorustState>,
+    principal: Principal<'_>,
     manager_id: String,
     data_source_id: String,
-    data: Option<Form<UploadedData>>
-) -> Result<OkResponder<()>, DataSourceError> {
-    let data = match &data {
-        Some(data) => Some(data.data.as_slice()),
-        None => None
+    registration: Option<Form<DataSourceRegistration>>
+) -> Result<OkResponder<RegisteredDataSource>, DataSourceError> {
+    state.authorize_registration(&principal, &manager_id, &data_source_id).await?;
+
+    let (data, metadata, expected_interval_secs) = match registration {
+        Some(registration) => {
+            let registration = registration.into_inner();
+            (registration.data, registration.metadata, registration.expected_interval_secs)
+        },
+        None => (None, DataSourceMetadata::default(), None)
     };
 
-    state_op_to_responder(state.register_data_source(&manager_id, data_source_id, data).await)
+    state_op_to_responder(
+        state.register_data_source(&manager_id, data_source_id, data.as_deref(), metadata, expected_interval_secs).await
+            .map(|token| RegisteredDataSource { token })
+    )
 }
 
 #[post("/unregister/<manager_id>/<data_source_id>", data = "<data>")]
 pub async fn unregister(
     state: &State<FlorustState>,
+    principal: Principal<'_>,
     manager_id: String,
     data_source_id: String,
     data: Option<Form<UploadedData>>
 ) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Write, Some((&manager_id, &data_source_id)))?;
+
     let data = match &data {
         Some(data) => Some(data.data.as_slice()),
         None => None
@@ -78,34 +143,226 @@ pub async fn unregister(
     state_op_to_responder(state.deregister_data_source(&manager_id, &data_source_id, data).await)
 }
 
-#[put("/upload_data/<manager_id>/<data_source_id>", data = "<data>")]
+#[put("/upload_data/<manager_id>/<data_source_id>", format = "json", data = "<data>")]
 pub async fn json_upload_data(
     state: &State<FlorustState>,
+    principal: Principal<'_>,
     manager_id: String,
     data_source_id: String,
-    data: Json<UploadedData>,
+    limits: &Limits,
+    data: Data<'_>,
+    client: Option<IpAddr>,
 ) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Write, Some((&manager_id, &data_source_id)))?;
+
+    let body = state.read_upload(&manager_id, &data_source_id, data, limits.get("json").unwrap_or(Limits::JSON)).await?;
+    let data: UploadedData = serde_json::from_slice(&body)
+        .map_err(|err| ManagerAndDataError::InvalidUpload(err.to_string()))?;
 
-    state_op_to_responder(state.update_data(&manager_id, &data_source_id, data.data.as_slice()).await)
+    state_op_to_responder(state.update_data(&manager_id, &data_source_id, data.data.as_slice(), client).await)
 }
 
-#[put("/upload_data/<manager_id>/<data_source_id>", data = "<data>")]
+#[put("/upload_data/<manager_id>/<data_source_id>", format = "form", data = "<data>")]
 pub async fn form_upload_data(
     state: &State<FlorustState>,
+    principal: Principal<'_>,
     manager_id: String,
     data_source_id: String,
-    data: Form<UploadedData>,
+    limits: &Limits,
+    data: Data<'_>,
+    client: Option<IpAddr>,
 ) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Write, Some((&manager_id, &data_source_id)))?;
+
+    let body = state.read_upload(&manager_id, &data_source_id, data, limits.get("form").unwrap_or(Limits::FORM)).await?;
+    let data = std::str::from_utf8(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|body| Form::<UploadedData>::parse(body).map_err(|err| err.to_string()))
+        .map_err(ManagerAndDataError::InvalidUpload)?;
 
-    state_op_to_responder(state.update_data(&manager_id, &data_source_id, data.data.as_slice()).await)
+    state_op_to_responder(state.update_data(&manager_id, &data_source_id, data.data.as_slice(), client).await)
 }
 
-#[get("/<manager_id>/<data_source_id>/<index>")]
+/// Returns a logged sample of a data source, converted to `unit` if one is given.
+#[get("/<manager_id>/<data_source_id>/<index>?<unit>")]
 pub async fn get_data(
     state: &State<FlorustState>,
+    principal: Principal<'_>,
     manager_id: String,
     data_source_id: String,
-    index: usize
+    index: usize,
+    unit: Option<String>
 ) -> Result<OkResponder<DataType>, DataSourceError> {
-    state_op_to_responder(state.get_data(&manager_id, &data_source_id, index).await)
+    state.authorize(&principal, Permission::Read, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(state.get_data(&manager_id, &data_source_id, index, unit.as_deref()).await)
+}
+
+/// Returns the data logged between `from` and `to`, in milliseconds since the unix epoch, converted to
+/// `unit` if one is given. `to` defaults to now, and `from` to the start of the epoch.
+#[get("/range/<manager_id>/<data_source_id>?<from>&<to>&<unit>")]
+pub async fn get_range(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String,
+    from: Option<u64>,
+    to: Option<u64>,
+    unit: Option<String>
+) -> Result<OkResponder<Series>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, Some((&manager_id, &data_source_id)))?;
+
+    let to = to.unwrap_or_else(now_millis);
+    let from = from.unwrap_or(0);
+
+    state_op_to_responder(state.get_range(&manager_id, &data_source_id, from, to, unit.as_deref()).await)
+}
+
+#[derive(FromForm)]
+pub struct AggregateQuery {
+    /// Comma separated aggregation functions, like `min,max,p95`.
+    fns: String,
+    /// Start of the range, defaults to the oldest data of the data source.
+    from: Option<u64>,
+    /// End of the range, defaults to now.
+    to: Option<u64>,
+    /// Width of the buckets the range is split into, in milliseconds.
+    bucket: Option<u64>,
+    /// Unit to convert the samples to before aggregating them.
+    unit: Option<String>,
+}
+
+/// Computes aggregation functions over the samples logged in a range of time, optionally split into
+/// fixed width buckets.
+#[get("/aggregate/<manager_id>/<data_source_id>?<query..>")]
+pub async fn aggregate(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String,
+    query: AggregateQuery
+) -> Result<OkResponder<Vec<AggregateBucket>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, Some((&manager_id, &data_source_id)))?;
+
+    let aggregations = parse_aggregations(&query.fns)?;
+    let to = query.to.unwrap_or_else(now_millis);
+
+    state_op_to_responder(state.aggregate(&manager_id, &data_source_id, &aggregations, query.from, to, query.bucket, query.unit.as_deref()).await)
+}
+
+#[derive(FromForm)]
+pub struct QueryParams {
+    /// The query to evaluate, see [`query`](crate::query) for its syntax.
+    q: String,
+    /// Start of the range, defaults to [`DEFAULT_BUCKETS`] steps before its end.
+    from: Option<u64>,
+    /// End of the range, defaults to now.
+    to: Option<u64>,
+    /// Width of the buckets series are joined on, in milliseconds, defaults to a minute.
+    step: Option<u64>,
+}
+
+/// Evaluates a query combining the data of any number of data sources.
+#[get("/query?<params..>")]
+pub async fn query(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    params: QueryParams
+) -> Result<OkResponder<Vec<QuerySeries>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    let to = params.to.unwrap_or_else(now_millis);
+    let step = params.step.unwrap_or(60 * 1000);
+    let from = params.from.unwrap_or_else(|| to.saturating_sub(step.saturating_mul(DEFAULT_BUCKETS - 1)));
+
+    state_op_to_responder(state.query(&principal, &params.q, from, to, step).await)
+}
+
+/// Builds the selector of the listing routes, from glob patterns the ids must match and `key:value_glob`
+/// tag filters.
+pub fn list_selector(manager: Option<&str>, source: Option<&str>, tags: &[String]) -> manager_and_data::Result<Selector> {
+    let mut selector = Selector::new(manager.unwrap_or("*"), source.unwrap_or("*"));
+    for tag in tags {
+        let (key, value) = tag.split_once(':').ok_or_else(|| {
+            ManagerAndDataError::InvalidQuery(format!("tag filter must be of the form key:value, got {}", tag))
+        })?;
+        selector = selector.with_tag(key, value);
+    }
+
+    Ok(selector)
+}
+
+#[derive(FromForm)]
+pub struct ListParams {
+    /// Glob pattern the manager id must match.
+    manager: Option<String>,
+    /// Glob pattern the data source id must match.
+    source: Option<String>,
+    /// Tags the data sources must have, as `key:value_glob`.
+    tag: Vec<String>,
+}
+
+/// Lists the data sources the requester may read, optionally filtered by id and tags.
+#[get("/list?<params..>")]
+pub async fn list(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    params: ListParams
+) -> Result<OkResponder<Vec<DataSourceListing>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    let selector = list_selector(params.manager.as_deref(), params.source.as_deref(), &params.tag)?;
+    Ok(OkResponder(Json(state.list_data_sources(&principal, &selector).await)))
+}
+
+#[derive(FromForm)]
+pub struct HeartbeatParams {
+    /// Glob pattern the manager id must match.
+    manager: Option<String>,
+    /// Glob pattern the data source id must match.
+    source: Option<String>,
+    /// Tags the data sources must have, as `key:value_glob`.
+    tag: Vec<String>,
+    /// Only list the data sources which are, or aren't, stale.
+    stale: Option<bool>,
+}
+
+/// Lists when the registered data sources the requester may read last uploaded data, and whether they're
+/// stale, optionally filtered like the listing route.
+#[get("/heartbeats?<params..>")]
+pub async fn heartbeats(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    params: HeartbeatParams
+) -> Result<OkResponder<Vec<HeartbeatListing>>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    let selector = list_selector(params.manager.as_deref(), params.source.as_deref(), &params.tag)?;
+    Ok(OkResponder(Json(state.heartbeats(&principal, &selector, params.stale).await)))
+}
+
+/// Replaces the metadata of a data source.
+#[put("/metadata/<manager_id>/<data_source_id>", format = "json", data = "<metadata>")]
+pub async fn set_metadata(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String,
+    metadata: Json<DataSourceMetadata>
+) -> Result<OkResponder<()>, DataSourceError> {
+    state.authorize(&principal, Permission::Write, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(state.set_metadata(&manager_id, &data_source_id, metadata.into_inner()).await)
+}
+
+#[get("/status/<manager_id>/<data_source_id>")]
+pub async fn status(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String
+) -> Result<OkResponder<DataSourceInfo>, DataSourceError> {
+    state.authorize(&principal, Permission::Read, Some((&manager_id, &data_source_id)))?;
+
+    state_op_to_responder(state.data_source_info(&manager_id, &data_source_id).await)
 }
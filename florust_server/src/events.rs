@@ -0,0 +1,122 @@
+use florust_common::DataSourceMetadata;
+use rocket::{serde::{Serialize, Deserialize}, tokio::sync::broadcast};
+
+/// How many events a subscriber may fall behind before it starts missing events.
+const CAPACITY: usize = 1024;
+
+/// Something noteworthy that happened in the server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// A plugin in the plugins dir couldn't be loaded.
+    PluginLoadFailed {
+        plugin: String,
+        error: String,
+    },
+    DataSourceRegistered {
+        manager_id: String,
+        data_source_id: String,
+        metadata: DataSourceMetadata,
+    },
+    DataSourceDeregistered {
+        manager_id: String,
+        data_source_id: String,
+    },
+    /// A data source went longer than expected without uploading a sample.
+    DataSourceStale {
+        manager_id: String,
+        data_source_id: String,
+    },
+    /// A data source manager rejected data uploaded by a data source as invalid.
+    DataRejected {
+        manager_id: String,
+        data_source_id: String,
+        error: String,
+    },
+}
+
+impl ServerEvent {
+    /// Name of the kind of event, as used in configuration, like `plugin_load_failed`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerEvent::PluginLoadFailed { .. } => "plugin_load_failed",
+            ServerEvent::DataSourceRegistered { .. } => "data_source_registered",
+            ServerEvent::DataSourceDeregistered { .. } => "data_source_deregistered",
+            ServerEvent::DataSourceStale { .. } => "data_source_stale",
+            ServerEvent::DataRejected { .. } => "data_rejected",
+        }
+    }
+
+    /// One line description of the event, for humans.
+    pub fn summary(&self) -> String {
+        match self {
+            ServerEvent::PluginLoadFailed { plugin, error } => format!("Plugin {} failed to load: {}", plugin, error),
+            ServerEvent::DataSourceRegistered { manager_id, data_source_id, .. } => {
+                format!("Data source {}/{} was registered", manager_id, data_source_id)
+            },
+            ServerEvent::DataSourceDeregistered { manager_id, data_source_id } => {
+                format!("Data source {}/{} was deregistered", manager_id, data_source_id)
+            },
+            ServerEvent::DataSourceStale { manager_id, data_source_id } => {
+                format!("Data source {}/{} stopped reporting", manager_id, data_source_id)
+            },
+            ServerEvent::DataRejected { manager_id, data_source_id, error } => {
+                format!("Data uploaded by {}/{} was rejected: {}", manager_id, data_source_id, error)
+            },
+        }
+    }
+
+    /// The manager's id and id of the data source the event is about, if it's about one.
+    pub fn data_source(&self) -> Option<(&str, &str)> {
+        match self {
+            ServerEvent::PluginLoadFailed { .. } => None,
+            ServerEvent::DataSourceRegistered { manager_id, data_source_id, .. }
+                | ServerEvent::DataSourceDeregistered { manager_id, data_source_id }
+                | ServerEvent::DataSourceStale { manager_id, data_source_id }
+                | ServerEvent::DataRejected { manager_id, data_source_id, .. } => Some((manager_id, data_source_id)),
+        }
+    }
+
+    /// Identifies events which are repeats of each other, for deduplication. Repeated rejections of a data
+    /// source's data are the same event, even if the error differs.
+    pub fn dedup_key(&self) -> String {
+        match self {
+            ServerEvent::PluginLoadFailed { plugin, .. } => format!("{}:{}", self.kind(), plugin),
+            ServerEvent::DataSourceRegistered { manager_id, data_source_id, .. }
+                | ServerEvent::DataSourceDeregistered { manager_id, data_source_id }
+                | ServerEvent::DataSourceStale { manager_id, data_source_id }
+                | ServerEvent::DataRejected { manager_id, data_source_id, .. } => {
+                format!("{}:{}/{}", self.kind(), manager_id, data_source_id)
+            },
+        }
+    }
+}
+
+/// Broadcasts server events to every subscriber. Events emitted while nobody is subscribed are dropped.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ServerEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+
+    pub fn emit(&self, event: ServerEvent) {
+        // Sending only fails if there are no subscribers, in which case nobody is interested in the event.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+
+use florust_common::DataSourceMetadata;
+use log::warn;
+use rocket::{FromForm, FromFormField, State, get, http::ContentType, response::stream::ByteStream, serde::{Serialize, json::{Value, json}}};
+
+use crate::{FlorustState, acl::Permission, auth::Principal, data_source::{DataSourceError, list_selector}, manager_and_data::{DataType, ManagerAndDataError, Sample, now_millis}};
+
+/// Number of samples encoded at once, and so the most samples an export holds in memory after fetching them.
+const CHUNK_SIZE: usize = 1000;
+
+#[derive(FromFormField, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+#[derive(FromForm)]
+pub struct ExportParams {
+    format: ExportFormat,
+    /// Glob pattern the manager id must match.
+    manager: Option<String>,
+    /// Glob pattern the data source id must match.
+    source: Option<String>,
+    /// Tags the data sources must have, as `key:value_glob`.
+    tag: Vec<String>,
+    /// Start of the range, defaults to the start of the epoch.
+    from: Option<u64>,
+    /// End of the range, defaults to now.
+    to: Option<u64>,
+}
+
+fn value_to_json(value: DataType) -> Value {
+    match value {
+        DataType::IInteger(value) => json!(value),
+        DataType::UInteger(value) => json!(value),
+        DataType::Float(value) => json!(value),
+    }
+}
+
+/// A sample, as exported, along with the data source it was logged for.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ExportedSample<'a> {
+    manager_id: &'a str,
+    data_source_id: &'a str,
+    display_name: Option<&'a str>,
+    units: Option<&'a str>,
+    tags: &'a BTreeMap<String, String>,
+    /// Milliseconds since the unix epoch.
+    timestamp: u64,
+    value: Value,
+}
+
+/// The samples of one data source, fetched when the export gets to the data source.
+struct ExportedSource {
+    manager_id: String,
+    data_source_id: String,
+    metadata: DataSourceMetadata,
+    samples: Vec<Sample<DataType>>,
+}
+
+impl ExportedSource {
+    fn rows(&self, samples: &[Sample<DataType>]) -> Vec<ExportedSample<'_>> {
+        samples.iter()
+            .map(|sample| ExportedSample {
+                manager_id: &self.manager_id,
+                data_source_id: &self.data_source_id,
+                display_name: self.metadata.display_name.as_deref(),
+                units: self.metadata.units.as_deref(),
+                tags: &self.metadata.tags,
+                timestamp: sample.timestamp,
+                value: value_to_json(sample.value),
+            })
+            .collect()
+    }
+}
+
+const CSV_HEADER: [&str; 7] = ["manager_id", "data_source_id", "display_name", "units", "tags", "timestamp", "value"];
+
+/// Encodes samples as CSV records, with the tags as a JSON object.
+fn encode_csv(rows: &[ExportedSample], header: bool) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if header {
+        let _ = writer.write_record(CSV_HEADER);
+    }
+
+    for row in rows {
+        let tags = serde_json::to_string(row.tags).unwrap_or_default();
+        let _ = writer.write_record([
+            row.manager_id,
+            row.data_source_id,
+            row.display_name.unwrap_or_default(),
+            row.units.unwrap_or_default(),
+            &tags,
+            &row.timestamp.to_string(),
+            &row.value.to_string(),
+        ]);
+    }
+
+    writer.into_inner().unwrap_or_default()
+}
+
+fn encode_ndjson(rows: &[ExportedSample]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for row in rows {
+        if serde_json::to_writer(&mut encoded, row).is_ok() {
+            encoded.push(b'\n');
+        }
+    }
+
+    encoded
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_export {
+    use std::{io::{self, Write}, sync::{Arc, Mutex}};
+
+    use parquet::{
+        data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+        errors::Result,
+        file::{properties::WriterProperties, writer::SerializedFileWriter},
+        schema::parser::parse_message_type,
+    };
+
+    use super::ExportedSample;
+
+    const SCHEMA: &str = "
+        message sample {
+            REQUIRED BYTE_ARRAY manager_id (UTF8);
+            REQUIRED BYTE_ARRAY data_source_id (UTF8);
+            OPTIONAL BYTE_ARRAY display_name (UTF8);
+            OPTIONAL BYTE_ARRAY units (UTF8);
+            REQUIRED BYTE_ARRAY tags (UTF8);
+            REQUIRED INT64 timestamp (TIMESTAMP(MILLIS, true));
+            REQUIRED DOUBLE value;
+        }
+    ";
+
+    /// Buffer the Parquet writer writes to, which the export drains after every row group.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().map_err(|_| io::Error::other("buffer lock poisoned"))?.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Writes samples as a Parquet file, a row group at a time. Values are written as doubles, whatever the
+    /// type of the data source.
+    pub struct ParquetEncoder {
+        buffer: SharedBuffer,
+        writer: SerializedFileWriter<SharedBuffer>,
+    }
+
+    fn strings<'a>(values: impl Iterator<Item = &'a str>) -> Vec<ByteArray> {
+        values.map(ByteArray::from).collect()
+    }
+
+    /// Values and definition levels of an optional column, where a level of 0 marks a missing value.
+    fn optional_strings<'a>(values: impl Iterator<Item = Option<&'a str>>) -> (Vec<ByteArray>, Vec<i16>) {
+        let values: Vec<Option<&str>> = values.collect();
+        let levels = values.iter().map(|value| value.is_some() as i16).collect();
+        (strings(values.into_iter().flatten()), levels)
+    }
+
+    impl ParquetEncoder {
+        pub fn new() -> Result<ParquetEncoder> {
+            let buffer = SharedBuffer::default();
+            let schema = Arc::new(parse_message_type(SCHEMA)?);
+            let writer = SerializedFileWriter::new(buffer.clone(), schema, Arc::new(WriterProperties::builder().build()))?;
+
+            Ok(ParquetEncoder { buffer, writer })
+        }
+
+        fn take(&self) -> Vec<u8> {
+            self.buffer.0.lock().map(|mut buffer| std::mem::take(&mut *buffer)).unwrap_or_default()
+        }
+
+        /// Writes the samples as a row group, returning the bytes written since the last call.
+        pub fn row_group(&mut self, rows: &[ExportedSample]) -> Result<Vec<u8>> {
+            let mut row_group = self.writer.next_row_group()?;
+            let mut index = 0;
+
+            while let Some(mut column) = row_group.next_column()? {
+                match index {
+                    0 => column.typed::<ByteArrayType>().write_batch(&strings(rows.iter().map(|row| row.manager_id)), None, None)?,
+                    1 => column.typed::<ByteArrayType>().write_batch(&strings(rows.iter().map(|row| row.data_source_id)), None, None)?,
+                    2 | 3 => {
+                        let (values, levels) = optional_strings(rows.iter().map(|row| if index == 2 { row.display_name } else { row.units }));
+                        column.typed::<ByteArrayType>().write_batch(&values, Some(&levels), None)?
+                    },
+                    4 => {
+                        let tags: Vec<ByteArray> = rows.iter()
+                            .map(|row| ByteArray::from(serde_json::to_string(row.tags).unwrap_or_default().as_str()))
+                            .collect();
+                        column.typed::<ByteArrayType>().write_batch(&tags, None, None)?
+                    },
+                    5 => {
+                        let timestamps: Vec<i64> = rows.iter().map(|row| row.timestamp as i64).collect();
+                        column.typed::<Int64Type>().write_batch(&timestamps, None, None)?
+                    },
+                    _ => {
+                        let values: Vec<f64> = rows.iter().map(|row| row.value.as_f64().unwrap_or(f64::NAN)).collect();
+                        column.typed::<DoubleType>().write_batch(&values, None, None)?
+                    },
+                };
+
+                column.close()?;
+                index += 1;
+            }
+
+            row_group.close()?;
+            Ok(self.take())
+        }
+
+        /// Writes the footer of the file, returning the remaining bytes.
+        pub fn finish(mut self) -> Result<Vec<u8>> {
+            self.writer.finish()?;
+            Ok(self.take())
+        }
+    }
+}
+
+/// Exports the samples logged between `from` and `to`, in milliseconds since the unix epoch, by the data
+/// sources the requester may read, optionally filtered like the listing route. The export is streamed one
+/// data source at a time, so it is never held in memory as a whole.
+#[get("/export?<params..>")]
+pub async fn export<'r>(
+    state: &'r State<FlorustState>,
+    principal: Principal<'_>,
+    params: ExportParams
+) -> Result<(ContentType, ByteStream![Vec<u8> + 'r]), DataSourceError> {
+    state.authorize(&principal, Permission::Read, None)?;
+
+    if params.format == ExportFormat::Parquet && !cfg!(feature = "parquet") {
+        return Err(ManagerAndDataError::InvalidQuery("this server was built without Parquet support".to_string()).into());
+    }
+
+    let selector = list_selector(params.manager.as_deref(), params.source.as_deref(), &params.tag)?;
+    let listing = state.list_data_sources(&principal, &selector).await;
+    let (from, to, format) = (params.from.unwrap_or(0), params.to.unwrap_or_else(now_millis), params.format);
+
+    let content_type = match format {
+        ExportFormat::Csv => ContentType::CSV,
+        ExportFormat::Ndjson => ContentType::new("application", "x-ndjson"),
+        ExportFormat::Parquet => ContentType::new("application", "vnd.apache.parquet"),
+    };
+
+    let stream = ByteStream! {
+        if format == ExportFormat::Csv {
+            yield encode_csv(&[], true);
+        }
+
+        #[cfg(feature = "parquet")]
+        let mut parquet = match format {
+            ExportFormat::Parquet => match parquet_export::ParquetEncoder::new() {
+                Ok(encoder) => Some(encoder),
+                Err(err) => {
+                    warn!("Failed to start Parquet export: {}", err);
+                    return;
+                },
+            },
+            _ => None,
+        };
+
+        for listing in listing {
+            let Ok(manager) = state.get_manager_or_err(&listing.manager_id) else {
+                continue;
+            };
+
+            let samples = match manager.get_samples(&listing.data_source_id, from, to).await {
+                Ok(samples) => samples,
+                Err(err) => {
+                    warn!("Skipping {}/{} in export: {}", listing.manager_id, listing.data_source_id, err);
+                    continue;
+                },
+            };
+
+            let source = ExportedSource {
+                manager_id: listing.manager_id,
+                data_source_id: listing.data_source_id,
+                metadata: listing.metadata,
+                samples,
+            };
+
+            for chunk in source.samples.chunks(CHUNK_SIZE) {
+                let rows = source.rows(chunk);
+                match format {
+                    ExportFormat::Csv => yield encode_csv(&rows, false),
+                    ExportFormat::Ndjson => yield encode_ndjson(&rows),
+                    ExportFormat::Parquet => {
+                        #[cfg(feature = "parquet")]
+                        if let Some(encoder) = parquet.as_mut() {
+                            match encoder.row_group(&rows) {
+                                Ok(bytes) => yield bytes,
+                                Err(err) => {
+                                    warn!("Failed to encode Parquet export: {}", err);
+                                    return;
+                                },
+                            }
+                        }
+                    },
+                }
+            }
+        }
+
+        #[cfg(feature = "parquet")]
+        if let Some(encoder) = parquet {
+            match encoder.finish() {
+                Ok(bytes) => yield bytes,
+                Err(err) => warn!("Failed to finish Parquet export: {}", err),
+            }
+        }
+    };
+
+    Ok((content_type, stream))
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use log::{info, warn};
+use rocket::{fairing::AdHoc, serde::{Serialize, Deserialize}, tokio::{self, time::interval}};
+
+use crate::{FlorustState, manager_and_data::now_millis};
+
+fn default_check_interval_secs() -> u64 { 30 }
+
+fn default_tolerance() -> f64 { 1.5 }
+
+/// How data sources which stop uploading data are detected. A data source which declared an expected
+/// interval is stale once it has gone `tolerance` times that interval without uploading a sample.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct HeartbeatConfig {
+    /// Expected interval, in seconds, of data sources which don't declare one when registering. Data sources
+    /// without an expected interval are never stale.
+    #[serde(default)]
+    pub default_expected_interval_secs: Option<u64>,
+    /// How many expected intervals may pass without a sample before a data source is stale.
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// How often, in seconds, the monitor checks for stale data sources.
+    #[serde(default = "default_check_interval_secs")]
+    pub check_interval_secs: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            default_expected_interval_secs: None,
+            tolerance: default_tolerance(),
+            check_interval_secs: default_check_interval_secs(),
+        }
+    }
+}
+
+/// A registered data source's reporting status, as reported by the heartbeats route.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HeartbeatListing {
+    pub manager_id: String,
+    pub data_source_id: String,
+    /// When the data source last uploaded a sample, in milliseconds since the unix epoch.
+    pub last_seen: Option<u64>,
+    pub expected_interval_secs: Option<u64>,
+    pub stale: bool,
+}
+
+/// Fairing that spawns the heartbeat monitor, a background task periodically updating which data sources
+/// are stale, and logging the data sources that go stale or start reporting again.
+pub fn monitor() -> AdHoc {
+    AdHoc::on_liftoff("Heartbeat monitor", |rocket| Box::pin(async move {
+        let Some(state) = rocket.state::<FlorustState>() else {
+            return;
+        };
+
+        let config = state.heartbeat;
+        let managers_and_data = state.managers_and_data.clone();
+        info!("Starting heartbeat monitor, running every {} seconds", config.check_interval_secs);
+
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(config.check_interval_secs.max(1)));
+            loop {
+                interval.tick().await;
+
+                let now = now_millis();
+                for (manager_id, manager_and_data) in managers_and_data.iter() {
+                    for (data_source_id, stale) in manager_and_data.check_stale(now, config.tolerance).await {
+                        if stale {
+                            warn!("Data source {}/{} is stale, it stopped uploading data", manager_id, data_source_id);
+                        }
+                        else {
+                            info!("Data source {}/{} is uploading data again", manager_id, data_source_id);
+                        }
+                    }
+                }
+            }
+        });
+    }))
+}
@@ -0,0 +1,344 @@
+use chrono::DateTime;
+use log::info;
+use rocket::{FromForm, FromFormField, State, post, data::{Data, Limits, ToByteUnit}, serde::{Serialize, Deserialize, json::Value}};
+
+use crate::{FlorustState, acl::Permission, auth::Principal, data_source::{DataSourceError, OkResponder, state_op_to_responder}, manager_and_data::ManagerAndDataError};
+
+/// Size limit of imports in mebibytes, unless Rocket's `limits.import` says otherwise.
+const DEFAULT_LIMIT_MIB: u64 = 16;
+
+/// Most invalid rows listed in a report, the rest are only counted.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(FromFormField, Clone, Copy)]
+pub enum ImportFormat {
+    Csv,
+    Ndjson,
+}
+
+/// What happens to an imported sample whose timestamp is already used, either by a logged sample or by an
+/// earlier row of the import.
+#[derive(FromFormField, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Keeps the sample that was there first.
+    #[default]
+    Skip,
+    /// Keeps the imported sample, or the last row of the import.
+    Replace,
+    /// Rejects the import.
+    Error,
+}
+
+#[derive(FromForm)]
+pub struct ImportParams {
+    format: ImportFormat,
+    /// Whether rows hold data for the manager to decode, like uploaded data, rather than values.
+    decode: Option<bool>,
+    /// Whether to only validate the import, without writing anything.
+    dry_run: Option<bool>,
+    on_duplicate: Option<DuplicatePolicy>,
+}
+
+pub struct ImportOptions {
+    pub on_duplicate: DuplicatePolicy,
+    pub dry_run: bool,
+}
+
+/// The value of an imported row, as given in the import.
+pub enum ImportedValue {
+    /// A value, parsed as the type of the data source.
+    Value(String),
+    /// Data decoded by the manager, like uploaded data.
+    Data(Vec<u8>),
+}
+
+pub struct ImportedRow {
+    /// Line of the row in the import, starting at 1.
+    pub line: u64,
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub value: ImportedValue,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct RowError {
+    pub line: u64,
+    pub error: String,
+}
+
+/// Outcome of an import, or of a dry run, which reports what the import would do.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Number of rows in the import, valid or not.
+    pub rows: usize,
+    /// Samples written to the data source's history.
+    pub imported: usize,
+    /// Rows left out because their timestamp was already used.
+    pub skipped: usize,
+    /// Logged samples replaced by an imported sample with the same timestamp.
+    pub replaced: usize,
+    /// Samples, imported or not, dropped because they no longer fit in the data source's history.
+    pub evicted: usize,
+    /// Number of invalid rows. A single invalid row keeps the whole import from being written.
+    pub invalid_rows: usize,
+    /// The first invalid rows, along with what is wrong with them.
+    pub errors: Vec<RowError>,
+}
+
+/// Rows of an import, as parsed before the manager validates their values.
+struct ParsedImport {
+    rows: Vec<ImportedRow>,
+    errors: Vec<RowError>,
+    total: usize,
+}
+
+impl ParsedImport {
+    fn new() -> ParsedImport {
+        ParsedImport {
+            rows: Vec::new(),
+            errors: Vec::new(),
+            total: 0,
+        }
+    }
+
+    fn push(&mut self, line: u64, row: Result<(u64, ImportedValue), String>) {
+        self.total += 1;
+        match row {
+            Ok((timestamp, value)) => self.rows.push(ImportedRow { line, timestamp, value }),
+            Err(error) => self.errors.push(RowError { line, error }),
+        }
+    }
+}
+
+/// Parses a timestamp, either in milliseconds since the unix epoch, or as an RFC 3339 date.
+fn parse_timestamp(timestamp: &str) -> Result<u64, String> {
+    if let Ok(millis) = timestamp.parse() {
+        return Ok(millis);
+    }
+
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .and_then(|timestamp| u64::try_from(timestamp.timestamp_millis()).ok())
+        .ok_or_else(|| format!("{} is neither milliseconds since the unix epoch, nor an RFC 3339 date after it", timestamp))
+}
+
+/// Parses data to decode, given as a JSON array of bytes like uploaded data.
+fn parse_data(data: &str) -> Result<ImportedValue, String> {
+    serde_json::from_str(data)
+        .map(ImportedValue::Data)
+        .map_err(|_| format!("{} isn't a JSON array of bytes", data))
+}
+
+/// Parses CSV with a header row, taking the timestamp from the `timestamp` column, and the value from the
+/// `value` column, or the `data` column when decoding. Other columns are ignored, so exports can be imported.
+fn parse_csv(body: &[u8], decode: bool) -> ParsedImport {
+    let mut parsed = ParsedImport::new();
+    let mut reader = csv::Reader::from_reader(body);
+    let value_column = if decode { "data" } else { "value" };
+
+    let columns = reader.headers().map(|headers| (
+        headers.iter().position(|header| header == "timestamp"),
+        headers.iter().position(|header| header == value_column),
+    ));
+    let (timestamp_index, value_index) = match columns {
+        Ok((Some(timestamp_index), Some(value_index))) => (timestamp_index, value_index),
+        Ok(_) => {
+            parsed.errors.push(RowError { line: 1, error: format!("the header must have a timestamp and a {} column", value_column) });
+            return parsed;
+        },
+        Err(err) => {
+            parsed.errors.push(RowError { line: 1, error: err.to_string() });
+            return parsed;
+        },
+    };
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                parsed.push(err.position().map_or(0, |position| position.line()), Err(err.to_string()));
+                continue;
+            },
+        };
+
+        let line = record.position().map_or(0, |position| position.line());
+        let (timestamp, value) = (record.get(timestamp_index).unwrap_or_default(), record.get(value_index).unwrap_or_default());
+
+        parsed.push(line, parse_timestamp(timestamp).and_then(|timestamp| {
+            let value = if decode { parse_data(value)? } else { ImportedValue::Value(value.to_string()) };
+            Ok((timestamp, value))
+        }));
+    }
+
+    parsed
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct NdjsonRow {
+    timestamp: Value,
+    value: Option<Value>,
+    data: Option<Vec<u8>>,
+}
+
+fn parse_ndjson_row(line: &[u8], decode: bool) -> Result<(u64, ImportedValue), String> {
+    let row: NdjsonRow = serde_json::from_slice(line).map_err(|err| err.to_string())?;
+
+    let timestamp = match &row.timestamp {
+        Value::Number(timestamp) => timestamp.as_u64().ok_or_else(|| format!("{} isn't a valid timestamp", timestamp))?,
+        Value::String(timestamp) => parse_timestamp(timestamp)?,
+        _ => return Err("timestamp must be a number or a string".to_string()),
+    };
+
+    let value = match (decode, row.value, row.data) {
+        (true, _, Some(data)) => ImportedValue::Data(data),
+        (true, _, None) => return Err("data is missing".to_string()),
+        (false, Some(Value::Number(value)), _) => ImportedValue::Value(value.to_string()),
+        (false, Some(_), _) => return Err("value must be a number".to_string()),
+        (false, None, _) => return Err("value is missing".to_string()),
+    };
+
+    Ok((timestamp, value))
+}
+
+/// Parses a JSON object per line, with a `timestamp`, and a `value`, or `data` when decoding. Other fields
+/// are ignored, so exports can be imported.
+fn parse_ndjson(body: &[u8], decode: bool) -> ParsedImport {
+    let mut parsed = ParsedImport::new();
+
+    for (index, line) in body.split(|byte| *byte == b'\n').enumerate() {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+
+        parsed.push(index as u64 + 1, parse_ndjson_row(line, decode));
+    }
+
+    parsed
+}
+
+/// Imports samples with their own timestamps into the history of a data source, registered or not, like
+/// readings from another logger. Nothing is written unless every row is valid, and nothing at all on a
+/// dry run, which reports what the import would do instead.
+#[post("/import/<manager_id>/<data_source_id>?<params..>", data = "<data>")]
+pub async fn import(
+    state: &State<FlorustState>,
+    principal: Principal<'_>,
+    manager_id: String,
+    data_source_id: String,
+    params: ImportParams,
+    limits: &Limits,
+    data: Data<'_>
+) -> Result<OkResponder<ImportReport>, DataSourceError> {
+    state.authorize(&principal, Permission::Write, Some((&manager_id, &data_source_id)))?;
+    let manager = state.get_manager_or_err(&manager_id)?;
+
+    let limit = limits.get("import").unwrap_or(DEFAULT_LIMIT_MIB.mebibytes());
+    let body = data.open(limit).into_bytes().await
+        .map_err(|err| ManagerAndDataError::InvalidQuery(format!("failed to read the import: {}", err)))?;
+    if !body.is_complete() {
+        return Err(ManagerAndDataError::BodyTooLarge { max_size: limit.as_u64() }.into());
+    }
+
+    let decode = params.decode.unwrap_or(false);
+    let mut parsed = match params.format {
+        ImportFormat::Csv => parse_csv(&body, decode),
+        ImportFormat::Ndjson => parse_ndjson(&body, decode),
+    };
+
+    let dry_run = params.dry_run.unwrap_or(false);
+    let options = ImportOptions {
+        on_duplicate: params.on_duplicate.unwrap_or_default(),
+        dry_run: dry_run || !parsed.errors.is_empty(),
+    };
+    let upload = state.upload_guard().await?;
+    let mut report = manager.import(&data_source_id, parsed.rows, &options).await?;
+    drop(upload);
+
+    report.dry_run = dry_run;
+    report.rows = parsed.total;
+    report.errors.append(&mut parsed.errors);
+    report.errors.sort_by_key(|error| error.line);
+    report.invalid_rows = report.errors.len();
+    report.errors.truncate(MAX_REPORTED_ERRORS);
+
+    if report.invalid_rows > 0 {
+        return Err(ManagerAndDataError::InvalidImport(Box::new(report)).into());
+    }
+
+    if !dry_run {
+        info!("Imported {} samples into {}/{}", report.imported, manager_id, data_source_id);
+    }
+
+    state_op_to_responder(Ok(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportedValue, ParsedImport, parse_csv, parse_ndjson, parse_timestamp};
+
+    /// The rows of an import, as the line, timestamp and value or data.
+    fn rows(parsed: &ParsedImport) -> Vec<(u64, u64, String)> {
+        parsed.rows.iter()
+            .map(|row| {
+                let value = match &row.value {
+                    ImportedValue::Value(value) => value.clone(),
+                    ImportedValue::Data(data) => format!("{:?}", data),
+                };
+                (row.line, row.timestamp, value)
+            })
+            .collect()
+    }
+
+    fn error_lines(parsed: &ParsedImport) -> Vec<u64> {
+        parsed.errors.iter().map(|error| error.line).collect()
+    }
+
+    #[test]
+    fn timestamps_are_millis_or_rfc_3339() {
+        assert_eq!(parse_timestamp("1700000000000"), Ok(1_700_000_000_000));
+        assert_eq!(parse_timestamp("2023-11-14T22:13:20Z"), Ok(1_700_000_000_000));
+        assert_eq!(parse_timestamp("2023-11-14T23:13:20.5+01:00"), Ok(1_700_000_000_500));
+        assert!(parse_timestamp("1969-12-31T23:59:59Z").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    #[test]
+    fn csv_columns_are_found_by_name() {
+        let parsed = parse_csv(b"source,value,timestamp\npot,21.5,1000\npot,warm,later\npot,22,2000\n", false);
+
+        assert_eq!(parsed.total, 3);
+        assert_eq!(rows(&parsed), [(2, 1000, "21.5".to_string()), (4, 2000, "22".to_string())]);
+        assert_eq!(error_lines(&parsed), [3]);
+    }
+
+    #[test]
+    fn csv_data_is_decoded_from_the_data_column() {
+        let parsed = parse_csv(b"timestamp,data\n1000,\"[1,2]\"\n2000,oops\n", true);
+
+        assert_eq!(rows(&parsed), [(2, 1000, "[1, 2]".to_string())]);
+        assert_eq!(error_lines(&parsed), [3]);
+        assert_eq!(error_lines(&parse_csv(b"timestamp,value\n1000,1\n", true)), [1]);
+    }
+
+    #[test]
+    fn ndjson_rows_are_parsed_per_line() {
+        let body = b"{\"timestamp\": 1000, \"value\": 21.5, \"source\": \"pot\"}\n\n  \n{\"timestamp\": \"1970-01-01T00:00:02Z\", \"value\": 22}\n{\"timestamp\": 3000, \"value\": \"warm\"}\n{\"timestamp\": -1, \"value\": 1}\nnot json\n";
+        let parsed = parse_ndjson(body, false);
+
+        assert_eq!(parsed.total, 5);
+        assert_eq!(rows(&parsed), [(1, 1000, "21.5".to_string()), (4, 2000, "22".to_string())]);
+        assert_eq!(error_lines(&parsed), [5, 6, 7]);
+    }
+
+    #[test]
+    fn ndjson_data_is_decoded_from_the_data_field() {
+        let parsed = parse_ndjson(b"{\"timestamp\": 1000, \"data\": [1, 2]}\n{\"timestamp\": 2000, \"value\": 1}\n", true);
+
+        assert_eq!(rows(&parsed), [(1, 1000, "[1, 2]".to_string())]);
+        assert_eq!(error_lines(&parsed), [2]);
+    }
+}
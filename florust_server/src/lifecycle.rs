@@ -0,0 +1,186 @@
+use std::{collections::BTreeMap, io, path::{Path, PathBuf}, sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use florust_common::server::PluginHealth;
+use log::{error, info, warn};
+use rocket::{fairing::AdHoc, serde::{Serialize, Deserialize}, tokio::{self, fs, sync::{RwLock, RwLockReadGuard}, time::{interval, timeout}}};
+
+use crate::{FlorustState, observe_sample, polling, manager_and_data::{self, ManagerAndDataError}, snapshot::{RestoreSummary, Snapshot}};
+
+fn default_drain_timeout_secs() -> u64 { 10 }
+fn default_tick_interval_secs() -> u64 { 60 }
+
+/// How the server starts up and shuts down.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct LifecycleConfig {
+    /// Snapshot the server saves its data to when shutting down, and restores it from when launching.
+    pub snapshot_path: Option<PathBuf>,
+    /// Longest the server waits, in seconds, for uploads in progress to finish when shutting down.
+    #[serde(default = "default_drain_timeout_secs")]
+    pub drain_timeout_secs: u64,
+    /// How often, in seconds, managers are asked for the values they compute.
+    #[serde(default = "default_tick_interval_secs")]
+    pub tick_interval_secs: u64,
+}
+
+impl Default for LifecycleConfig {
+    fn default() -> Self {
+        LifecycleConfig {
+            snapshot_path: None,
+            drain_timeout_secs: default_drain_timeout_secs(),
+            tick_interval_secs: default_tick_interval_secs(),
+        }
+    }
+}
+
+/// Lets data be written until the server shuts down, and lets shutting down wait for the writes in progress.
+#[derive(Default)]
+pub struct UploadGate {
+    /// Held for reading while data is written.
+    lock: RwLock<()>,
+    closed: AtomicBool,
+}
+
+impl UploadGate {
+    /// Returns a guard to hold while writing data, failing if the server is shutting down.
+    pub async fn enter(&self) -> manager_and_data::Result<RwLockReadGuard<'_, ()>> {
+        let guard = self.lock.read().await;
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(ManagerAndDataError::ShuttingDown);
+        }
+
+        Ok(guard)
+    }
+
+    /// Refuses any further writes, and waits for the writes in progress to finish. Returns false if they
+    /// didn't finish within `drain_timeout`.
+    pub async fn close(&self, drain_timeout: Duration) -> bool {
+        self.closed.store(true, Ordering::SeqCst);
+        timeout(drain_timeout, self.lock.write()).await.is_ok()
+    }
+}
+
+/// Health of every manager, as reported by their plugins.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HealthReport {
+    /// Whether no manager is unhealthy.
+    pub healthy: bool,
+    pub managers: BTreeMap<String, PluginHealth>,
+}
+
+/// Writes a snapshot next to its destination first, so a failed write never leaves a partial snapshot.
+async fn save_snapshot(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, serde_json::to_vec(snapshot)?).await?;
+    fs::rename(&temporary, path).await
+}
+
+/// Restores the snapshot at `path`, if there is one.
+async fn restore_snapshot(state: &FlorustState, path: &Path) -> Result<Option<RestoreSummary>, String> {
+    let snapshot = match fs::read(path).await {
+        Ok(snapshot) => snapshot,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let snapshot = Snapshot::parse(&snapshot).map_err(|err| err.to_string())?;
+    state.restore(snapshot, true).await
+        .map(Some)
+        .map_err(|err| err.to_string())
+}
+
+/// Fairing restoring the configured snapshot before the server starts handling requests. The launch is
+/// aborted if the snapshot exists but can't be restored, as the server would otherwise overwrite it with an
+/// empty snapshot when shutting down.
+pub fn restore() -> AdHoc {
+    AdHoc::try_on_ignite("Snapshot restore", |rocket| Box::pin(async move {
+        let result = match rocket.state::<FlorustState>() {
+            Some(state) => match &state.lifecycle.snapshot_path {
+                Some(path) => restore_snapshot(state, path).await.map_err(|err| (path.clone(), err)),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        };
+
+        match result {
+            Ok(Some(summary)) => {
+                info!("Restored {} data sources, with {} samples, from the snapshot", summary.data_sources, summary.samples);
+                Ok(rocket)
+            },
+            Ok(None) => Ok(rocket),
+            Err((path, err)) => {
+                error!("Failed to restore the snapshot at {}: {}", path.display(), err);
+                Err(rocket)
+            },
+        }
+    }))
+}
+
+/// Fairing telling managers the server has launched, before polling the data sources they poll, and
+/// spawning a background task periodically logging the values they compute, like uploaded values.
+pub fn start() -> AdHoc {
+    AdHoc::on_liftoff("Plugin start", |rocket| Box::pin(async move {
+        let Some(state) = rocket.state::<FlorustState>() else {
+            return;
+        };
+
+        for manager in state.managers_and_data.values() {
+            manager.start().await;
+        }
+
+        polling::spawn_pollers(state).await;
+
+        let tick_interval = state.lifecycle.tick_interval_secs.max(1);
+        let background = state.background();
+        info!("Starting plugin ticker, running every {} seconds", tick_interval);
+
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(tick_interval));
+            loop {
+                interval.tick().await;
+
+                let Ok(_upload) = background.uploads.enter().await else {
+                    return;
+                };
+
+                for (manager_id, manager) in background.managers_and_data.iter() {
+                    for (data_source_id, logged) in manager.tick().await {
+                        match logged {
+                            Ok(sample) => observe_sample(&background.alerts, &background.automations, manager, &data_source_id, sample).await,
+                            Err(err) => warn!("Failed to log the value computed for {}/{}: {}", manager_id, data_source_id, err),
+                        }
+                    }
+                }
+            }
+        });
+    }))
+}
+
+/// Fairing shutting the server down in order: uploads are refused, and those in progress are given time to
+/// finish, before the snapshot is saved, if one is configured, and managers are told the server is shutting
+/// down.
+pub fn shutdown() -> AdHoc {
+    AdHoc::on_shutdown("Graceful shutdown", |rocket| Box::pin(async move {
+        let Some(state) = rocket.state::<FlorustState>() else {
+            return;
+        };
+
+        info!("Shutting down, no longer accepting uploads");
+        let drain_timeout = state.lifecycle.drain_timeout_secs;
+        if !state.uploads.close(Duration::from_secs(drain_timeout)).await {
+            warn!("Uploads in progress didn't finish within {} seconds, shutting down regardless", drain_timeout);
+        }
+
+        if let Some(path) = &state.lifecycle.snapshot_path {
+            match save_snapshot(path, &state.snapshot().await).await {
+                Ok(()) => info!("Saved snapshot to {}", path.display()),
+                Err(err) => error!("Failed to save snapshot to {}: {}", path.display(), err),
+            }
+        }
+
+        for manager in state.managers_and_data.values() {
+            manager.shutdown().await;
+        }
+    }))
+}
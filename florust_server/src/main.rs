@@ -1,16 +1,59 @@
+mod acl;
+mod alerting;
+mod alerts;
+mod aggregate;
+mod admin;
+mod auth;
+mod automation;
+mod automations;
 mod circular_vec;
+mod command_queue;
+mod commands;
+mod config;
 mod data_source;
+mod events;
+mod export;
+mod heartbeat;
+mod import;
+mod lifecycle;
 mod manager_and_data;
+mod notifications;
+mod polling;
+mod query;
+mod rate_limit;
+mod retention;
+mod rollup;
+mod snapshot;
+mod units;
 #[cfg(any(feature = "iinteger_default_plugin", feature = "uinteger_default_plugin", feature = "float_default_plugin"))]
 mod default_plugins;
+#[cfg(feature = "sysfs_plugin")]
+mod sysfs_plugin;
 
+use acl::{Acl, Permission};
+use alerting::AlertEngine;
+use aggregate::{AggregateBucket, Aggregation};
+use auth::{AuthError, Principal, TokenStore};
+use automation::AutomationEngine;
+use command_queue::{Command, CommandAck, CommandsConfig, NewCommand};
+use config::FlorustConfig;
+use events::{EventBus, ServerEvent};
+use heartbeat::{HeartbeatConfig, HeartbeatListing};
+use lifecycle::{HealthReport, LifecycleConfig, UploadGate};
 use log::{info, warn};
-use manager_and_data::{ManagerAndDataError, DataType, IIntegerManagerAndData, UIntegerManagerAndData, FloatManagerAndData};
-use rocket::{launch, routes, serde::{Serialize, Deserialize}};
+use query::{Buckets, QuerySeries, Selector};
+use manager_and_data::{ManagerAndDataError, DataType, now_millis, DataSourceInfo, DataSourceListing, Sample, Series, IIntegerManagerAndData, UIntegerManagerAndData, FloatManagerAndData};
+use notifications::Notifier;
+use polling::PollingConfig;
+use rate_limit::{LimitExceeded, RateLimiter};
+use retention::RetentionConfig;
+use snapshot::{ManagerSnapshot, RestoreSummary, SNAPSHOT_VERSION, Snapshot};
+use rocket::{catchers, launch, routes, data::{ByteUnit, Data, ToByteUnit}, serde::{Serialize, Deserialize}, tokio::{sync::RwLockReadGuard, time::{Instant, timeout_at}}};
 use toml::Table;
-use std::{collections::HashMap, sync::Arc, fs::{read_dir, read_to_string}};
+use units::Conversion;
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::Arc, fs::{read_dir, read_to_string}, net::IpAddr, time::Duration};
 
-use florust_common::server::{FlorustServerPluginError, CreateIIntegerDataSourceManager, CreateUIntegerDataSourceManager, CreateFloatDataSourceManager};
+use florust_common::{DataSourceMetadata, server::{FlorustServerPluginError, PluginHealth, CreateIIntegerDataSourceManager, CreateUIntegerDataSourceManager, CreateFloatDataSourceManager}};
 
 #[cfg(feature = "iinteger_default_plugin")]
 use default_plugins::DefaultIIntegerDataManager;
@@ -21,6 +64,9 @@ use default_plugins::DefaultUIntegerDataManager;
 #[cfg(feature = "float_default_plugin")]
 use default_plugins::DefaultFloatDataManager;
 
+#[cfg(feature = "sysfs_plugin")]
+use sysfs_plugin::SysfsDataManager;
+
 type BoxedManagerAndData = Box<dyn manager_and_data::ManagerAndData>;
 
 pub struct FlorustState {
@@ -30,6 +76,63 @@ pub struct FlorustState {
             BoxedManagerAndData,
         >,
     >,
+    tokens: TokenStore,
+    acl: Acl,
+    rate_limiter: RateLimiter,
+    retention: RetentionConfig,
+    heartbeat: HeartbeatConfig,
+    commands: CommandsConfig,
+    alerts: Arc<AlertEngine>,
+    automations: Arc<AutomationEngine>,
+    lifecycle: LifecycleConfig,
+    polling: PollingConfig,
+    uploads: Arc<UploadGate>,
+}
+
+/// The parts of the [`FlorustState`] background tasks need to log values like uploaded data.
+#[derive(Clone)]
+pub struct BackgroundState {
+    managers_and_data: Arc<HashMap<&'static str, BoxedManagerAndData>>,
+    alerts: Arc<AlertEngine>,
+    automations: Arc<AutomationEngine>,
+    uploads: Arc<UploadGate>,
+}
+
+/// Feeds a sample logged for a data source to the alert and automation rules watching the data source. The
+/// sample is already stored, so failing to evaluate the rules is only logged.
+async fn observe_sample(
+    alerts: &AlertEngine,
+    automations: &Arc<AutomationEngine>,
+    manager: &BoxedManagerAndData,
+    data_source_id: &str,
+    sample: Sample<DataType>
+) {
+    let manager_id = manager.manager_id();
+    let sample = (sample.timestamp, sample.value.as_f64());
+    let alerts_previous = alerts.record_sample(manager_id, data_source_id, sample.0, sample.1).await;
+    let automations_previous = automations.record_sample(manager_id, data_source_id, sample.0, sample.1).await;
+
+    let (alert_rules, automation_rules) = (alerts.has_rules().await, automations.has_rules().await);
+    if !alert_rules && !automation_rules {
+        return;
+    }
+
+    let metadata = match manager.info(data_source_id).await {
+        Ok(info) => info.metadata,
+        Err(err) => {
+            warn!("Couldn't evaluate the rules watching {}/{}: {}", manager_id, data_source_id, err);
+            return;
+        },
+    };
+
+    if alert_rules {
+        alerts.observe(manager_id, data_source_id, &metadata, sample, alerts_previous).await;
+    }
+
+    if automation_rules {
+        let triggered = automations.observe(manager_id, data_source_id, &metadata, sample, automations_previous).await;
+        automations.spawn(triggered);
+    }
 }
 
 impl FlorustState {
@@ -37,6 +140,30 @@ impl FlorustState {
         self.managers_and_data.contains_key(manager_id)
     }
 
+    /// Checks whether a principal has a permission, either over a specific data source or in general.
+    pub fn authorize(&self, principal: &Principal<'_>, permission: Permission, source: Option<(&str, &str)>) -> Result<(), AuthError> {
+        principal.authorize(&self.acl, permission, source)
+    }
+
+    /// Checks whether a principal may register a data source, which requires the write permission over it. If
+    /// open registration is enabled in the ACL config, anyone may register a data source which doesn't exist
+    /// yet, but registering an existing one again, which issues a new token for it, still requires the write
+    /// permission.
+    pub async fn authorize_registration(&self, principal: &Principal<'_>, manager_id: &str, data_source_id: &str) -> Result<(), AuthError> {
+        if self.acl.open_registration() {
+            let exists = match self.managers_and_data.get(manager_id) {
+                Some(manager) => manager.info(data_source_id).await.is_ok(),
+                None => false,
+            };
+
+            if !exists {
+                return Ok(());
+            }
+        }
+
+        self.authorize(principal, Permission::Write, Some((manager_id, data_source_id)))
+    }
+
     pub fn get_manager_or_err(&self, manager_id: &str) -> manager_and_data::Result<&BoxedManagerAndData> {
         self.managers_and_data
             .get(manager_id)
@@ -48,43 +175,435 @@ impl FlorustState {
             )
     }
 
-    pub async fn register_data_source(&self, manager_id: &str, data_source_id: String, data: Option<&[u8]>) -> manager_and_data::Result<()> {
+    pub fn background(&self) -> BackgroundState {
+        BackgroundState {
+            managers_and_data: Arc::clone(&self.managers_and_data),
+            alerts: Arc::clone(&self.alerts),
+            automations: Arc::clone(&self.automations),
+            uploads: Arc::clone(&self.uploads),
+        }
+    }
+
+    /// Returns a guard to hold while writing uploaded data, failing if the server is shutting down.
+    pub async fn upload_guard(&self) -> manager_and_data::Result<RwLockReadGuard<'_, ()>> {
+        self.uploads.enter().await
+    }
+
+    /// Returns the health of every manager.
+    pub async fn health(&self) -> HealthReport {
+        let mut managers = BTreeMap::new();
+        for (manager_id, manager) in self.managers_and_data.iter() {
+            managers.insert(manager_id.to_string(), manager.health().await);
+        }
+
+        HealthReport {
+            healthy: !managers.values().any(|health| matches!(health, PluginHealth::Unhealthy(_))),
+            managers,
+        }
+    }
+
+    /// Registers a data source, returning the token the data source must use for further requests. Data
+    /// sources which don't declare an expected interval get the configured default, if any.
+    pub async fn register_data_source(
+        &self,
+        manager_id: &str,
+        data_source_id: String,
+        data: Option<&[u8]>,
+        metadata: DataSourceMetadata,
+        expected_interval_secs: Option<u64>
+    ) -> manager_and_data::Result<String> {
+        let expected_interval_secs = expected_interval_secs.or(self.heartbeat.default_expected_interval_secs);
+
         if let Some(data) = data {
             self.get_manager_or_err(manager_id)?
-                .register_with_data(data_source_id, data).await
+                .register_with_data(data_source_id.clone(), data, metadata, expected_interval_secs).await?;
         }
         else {
             self.get_manager_or_err(manager_id)?
-                .register(data_source_id).await
+                .register(data_source_id.clone(), metadata, expected_interval_secs).await?;
         }
+
+        Ok(self.tokens.issue(manager_id, &data_source_id).await)
     }
 
     pub async fn deregister_data_source(&self, manager_id: &str, data_source_id: &str, data: Option<&[u8]>) -> manager_and_data::Result<()> {
         if let Some(data) = data {
             self.get_manager_or_err(manager_id)?
-                .deregister_with_data(data_source_id, data).await
+                .deregister_with_data(data_source_id, data).await?;
         }
         else {
             self.get_manager_or_err(manager_id)?
-                .deregister(data_source_id).await
+                .deregister(data_source_id).await?;
         }
+
+        self.tokens.revoke(manager_id, data_source_id).await;
+        Ok(())
     }
 
-    pub async fn update_data(&self, manager_id: &str, data_source_id: &str, data: &[u8]) -> manager_and_data::Result<()> {
-        self.managers_and_data
+    /// Issues a new token for a registered data source, invalidating the previous one.
+    pub async fn rotate_token(&self, manager_id: &str, data_source_id: &str) -> manager_and_data::Result<String> {
+        if !self.get_manager_or_err(manager_id)?.is_registered(data_source_id).await {
+            return Err(
+                ManagerAndDataError::DataSourceManager(
+                    FlorustServerPluginError::DataSourceDoesntExist(data_source_id.to_string())
+                )
+            );
+        }
+
+        Ok(self.tokens.issue(manager_id, data_source_id).await)
+    }
+
+    /// Passes data uploaded by a client to the data source's manager, if the upload is within the configured
+    /// limits. Uploads exceeding the limits are counted as dropped samples of the data source.
+    pub async fn update_data(&self, manager_id: &str, data_source_id: &str, data: &[u8], client: Option<IpAddr>) -> manager_and_data::Result<()> {
+        let _upload = self.upload_guard().await?;
+        let manager = self.managers_and_data
             .get(manager_id)
             .ok_or(
                 ManagerAndDataError::DataSourceManager(
                         FlorustServerPluginError::DataSourceManagerDoesntExist(manager_id.to_string()
                     )
                 )
-            )?
-            .update_data(data_source_id, data).await
+            )?;
+
+        if let Err(exceeded) = self.rate_limiter.check(manager_id, data_source_id, client, data.len()).await {
+            manager.record_dropped(data_source_id).await;
+
+            return Err(match exceeded {
+                LimitExceeded::Rate { retry_after } => ManagerAndDataError::RateLimited {
+                    retry_after: retry_after.as_secs_f64().ceil().max(1.0) as u64
+                },
+                LimitExceeded::PayloadSize { max_size } => ManagerAndDataError::PayloadTooLarge {
+                    size: data.len(),
+                    max_size
+                },
+            });
+        }
+
+        let sample = manager.update_data(data_source_id, data).await?;
+        observe_sample(&self.alerts, &self.automations, manager, data_source_id, sample).await;
+
+        Ok(())
     }
 
-    pub async fn get_data(&self, manager_id: &str, data_source_id: &str, index: usize) -> manager_and_data::Result<DataType> {
+    /// Reads the body of an upload, up to `limit` bytes, or fewer if the data source's maximum payload size
+    /// means a larger body can't hold an acceptable upload, so oversized uploads are rejected before being
+    /// parsed. Rejected uploads are counted as dropped samples of the data source.
+    pub async fn read_upload(&self, manager_id: &str, data_source_id: &str, data: Data<'_>, limit: ByteUnit) -> manager_and_data::Result<Vec<u8>> {
+        let limit = match self.rate_limiter.max_body_size(manager_id, data_source_id) {
+            Some(max_body_size) => limit.min((max_body_size as u64).bytes()),
+            None => limit,
+        };
+
+        let body = data.open(limit).into_bytes().await
+            .map_err(|err| ManagerAndDataError::InvalidQuery(format!("failed to read the upload: {}", err)))?;
+        if !body.is_complete() {
+            if let Some(manager) = self.managers_and_data.get(manager_id) {
+                manager.record_dropped(data_source_id).await;
+            }
+
+            return Err(ManagerAndDataError::BodyTooLarge { max_size: limit.as_u64() });
+        }
+
+        Ok(body.into_inner())
+    }
+
+    /// Finds the conversion from a data source's units to `unit`, if a unit was requested. Fails if the
+    /// data source has no units, or they can't be converted to the requested unit.
+    async fn conversion(&self, manager_id: &str, data_source_id: &str, unit: Option<&str>) -> manager_and_data::Result<Option<Conversion>> {
+        let Some(unit) = unit else {
+            return Ok(None);
+        };
+
+        let metadata = self.get_manager_or_err(manager_id)?
+            .info(data_source_id).await?
+            .metadata;
+        let units = metadata.units.ok_or_else(|| {
+            ManagerAndDataError::InvalidUnit(format!("data source {} has no units to convert from", data_source_id))
+        })?;
+
+        Conversion::between(&units, unit).map(Some)
+    }
+
+    /// Returns a sample of a data source, converted to `unit` if one is given.
+    pub async fn get_data(&self, manager_id: &str, data_source_id: &str, index: usize, unit: Option<&str>) -> manager_and_data::Result<DataType> {
+        let conversion = self.conversion(manager_id, data_source_id, unit).await?;
+        let value = self.get_manager_or_err(manager_id)?
+            .get_data(data_source_id, index).await?;
+
+        Ok(match conversion {
+            Some(conversion) => value.convert(&conversion),
+            None => value,
+        })
+    }
+
+    /// Removes all logged data of a data source, see [`ManagerAndData::purge`](manager_and_data::ManagerAndData::purge).
+    pub async fn purge_data(&self, manager_id: &str, data_source_id: &str) -> manager_and_data::Result<()> {
         self.get_manager_or_err(manager_id)?
-            .get_data(data_source_id, index).await
+            .purge(data_source_id).await
+    }
+
+    /// Returns the data a data source logged between `from` and `to`, converted to `unit` if one is given.
+    pub async fn get_range(&self, manager_id: &str, data_source_id: &str, from: u64, to: u64, unit: Option<&str>) -> manager_and_data::Result<Series> {
+        let conversion = self.conversion(manager_id, data_source_id, unit).await?;
+        let series = self.get_manager_or_err(manager_id)?
+            .get_range(data_source_id, from, to).await?;
+
+        Ok(match conversion {
+            Some(conversion) => series.convert(&conversion),
+            None => series,
+        })
+    }
+
+    /// Computes aggregations over the data a data source logged between `from` and `to`, optionally split
+    /// into buckets of `bucket_width` milliseconds, falling back to its rollups for ranges its samples no
+    /// longer cover. `from` defaults to the oldest data of the data source, see [`aggregate::default_from`].
+    /// Data is converted to `unit` before being aggregated if one is given.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn aggregate(
+        &self,
+        manager_id: &str,
+        data_source_id: &str,
+        aggregations: &[(String, Aggregation)],
+        from: Option<u64>,
+        to: u64,
+        bucket_width: Option<u64>,
+        unit: Option<&str>
+    ) -> manager_and_data::Result<Vec<AggregateBucket>> {
+        let conversion = self.conversion(manager_id, data_source_id, unit).await?;
+        let manager = self.get_manager_or_err(manager_id)?;
+        let from = match from {
+            Some(from) => from,
+            None => aggregate::default_from(manager.oldest_timestamp(data_source_id).await?, to, bucket_width),
+        };
+
+        let series = manager.get_full_range(data_source_id, from, to).await?;
+        let series = match conversion {
+            Some(conversion) => series.convert(&conversion),
+            None => series,
+        };
+
+        aggregate::aggregate(&series, aggregations, from, to, bucket_width)
+    }
+
+    /// Evaluates a query over the data logged between `from` and `to`, split into buckets of `step`
+    /// milliseconds. Data sources the principal may not read are left out of the query's selections.
+    pub async fn query(
+        &self,
+        principal: &Principal<'_>,
+        query: &str,
+        from: u64,
+        to: u64,
+        step: u64
+    ) -> manager_and_data::Result<Vec<QuerySeries>> {
+        let query = query::parse(query)?;
+        let buckets = Buckets::new(from, to, step)?;
+
+        let mut selected = Vec::new();
+        for selector in query.selectors() {
+            let mut series = Vec::new();
+
+            for (manager_id, manager) in self.managers_and_data.iter() {
+                if !selector.matches_manager(manager_id) {
+                    continue;
+                }
+
+                for (data_source_id, metadata) in manager.data_sources().await {
+                    let readable = self.authorize(principal, Permission::Read, Some((manager_id, &data_source_id))).is_ok();
+                    if !selector.matches_source(&data_source_id) || !selector.matches_tags(&metadata.tags) || !readable {
+                        continue;
+                    }
+
+                    // The data source may have been removed since listing it, in which case it is skipped.
+                    let Ok(samples) = manager.get_samples(&data_source_id, from, to).await else {
+                        continue;
+                    };
+                    let samples: Vec<Sample<f64>> = samples.into_iter()
+                        .map(|sample| Sample { timestamp: sample.timestamp, value: sample.value.as_f64() })
+                        .collect();
+
+                    series.push(buckets.series(manager_id, &data_source_id, &samples));
+                }
+            }
+
+            selected.push(series);
+        }
+
+        query.evaluate(&buckets, selected)
+    }
+
+    /// Lists the data sources matching a selector which the principal may read.
+    pub async fn list_data_sources(&self, principal: &Principal<'_>, selector: &Selector) -> Vec<DataSourceListing> {
+        let mut listing = Vec::new();
+
+        for (manager_id, manager) in self.managers_and_data.iter() {
+            if !selector.matches_manager(manager_id) {
+                continue;
+            }
+
+            for (data_source_id, metadata) in manager.data_sources().await {
+                let readable = self.authorize(principal, Permission::Read, Some((manager_id, &data_source_id))).is_ok();
+                if selector.matches_source(&data_source_id) && selector.matches_tags(&metadata.tags) && readable {
+                    listing.push(DataSourceListing {
+                        manager_id: manager_id.to_string(),
+                        data_source_id,
+                        metadata,
+                    });
+                }
+            }
+        }
+
+        listing.sort_by(|a, b| (&a.manager_id, &a.data_source_id).cmp(&(&b.manager_id, &b.data_source_id)));
+        listing
+    }
+
+    /// Returns the reporting status of the registered data sources the principal may read, optionally only
+    /// those which are, or aren't, stale.
+    pub async fn heartbeats(&self, principal: &Principal<'_>, selector: &Selector, stale: Option<bool>) -> Vec<HeartbeatListing> {
+        let mut heartbeats = Vec::new();
+
+        for listing in self.list_data_sources(principal, selector).await {
+            let Ok(info) = self.data_source_info(&listing.manager_id, &listing.data_source_id).await else {
+                continue;
+            };
+
+            let wanted = match stale {
+                Some(stale) => stale == info.stale,
+                None => true,
+            };
+            if info.registered && wanted {
+                heartbeats.push(HeartbeatListing {
+                    manager_id: listing.manager_id,
+                    data_source_id: listing.data_source_id,
+                    last_seen: info.last_seen,
+                    expected_interval_secs: info.expected_interval_secs,
+                    stale: info.stale,
+                });
+            }
+        }
+
+        heartbeats
+    }
+
+    pub async fn enqueue_command(&self, manager_id: &str, data_source_id: &str, command: NewCommand) -> manager_and_data::Result<Command> {
+        self.get_manager_or_err(manager_id)?
+            .enqueue_command(data_source_id, command, &self.commands).await
+    }
+
+    /// Returns the commands a data source should carry out. If there are none, waits up to `wait_secs`
+    /// seconds, capped by the configured maximum, for a command to be enqueued.
+    pub async fn poll_commands(&self, manager_id: &str, data_source_id: &str, wait_secs: u64) -> manager_and_data::Result<Vec<Command>> {
+        let manager = self.get_manager_or_err(manager_id)?;
+        let notify = manager.command_notify(data_source_id).await?;
+        let deadline = Instant::now() + Duration::from_secs(wait_secs.min(self.commands.max_wait_secs));
+
+        loop {
+            // Listening before taking the commands ensures a command enqueued in between isn't missed.
+            let enqueued = notify.notified();
+
+            let commands = manager.take_commands(data_source_id, &self.commands).await?;
+            if !commands.is_empty() || timeout_at(deadline, enqueued).await.is_err() {
+                return Ok(commands);
+            }
+        }
+    }
+
+    pub async fn acknowledge_command(
+        &self,
+        manager_id: &str,
+        data_source_id: &str,
+        command_id: u64,
+        ack: CommandAck
+    ) -> manager_and_data::Result<Command> {
+        self.get_manager_or_err(manager_id)?
+            .acknowledge_command(data_source_id, command_id, ack, &self.commands).await
+    }
+
+    pub async fn commands(&self, manager_id: &str, data_source_id: &str) -> manager_and_data::Result<Vec<Command>> {
+        self.get_manager_or_err(manager_id)?
+            .commands(data_source_id).await
+    }
+
+    /// Takes a snapshot of every manager's data sources, along with the tokens issued to them.
+    pub async fn snapshot(&self) -> Snapshot {
+        let mut managers = Vec::with_capacity(self.managers_and_data.len());
+        for (manager_id, manager) in self.managers_and_data.iter() {
+            managers.push(ManagerSnapshot {
+                manager_id: manager_id.to_string(),
+                data_sources: manager.snapshot().await,
+            });
+        }
+
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            created_at: now_millis(),
+            managers,
+            tokens: self.tokens.snapshot().await,
+        }
+    }
+
+    /// Restores a snapshot, once every manager it holds data sources for has checked that they can be
+    /// restored, so a snapshot is either restored entirely or not at all. If a manager fails to restore its
+    /// data sources anyway, the managers restored before it are put back as they were. Data sources which
+    /// already exist are only replaced if `replace` is true.
+    pub async fn restore(&self, snapshot: Snapshot, replace: bool) -> manager_and_data::Result<RestoreSummary> {
+        let _upload = self.upload_guard().await?;
+
+        for manager_snapshot in &snapshot.managers {
+            self.get_manager_or_err(&manager_snapshot.manager_id)?
+                .check_restore(&manager_snapshot.data_sources, replace).await?;
+        }
+
+        let mut summary = RestoreSummary { data_sources: 0, samples: 0 };
+        let mut registered = HashSet::new();
+        let mut deregistered = Vec::new();
+        let mut restored = Vec::with_capacity(snapshot.managers.len());
+        for manager_snapshot in snapshot.managers {
+            for data_source in &manager_snapshot.data_sources {
+                summary.data_sources += 1;
+                summary.samples += data_source.samples.len();
+
+                if data_source.registered {
+                    registered.insert((manager_snapshot.manager_id.clone(), data_source.id.clone()));
+                }
+                else {
+                    deregistered.push((manager_snapshot.manager_id.clone(), data_source.id.clone()));
+                }
+            }
+
+            let manager = self.get_manager_or_err(&manager_snapshot.manager_id)?;
+            match manager.restore(manager_snapshot.data_sources, replace).await {
+                Ok(previous) => restored.push((manager, previous)),
+                Err(err) => {
+                    for (manager, previous) in restored.into_iter().rev() {
+                        manager.undo_restore(previous).await;
+                    }
+                    return Err(err);
+                },
+            }
+        }
+
+        // Tokens of data sources which were deregistered when the snapshot was taken no longer work.
+        for (manager_id, data_source_id) in deregistered {
+            self.tokens.revoke(&manager_id, &data_source_id).await;
+        }
+        for token in snapshot.tokens {
+            if registered.contains(&(token.manager_id.clone(), token.data_source_id.clone())) {
+                self.tokens.restore(token).await;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    pub async fn set_metadata(&self, manager_id: &str, data_source_id: &str, metadata: DataSourceMetadata) -> manager_and_data::Result<()> {
+        self.get_manager_or_err(manager_id)?
+            .set_metadata(data_source_id, metadata).await
+    }
+
+    pub async fn data_source_info(&self, manager_id: &str, data_source_id: &str) -> manager_and_data::Result<DataSourceInfo> {
+        self.get_manager_or_err(manager_id)?
+            .info(data_source_id).await
     }
 }
 
@@ -98,7 +617,8 @@ pub struct FlorustServerPluginConfig {
     #[serde(default = "default_max_data")]
     max_data: usize,
     data_type: String,
-    create_func: Option<String>
+    create_func: Option<String>,
+    unit: Option<String>
 }
 
 impl FlorustServerPluginConfig {
@@ -121,12 +641,26 @@ impl FlorustServerPluginConfig {
     pub fn create_func(&self) -> Option<&str> {
         self.create_func.as_deref()
     }
+
+    pub fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
 }
 
 #[launch]
 fn launch() -> _ {
+    let rocket = rocket::build();
+    let config = match FlorustConfig::from_figment(rocket.figment()) {
+        Ok(config) => config,
+        Err(err) => panic!("Failed to parse florust config: {}", err),
+    };
+
+    // The notifier subscribes to events before plugins are loaded, so it is told about plugins failing to load.
+    let events = EventBus::new();
+    let notifier = Notifier::new(config.notifications.clone(), &events);
+
     let mut managers = HashMap::new();
-    for plugin in load_plugins() {
+    for plugin in load_plugins(&config, &events) {
         if let Some(_) = managers.get(plugin.manager_id()) {
             warn!("Skipping plugin (id: {}) because a plugin with the same id already exists", plugin.manager_id());
             continue;
@@ -135,24 +669,87 @@ fn launch() -> _ {
         managers.insert(plugin.manager_id(), plugin);
     }
 
+    let managers = Arc::new(managers);
     let florust_state = FlorustState {
-        managers_and_data: Arc::new(managers),
+        automations: Arc::new(AutomationEngine::new(config.automations, config.commands, Arc::clone(&managers))),
+        managers_and_data: managers,
+        tokens: TokenStore::default(),
+        acl: Acl::new(config.acl, config.admin_tokens),
+        rate_limiter: RateLimiter::new(config.limits),
+        retention: config.retention,
+        heartbeat: config.heartbeat,
+        commands: config.commands,
+        alerts: Arc::new(AlertEngine::new(config.alerts)),
+        lifecycle: config.lifecycle,
+        polling: config.polling,
+        uploads: Arc::new(UploadGate::default()),
     };
 
-    rocket::build().manage(florust_state).mount(
+    rocket.manage(florust_state).mount(
         "/data_source",
         routes![
             data_source::register,
             data_source::unregister,
             data_source::json_upload_data,
             data_source::form_upload_data,
-            data_source::get_data
+            data_source::get_data,
+            data_source::get_range,
+            data_source::aggregate,
+            data_source::query,
+            data_source::list,
+            data_source::heartbeats,
+            export::export,
+            import::import,
+            data_source::set_metadata,
+            data_source::status
+        ],
+    ).mount(
+        "/admin",
+        routes![
+            admin::rotate_token,
+            admin::purge_data,
+            admin::snapshot,
+            admin::restore,
+            admin::health
+        ],
+    ).mount(
+        "/commands",
+        routes![
+            commands::enqueue,
+            commands::poll,
+            commands::acknowledge,
+            commands::history
+        ],
+    ).mount(
+        "/alerts",
+        routes![
+            alerts::list,
+            alerts::history,
+            alerts::rules,
+            alerts::set_rule,
+            alerts::remove_rule
         ],
-    )
+    ).mount(
+        "/automations",
+        routes![
+            automations::rules,
+            automations::set_rule,
+            automations::remove_rule,
+            automations::audit
+        ],
+    ).register("/", catchers![auth::unauthorized])
+    .attach(retention::reaper())
+    .attach(heartbeat::monitor())
+    .attach(alerting::checker())
+    .attach(notifications::notifier(notifier))
+    .attach(lifecycle::restore())
+    .attach(lifecycle::start())
+    .attach(lifecycle::shutdown())
 }
 
-fn load_plugins() -> Vec<BoxedManagerAndData> {
+fn load_plugins(config: &FlorustConfig, events: &EventBus) -> Vec<BoxedManagerAndData> {
     let mut plugins = Vec::new();
+    let rollup_config = config.rollups;
 
     // Load default plugins if they are enabled.
     #[cfg(feature = "iinteger_default_plugin")] {
@@ -160,7 +757,10 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
 
         let iinteger_manager = Box::new(IIntegerManagerAndData::new(
             Box::new(DefaultIIntegerDataManager{}) as _,
-            10
+            10,
+            rollup_config,
+            None,
+            events.clone()
         )) as BoxedManagerAndData;
         plugins.push(iinteger_manager);
     }
@@ -170,7 +770,10 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
 
         let uinteger_manager = Box::new(UIntegerManagerAndData::new(
             Box::new(DefaultUIntegerDataManager{}) as _,
-            10
+            10,
+            rollup_config,
+            None,
+            events.clone()
         ));
         plugins.push(uinteger_manager);
     }
@@ -180,11 +783,27 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
 
         let float_manager = Box::new(FloatManagerAndData::new(
             Box::new(DefaultFloatDataManager{}) as _,
-            10
+            10,
+            rollup_config,
+            None,
+            events.clone()
         ));
         plugins.push(float_manager);
     }
 
+    #[cfg(feature = "sysfs_plugin")] {
+        info!("Loading built-in plugin: {}", config.sysfs.manager_id);
+
+        let sysfs_manager = Box::new(FloatManagerAndData::new(
+            Box::new(SysfsDataManager::new(config.sysfs.clone())) as _,
+            10,
+            rollup_config,
+            None,
+            events.clone()
+        ));
+        plugins.push(sysfs_manager);
+    }
+
     info!("Checking for custom plugins");
     let custom_plugin_dirs = match read_dir("plugins/") {
         Ok(entries) => entries,
@@ -216,11 +835,15 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
         };
 
         let plugin_dir_path = plugin_dir.path();
+        let failed = |error: String| events.emit(ServerEvent::PluginLoadFailed {
+            plugin: plugin_dir_path.to_string_lossy().to_string(),
+            error
+        });
 
         // Path pointing to plugin.toml file
         let plugin_config_path = {
             let mut tmp = plugin_dir_path.clone();
-            tmp.push("/plugin.toml");
+            tmp.push("plugin.toml");
             tmp
         };
 
@@ -228,6 +851,7 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
             Ok(str) => str,
             Err(err) => {
                 warn!("Failed to open plugin.toml inside of dir found in plugins dir: {}", err);
+                failed(format!("failed to open plugin.toml: {}", err));
                 continue;
             }
         };
@@ -236,6 +860,7 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
             Ok(toml) => toml,
             Err(err) => {
                 warn!("Failed to parse plugin.toml: {}", err);
+                failed(format!("failed to parse plugin.toml: {}", err));
                 continue;
             }
         };
@@ -246,6 +871,7 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
                 "Plugin config doesn't contain mandated plugin section, file: {}",
                 plugin_config_path.to_string_lossy()
             );
+            failed("plugin.toml doesn't contain a plugin section".to_string());
             continue;
         };
 
@@ -261,11 +887,12 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
                 "Plugin config contains key for \"plugin\", but it isn't a table, file: {}",
                 plugin_config_path.to_string_lossy()
             );
+            failed("plugin section of plugin.toml isn't a table".to_string());
             continue;
         };
 
         // Parse the config
-        let config = match toml::from_str::<FlorustServerPluginConfig>(&config_raw.to_string()) {
+        let config = match config_raw.try_into::<FlorustServerPluginConfig>() {
             Ok(c) => c,
             Err(err) => {
                 warn!(
@@ -273,6 +900,7 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
                     plugin_config_path.to_string_lossy(),
                     err
                 );
+                failed(format!("failed to parse plugin section of plugin.toml: {}", err));
                 continue;
             },
         };
@@ -295,11 +923,12 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
                         plugin_config_path.to_string_lossy(),
                         err
                     );
+                    failed(format!("failed to open library: {}", err));
                     continue;
                 },
             };
 
-            match config.data_type() {
+            let manager_and_data = match config.data_type() {
                 "i64" => {
                     let create_func_name = if let Some(name) = config.create_func() {
                         name
@@ -317,16 +946,18 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
                                 plugin_dir_path.to_string_lossy(),
                                 err
                             );
+                            failed(format!("failed to retrieve create function {}: {}", create_func_name, err));
                             continue;
                         },
                     };
 
                     match *create_func(Box::new(toml)) {
                         Ok(m) => Box::new(
-                            IIntegerManagerAndData::new(m, config.max_data())
+                            IIntegerManagerAndData::new(m, config.max_data(), rollup_config, config.unit().map(str::to_string), events.clone())
                         ) as BoxedManagerAndData,
                         Err(err) => {
                             warn!("Failed to create manager for plugin (path: {}) with error: {}", plugin_dir_path.to_string_lossy(), err);
+                            failed(format!("failed to create manager: {}", err));
                             continue;
                         },
                     }
@@ -348,16 +979,18 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
                                 plugin_dir_path.to_string_lossy(),
                                 err
                             );
+                            failed(format!("failed to retrieve create function {}: {}", create_func_name, err));
                             continue;
                         },
                     };
 
                     match *create_func(Box::new(toml)) {
                         Ok(m) => Box::new(
-                            UIntegerManagerAndData::new(m, config.max_data())
+                            UIntegerManagerAndData::new(m, config.max_data(), rollup_config, config.unit().map(str::to_string), events.clone())
                         ),
                         Err(err) => {
                             warn!("Failed to create manager for plugin (path: {}) with error: {}", plugin_dir_path.to_string_lossy(), err);
+                            failed(format!("failed to create manager: {}", err));
                             continue;
                         },
                     }
@@ -379,22 +1012,32 @@ fn load_plugins() -> Vec<BoxedManagerAndData> {
                                 plugin_dir_path.to_string_lossy(),
                                 err
                             );
+                            failed(format!("failed to retrieve create function {}: {}", create_func_name, err));
                             continue;
                         },
                     };
 
                     match *create_func(Box::new(toml)) {
                         Ok(m) => Box::new(
-                            FloatManagerAndData::new(m, config.max_data())
+                            FloatManagerAndData::new(m, config.max_data(), rollup_config, config.unit().map(str::to_string), events.clone())
                         ),
                         Err(err) => {
                             warn!("Failed to create manager for plugin (path: {}) with error: {}", plugin_dir_path.to_string_lossy(), err);
+                            failed(format!("failed to create manager: {}", err));
                             continue;
                         },
                     }
                 },
-                _ => unreachable!("data type config value no longer valid despite being valid a bit ago")
-            }
+                data_type => {
+                    warn!("Plugin (path: {}) has an unknown data type: {}", plugin_dir_path.to_string_lossy(), data_type);
+                    failed(format!("unknown data type: {}", data_type));
+                    continue;
+                }
+            };
+
+            // The manager's code lives in the library, which must stay loaded for as long as the server runs.
+            std::mem::forget(lib);
+            manager_and_data
         };
 
         plugins.push(manager_and_data);
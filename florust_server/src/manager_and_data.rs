@@ -1,15 +1,33 @@
-use std::{collections::HashMap, result};
+use std::{collections::{HashMap, HashSet}, result, sync::Arc, time::{SystemTime, UNIX_EPOCH}};
 
-use florust_common::server::{IIntegerDataSourceManager, UIntegerDataSourceManager, FloatDataSourceManager, FlorustServerPluginError};
-use rocket::{async_trait, tokio::sync::RwLock, serde::{Serialize, Deserialize}};
+use florust_common::{DataSourceMetadata, server::{DataSourceManagerError, IIntegerDataSourceManager, PluginHealth, PolledSource, UIntegerDataSourceManager, FloatDataSourceManager, FlorustServerPluginError}};
+use log::warn;
+use rocket::{async_trait, tokio::sync::{Notify, RwLock}, serde::{Serialize, Deserialize}};
 use thiserror::Error;
 
-use crate::circular_vec::CircularVec;
+use crate::{circular_vec::CircularVec, command_queue::{Command, CommandAck, CommandQueue, CommandsConfig, NewCommand}, events::{EventBus, ServerEvent}, import::{DuplicatePolicy, ImportOptions, ImportReport, ImportedRow, ImportedValue, RowError}, units::Conversion, retention::RetentionConfig, rollup::{Resolution, Rollup, RollupConfig, RollupTiers}, snapshot::DataSourceSnapshot};
+
+/// Returns the current time as milliseconds since the unix epoch, the format of all timestamps in Florust.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A value logged for a data source, along with the time it was logged at.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct Sample<T> {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub value: T,
+}
 
 enum DataSourceStatus<T> where T: Send + Sync {
-    Registered(CircularVec<T>),
+    Registered(CircularVec<Sample<T>>),
     RegisteredNoData,
-    Deregistered(CircularVec<T>)
+    Deregistered(CircularVec<Sample<T>>)
 }
 
 impl<T> DataSourceStatus<T> where T: Send + Sync {
@@ -20,15 +38,370 @@ impl<T> DataSourceStatus<T> where T: Send + Sync {
         }
     }
 
-    fn data_or_err<O: FnOnce() -> ManagerAndDataError>(&self, op: O) -> Result<&CircularVec<T>> {
+    fn data_or_err<O: FnOnce() -> ManagerAndDataError>(&self, op: O) -> Result<&CircularVec<Sample<T>>> {
         match self {
             Self::Registered(data) | Self::Deregistered(data) => Ok(data),
             Self::RegisteredNoData => Err(op())
         }
     }
+
+    fn logged_samples(&self) -> usize {
+        match self {
+            Self::Registered(data) | Self::Deregistered(data) => data.len(),
+            Self::RegisteredNoData => 0
+        }
+    }
+
+    /// Returns the logged samples with a timestamp between `from` and `to`, inclusive.
+    fn samples_in_range(&self, from: u64, to: u64) -> Vec<Sample<T>> where T: Copy {
+        match self {
+            Self::Registered(data) | Self::Deregistered(data) => data
+                .iter()
+                .filter(|sample| sample.timestamp >= from && sample.timestamp <= to)
+                .copied()
+                .collect(),
+            Self::RegisteredNoData => Vec::new()
+        }
+    }
+
+    fn oldest_timestamp(&self) -> Option<u64> {
+        match self {
+            Self::Registered(data) | Self::Deregistered(data) => data.front().map(|sample| sample.timestamp),
+            Self::RegisteredNoData => None
+        }
+    }
+}
+
+/// A data source's status, along with the bookkeeping Florust does for it.
+struct DataSource<T> where T: Send + Sync {
+    status: DataSourceStatus<T>,
+    metadata: DataSourceMetadata,
+    /// Rollups of every sample logged, kept around for longer than the samples themselves.
+    rollups: RollupTiers,
+    /// Whether samples older than the oldest logged one were dropped, by the logged samples overflowing or by
+    /// the retention policy, in which case only the rollups still cover them.
+    truncated: bool,
+    dropped_samples: u64,
+    /// When the data source was deregistered, in milliseconds since the unix epoch.
+    deregistered_at: Option<u64>,
+    /// When the data source was registered, in milliseconds since the unix epoch.
+    registered_at: u64,
+    /// When the data source last uploaded a sample, in milliseconds since the unix epoch.
+    last_seen: Option<u64>,
+    /// How often the data source declared it would upload samples.
+    expected_interval_secs: Option<u64>,
+    /// Whether the data source missed its expected reports, as of the last heartbeat check.
+    stale: bool,
+    /// Commands for the data source to carry out, along with those it already did.
+    commands: CommandQueue,
+    /// Data the data source registered with, passed to the manager again when the data source is restored.
+    registration_data: Option<Vec<u8>>,
+}
+
+impl<T> DataSource<T> where T: Send + Sync {
+    fn new(rollup_config: &RollupConfig, metadata: DataSourceMetadata, expected_interval_secs: Option<u64>) -> DataSource<T> {
+        DataSource {
+            status: DataSourceStatus::RegisteredNoData,
+            metadata,
+            rollups: RollupTiers::new(rollup_config),
+            truncated: false,
+            dropped_samples: 0,
+            deregistered_at: None,
+            registered_at: now_millis(),
+            last_seen: None,
+            expected_interval_secs,
+            stale: false,
+            commands: CommandQueue::new(),
+            registration_data: None,
+        }
+    }
+
+    /// Updates whether the data source is stale, which it is once it has gone `tolerance` times its expected
+    /// interval without uploading a sample. Returns the new status if it changed.
+    fn check_stale(&mut self, now: u64, tolerance: f64) -> Option<bool> {
+        let stale = match self.expected_interval_secs {
+            Some(interval) if self.status.is_registered() => {
+                let last_seen = self.last_seen.unwrap_or(self.registered_at);
+                now.saturating_sub(last_seen) as f64 > interval as f64 * 1000.0 * tolerance
+            },
+            _ => false
+        };
+
+        if stale == self.stale {
+            return None;
+        }
+
+        self.stale = stale;
+        Some(stale)
+    }
+
+    /// Logs a value of the data source, timestamped now. Fails if the data source is deregistered.
+    fn log(&mut self, id: &str, value: T, max_size: usize, default: T, into_data_type: fn(T) -> DataType) -> Result<Sample<DataType>> where T: Copy {
+        let sample = Sample {
+            timestamp: now_millis(),
+            value,
+        };
+
+        match &mut self.status {
+            DataSourceStatus::RegisteredNoData => {
+                let mut logged_data = CircularVec::new(max_size, Sample { timestamp: 0, value: default });
+                logged_data.append(sample);
+                self.status = DataSourceStatus::Registered(logged_data);
+            },
+            DataSourceStatus::Registered(logged_data) => {
+                if logged_data.len() == logged_data.capacity() {
+                    self.truncated = true;
+                }
+                logged_data.append(sample);
+            },
+            DataSourceStatus::Deregistered(_) => return Err(
+                ManagerAndDataError::DataSourceManager(
+                    FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                )
+            ),
+        }
+
+        let sample = Sample { timestamp: sample.timestamp, value: into_data_type(sample.value) };
+        self.rollups.add(sample.timestamp, sample.value.as_f64());
+        self.last_seen = Some(sample.timestamp);
+
+        Ok(sample)
+    }
+
+    /// Returns the data between `from` and `to`, from the raw samples if they cover the range in at most
+    /// `max_points` samples, or from the rollups otherwise. The raw samples cover the range unless samples
+    /// logged after `from` were dropped.
+    fn range(&self, from: u64, to: u64, max_points: usize, into_data_type: fn(T) -> DataType) -> Series where T: Copy {
+        let samples = self.status.samples_in_range(from, to);
+        let covered = !self.truncated || self.status.oldest_timestamp().is_some_and(|oldest| oldest <= from);
+
+        if covered && samples.len() <= max_points {
+            return Series::Raw(into_data_types(samples, into_data_type));
+        }
+
+        match self.rollups.range(from, to, max_points) {
+            Some((resolution, buckets)) => Series::Rollup { resolution, buckets },
+            None => Series::Raw(into_data_types(samples, into_data_type)),
+        }
+    }
+
+    /// Returns when the oldest data of the data source was logged: the oldest logged sample, unless older
+    /// samples were dropped, in which case the start of the oldest rollup.
+    fn oldest_timestamp(&self) -> Option<u64> {
+        match self.status.oldest_timestamp() {
+            Some(oldest) if !self.truncated => Some(oldest),
+            oldest => self.rollups.oldest().or(oldest),
+        }
+    }
+
+    fn snapshot(&self, id: &str, into_data_type: fn(T) -> DataType) -> DataSourceSnapshot where T: Copy {
+        let samples = match &self.status {
+            DataSourceStatus::Registered(data) | DataSourceStatus::Deregistered(data) => into_data_types(data.iter().copied().collect(), into_data_type),
+            DataSourceStatus::RegisteredNoData => Vec::new(),
+        };
+
+        DataSourceSnapshot {
+            id: id.to_string(),
+            registered: self.status.is_registered(),
+            metadata: self.metadata.clone(),
+            samples,
+            rollups: self.rollups.snapshot(),
+            truncated: self.truncated,
+            dropped_samples: self.dropped_samples,
+            registered_at: self.registered_at,
+            deregistered_at: self.deregistered_at,
+            last_seen: self.last_seen,
+            expected_interval_secs: self.expected_interval_secs,
+            commands: self.commands.history(),
+            registration_data: self.registration_data.clone(),
+        }
+    }
+
+    /// Recreates a data source from a snapshot, keeping at most `max_size` of its samples.
+    fn restore(
+        snapshot: DataSourceSnapshot,
+        rollup_config: &RollupConfig,
+        max_size: usize,
+        default: Sample<T>,
+        from_data_type: fn(DataType) -> Option<T>
+    ) -> Result<DataSource<T>> where T: Copy {
+        let samples = snapshot_samples(&snapshot, from_data_type)?;
+        let truncated = snapshot.truncated || samples.len() > max_size;
+
+        let status = if samples.is_empty() && snapshot.registered {
+            DataSourceStatus::RegisteredNoData
+        }
+        else {
+            let mut data = CircularVec::new(max_size, default);
+            for sample in samples {
+                data.append(sample);
+            }
+
+            if snapshot.registered { DataSourceStatus::Registered(data) } else { DataSourceStatus::Deregistered(data) }
+        };
+
+        Ok(DataSource {
+            status,
+            metadata: snapshot.metadata,
+            rollups: RollupTiers::restore(rollup_config, snapshot.rollups),
+            truncated,
+            dropped_samples: snapshot.dropped_samples,
+            deregistered_at: snapshot.deregistered_at,
+            registered_at: snapshot.registered_at,
+            last_seen: snapshot.last_seen,
+            expected_interval_secs: snapshot.expected_interval_secs,
+            stale: false,
+            commands: CommandQueue::from_commands(snapshot.commands),
+            registration_data: snapshot.registration_data,
+        })
+    }
+
+    /// Merges imported samples, along with the lines they were imported from, into the logged samples, keeping
+    /// at most `max_size` of them. Nothing is written on a dry run, or if a duplicate is rejected.
+    fn import(
+        &mut self,
+        mut imported: Vec<(u64, Sample<T>)>,
+        options: &ImportOptions,
+        max_size: usize,
+        default: Sample<T>,
+        into_data_type: fn(T) -> DataType
+    ) -> ImportReport where T: Copy {
+        let mut report = ImportReport::default();
+        imported.sort_by_key(|(_, sample)| sample.timestamp);
+
+        let mut deduplicated: Vec<(u64, Sample<T>)> = Vec::with_capacity(imported.len());
+        for (line, sample) in imported {
+            match deduplicated.last_mut() {
+                Some((kept_line, kept)) if kept.timestamp == sample.timestamp => match options.on_duplicate {
+                    DuplicatePolicy::Skip => report.skipped += 1,
+                    DuplicatePolicy::Replace => {
+                        (*kept_line, *kept) = (line, sample);
+                        report.skipped += 1;
+                    },
+                    DuplicatePolicy::Error => report.errors.push(RowError {
+                        line,
+                        error: format!("timestamp {} is also used on line {}", sample.timestamp, kept_line),
+                    }),
+                },
+                _ => deduplicated.push((line, sample)),
+            }
+        }
+
+        let logged: Vec<Sample<T>> = match &self.status {
+            DataSourceStatus::Registered(data) | DataSourceStatus::Deregistered(data) => data.iter().copied().collect(),
+            DataSourceStatus::RegisteredNoData => Vec::new(),
+        };
+        let logged_timestamps: HashSet<u64> = logged.iter().map(|sample| sample.timestamp).collect();
+
+        let mut new_samples = Vec::with_capacity(deduplicated.len());
+        let mut replaced_timestamps = HashSet::new();
+        for (line, sample) in deduplicated {
+            if !logged_timestamps.contains(&sample.timestamp) {
+                new_samples.push(sample);
+                continue;
+            }
+
+            match options.on_duplicate {
+                DuplicatePolicy::Skip => report.skipped += 1,
+                DuplicatePolicy::Replace => {
+                    replaced_timestamps.insert(sample.timestamp);
+                    new_samples.push(sample);
+                },
+                DuplicatePolicy::Error => report.errors.push(RowError {
+                    line,
+                    error: format!("timestamp {} is already logged", sample.timestamp),
+                }),
+            }
+        }
+
+        let mut merged: Vec<Sample<T>> = logged.into_iter()
+            .filter(|sample| !replaced_timestamps.contains(&sample.timestamp))
+            .chain(new_samples.iter().copied())
+            .collect();
+        merged.sort_by_key(|sample| sample.timestamp);
+
+        report.imported = new_samples.len();
+        report.replaced = replaced_timestamps.len();
+        report.evicted = merged.len().saturating_sub(max_size);
+
+        if options.dry_run || !report.errors.is_empty() || new_samples.is_empty() {
+            return report;
+        }
+
+        if report.evicted > 0 {
+            self.truncated = true;
+        }
+
+        let mut data = CircularVec::new(max_size, default);
+        for sample in merged {
+            data.append(sample);
+        }
+
+        self.status = if self.status.is_registered() {
+            DataSourceStatus::Registered(data)
+        }
+        else {
+            DataSourceStatus::Deregistered(data)
+        };
+
+        // Rollups can't take samples out, so those of replaced samples still count them.
+        let rollup_samples: Vec<(u64, f64)> = new_samples.iter()
+            .map(|sample| (sample.timestamp, into_data_type(sample.value).as_f64()))
+            .collect();
+        self.rollups.import(&rollup_samples);
+
+        report
+    }
+
+    /// Whether the data source should be removed, because it has been deregistered for longer than the
+    /// retention policy allows, or because it is deregistered and neither its samples nor its rollups are left.
+    fn is_expired(&self, policy: &RetentionConfig, now: u64) -> bool {
+        let DataSourceStatus::Deregistered(data) = &self.status else {
+            return false;
+        };
+
+        let kept_for = self.deregistered_at.map(|deregistered_at| now.saturating_sub(deregistered_at));
+        if let (Some(keep_for), Some(kept_for)) = (policy.keep_deregistered_for_secs, kept_for) {
+            if kept_for >= keep_for * 1000 {
+                return true;
+            }
+        }
+
+        data.is_empty() && self.rollups.expired(now)
+    }
+
+    /// Removes the samples which the retention policy no longer allows to be kept.
+    fn apply_retention(&mut self, policy: &RetentionConfig, now: u64) {
+        let data = match &mut self.status {
+            DataSourceStatus::Registered(data) | DataSourceStatus::Deregistered(data) => data,
+            DataSourceStatus::RegisteredNoData => return,
+        };
+
+        let logged = data.len();
+        if let Some(max_age) = policy.max_age_secs {
+            let cutoff = now.saturating_sub(max_age * 1000);
+            while data.front().is_some_and(|sample| sample.timestamp < cutoff) {
+                data.pop_front();
+            }
+        }
+
+        if let Some(max_samples) = policy.max_samples {
+            while data.len() > max_samples {
+                data.pop_front();
+            }
+        }
+
+        if data.len() < logged {
+            self.truncated = true;
+        }
+
+        if data.is_empty() && self.status.is_registered() {
+            self.status = DataSourceStatus::RegisteredNoData;
+        }
+    }
 }
 
-type LoggedData<T> = RwLock<DataSourceStatus<T>>;
+type LoggedData<T> = RwLock<DataSource<T>>;
 
 type IIntegerDataManager = Box<IIntegerDataSourceManager>;
 type IIntegerLoggedData = LoggedData<i64>;
@@ -39,7 +412,7 @@ type UIntegerLoggedData = LoggedData<u64>;
 type FloatDataManager = Box<FloatDataSourceManager>;
 type FloatLoggedData = LoggedData<f64>;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy)]
 #[serde(crate = "rocket::serde")]
 pub enum DataType {
     IInteger(i64),
@@ -47,6 +420,21 @@ pub enum DataType {
     Float(f64)
 }
 
+impl DataType {
+    /// Converts the value to another unit, which always results in a float.
+    pub fn convert(&self, conversion: &Conversion) -> DataType {
+        DataType::Float(conversion.apply(self.as_f64()))
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            DataType::IInteger(value) => *value as f64,
+            DataType::UInteger(value) => *value as f64,
+            DataType::Float(value) => *value,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Error, Debug)]
 #[serde(crate = "rocket::serde")]
 pub enum ManagerAndDataError {
@@ -55,7 +443,115 @@ pub enum ManagerAndDataError {
     #[error("Attempted to access data from a data source but it has no reported data")]
     NoData,
     #[error("Attempted to access data from a data source but an out of bounds index was used")]
-    IndexOutOfBounds
+    IndexOutOfBounds,
+    #[error("Data source exceeded its rate limit, retry after {retry_after} seconds")]
+    RateLimited {
+        retry_after: u64
+    },
+    #[error("Uploaded data is {size} bytes, but at most {max_size} bytes are accepted")]
+    PayloadTooLarge {
+        size: usize,
+        max_size: usize
+    },
+    #[error("Uploaded data couldn't be parsed: {0}")]
+    InvalidUpload(String),
+    #[error("Query is invalid: {0}")]
+    InvalidQuery(String),
+    #[error("Unit conversion is invalid: {0}")]
+    InvalidUnit(String),
+    #[error("Attempted to access alert rule ({0}), but it doesn't exist")]
+    AlertRuleDoesntExist(String),
+    #[error("Attempted to access automation rule ({0}), but it doesn't exist")]
+    AutomationRuleDoesntExist(String),
+    #[error("Automation rules set at runtime may only run the programs allowed by the configuration, which {0} isn't")]
+    ProgramNotAllowed(String),
+    #[error("Attempted to access command ({0}), but it doesn't exist")]
+    CommandDoesntExist(u64),
+    #[error("Attempted to acknowledge command ({0}), but it was already completed")]
+    CommandAlreadyCompleted(u64),
+    #[error("Import has {} invalid rows", .0.invalid_rows)]
+    InvalidImport(Box<ImportReport>),
+    #[error("Request body exceeds the limit of {max_size} bytes")]
+    BodyTooLarge {
+        max_size: u64
+    },
+    #[error("Snapshot can't be restored: {0}")]
+    InvalidSnapshot(String),
+    #[error("Server is shutting down, and no longer accepts data")]
+    ShuttingDown
+}
+
+/// Summary of a data source's status, as reported by the status route.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DataSourceInfo {
+    pub registered: bool,
+    pub logged_samples: usize,
+    /// Number of samples that were rejected by the server's limits, rather than logged.
+    pub dropped_samples: u64,
+    pub metadata: DataSourceMetadata,
+    /// When the data source last uploaded a sample, in milliseconds since the unix epoch.
+    pub last_seen: Option<u64>,
+    pub expected_interval_secs: Option<u64>,
+    pub stale: bool,
+}
+
+/// A data source, as reported by the listing route.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DataSourceListing {
+    pub manager_id: String,
+    pub data_source_id: String,
+    pub metadata: DataSourceMetadata,
+}
+
+/// Data of a data source over a range of time, either as the logged samples, or as rollups if the samples
+/// didn't cover the range.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub enum Series {
+    Raw(Vec<Sample<DataType>>),
+    Rollup {
+        resolution: Resolution,
+        buckets: Vec<Rollup>
+    },
+}
+
+impl Series {
+    /// Converts every value of the series to another unit.
+    pub fn convert(self, conversion: &Conversion) -> Series {
+        match self {
+            Series::Raw(samples) => Series::Raw(
+                samples.into_iter()
+                    .map(|sample| Sample { timestamp: sample.timestamp, value: sample.value.convert(conversion) })
+                    .collect()
+            ),
+            Series::Rollup { resolution, buckets } => Series::Rollup {
+                resolution,
+                buckets: buckets.into_iter().map(|bucket| bucket.convert(conversion)).collect(),
+            },
+        }
+    }
+}
+
+fn into_data_types<T>(samples: Vec<Sample<T>>, into_data_type: fn(T) -> DataType) -> Vec<Sample<DataType>> {
+    samples.into_iter()
+        .map(|sample| Sample { timestamp: sample.timestamp, value: into_data_type(sample.value) })
+        .collect()
+}
+
+/// Returns the samples of a data source's snapshot as the manager's type, failing if any is of another type.
+fn snapshot_samples<T>(snapshot: &DataSourceSnapshot, from_data_type: fn(DataType) -> Option<T>) -> Result<Vec<Sample<T>>> {
+    snapshot.samples
+        .iter()
+        .map(|sample| {
+            from_data_type(sample.value)
+                .map(|value| Sample { timestamp: sample.timestamp, value })
+                .ok_or_else(|| ManagerAndDataError::InvalidSnapshot(
+                    format!("data source {} has samples of another type than its manager", snapshot.id)
+                ))
+        })
+        .collect()
 }
 
 pub type Result<T> = result::Result<T, ManagerAndDataError>;
@@ -64,45 +560,244 @@ pub type Result<T> = result::Result<T, ManagerAndDataError>;
 pub trait ManagerAndData: Send + Sync {
     fn manager_id(&self) -> &'static str;
 
-    async fn register(&self, id: String) -> Result<()>;
+    /// Registers a data source, which promises to upload a sample every `expected_interval_secs`, if given.
+    async fn register(&self, id: String, metadata: DataSourceMetadata, expected_interval_secs: Option<u64>) -> Result<()>;
 
-    async fn register_with_data(&self, id: String, data: &[u8]) -> Result<()>;
+    async fn register_with_data(
+        &self,
+        id: String,
+        data: &[u8],
+        metadata: DataSourceMetadata,
+        expected_interval_secs: Option<u64>
+    ) -> Result<()>;
 
     async fn deregister(&self, id: &str) -> Result<()>;
 
     async fn deregister_with_data(&self, id: &str, data: &[u8]) -> Result<()>;
 
-    async fn update_data(&self, id: &str, data: &[u8]) -> Result<()>;
+    /// Passes uploaded data to the manager, and logs the value it produces. Returns the logged sample.
+    async fn update_data(&self, id: &str, data: &[u8]) -> Result<Sample<DataType>>;
 
     async fn get_data(&self, id: &str, index: usize) -> Result<DataType>;
+
+    /// Returns the data logged between `from` and `to`, in milliseconds since the unix epoch. The data comes
+    /// from the logged samples if they cover the range, or from the coarsest rollups necessary otherwise.
+    async fn get_range(&self, id: &str, from: u64, to: u64) -> Result<Series>;
+
+    /// Returns every sample logged between `from` and `to` if the logged samples cover the range, or the
+    /// buckets of the finest rollups covering it otherwise, however many there are.
+    async fn get_full_range(&self, id: &str, from: u64, to: u64) -> Result<Series>;
+
+    /// Returns when the oldest data of a data source, raw or rolled up, was logged.
+    async fn oldest_timestamp(&self, id: &str) -> Result<Option<u64>>;
+
+    /// Returns the logged samples with a timestamp between `from` and `to`, inclusive, oldest first.
+    async fn get_samples(&self, id: &str, from: u64, to: u64) -> Result<Vec<Sample<DataType>>>;
+
+    /// Merges samples with their own timestamps into the logged samples of a data source, decoding the
+    /// values that need it with the manager once the other rows are valid, and never on a dry run. Nothing is
+    /// written on a dry run, or if any row is invalid, in which case the report lists the invalid rows.
+    async fn import(&self, id: &str, rows: Vec<ImportedRow>, options: &ImportOptions) -> Result<ImportReport>;
+
+    async fn is_registered(&self, id: &str) -> bool;
+
+    /// Returns the ids and metadata of every data source the manager holds data for, registered or not.
+    async fn data_sources(&self) -> Vec<(String, DataSourceMetadata)>;
+
+    /// Replaces the metadata of a data source.
+    async fn set_metadata(&self, id: &str, metadata: DataSourceMetadata) -> Result<()>;
+
+    async fn info(&self, id: &str) -> Result<DataSourceInfo>;
+
+    /// Enqueues a command for a registered data source.
+    async fn enqueue_command(&self, id: &str, command: NewCommand, config: &CommandsConfig) -> Result<Command>;
+
+    /// Returns the commands a data source should carry out, marking them as delivered.
+    async fn take_commands(&self, id: &str, config: &CommandsConfig) -> Result<Vec<Command>>;
+
+    /// Returns what is notified whenever a command is enqueued for the data source.
+    async fn command_notify(&self, id: &str) -> Result<Arc<Notify>>;
+
+    async fn acknowledge_command(&self, id: &str, command_id: u64, ack: CommandAck, config: &CommandsConfig) -> Result<Command>;
+
+    /// Returns the commands of a data source, completed ones first, oldest first.
+    async fn commands(&self, id: &str) -> Result<Vec<Command>>;
+
+    /// Records that a sample for the data source was dropped, rather than logged. Does nothing if the data
+    /// source doesn't exist.
+    async fn record_dropped(&self, id: &str);
+
+    /// Updates which data sources are stale, see [`HeartbeatConfig`](crate::heartbeat::HeartbeatConfig).
+    /// Returns the data sources whose status changed, along with whether they are now stale.
+    async fn check_stale(&self, now: u64, tolerance: f64) -> Vec<(String, bool)>;
+
+    /// Applies the retention policy to every data source, removing the samples and deregistered data
+    /// sources that are too old.
+    async fn apply_retention(&self, policy: &RetentionConfig, now: u64);
+
+    /// Removes all logged data of a data source, along with what was recorded about it, like its dropped samples
+    /// and when it was last seen. A registered data source stays registered, while a deregistered one is
+    /// removed entirely.
+    async fn purge(&self, id: &str) -> Result<()>;
+
+    /// Returns every data source the manager holds data for, registered or not, to be saved in a snapshot.
+    async fn snapshot(&self) -> Vec<DataSourceSnapshot>;
+
+    /// Checks whether data sources can be restored from a snapshot, which they can't if they hold samples of
+    /// another type than the manager's, or if they already exist and aren't to be replaced.
+    async fn check_restore(&self, data_sources: &[DataSourceSnapshot], replace: bool) -> Result<()>;
+
+    /// Restores data sources from a snapshot, replacing the data sources with the same ids if `replace` is
+    /// true, and registering or deregistering them with the manager to match the snapshot. Either every data
+    /// source is restored, or, if any can't be, none is. Returns the data sources as they were before, or
+    /// `None` for those which didn't exist, for [`undo_restore`](ManagerAndData::undo_restore) to put back.
+    async fn restore(&self, data_sources: Vec<DataSourceSnapshot>, replace: bool) -> Result<Vec<(String, Option<DataSourceSnapshot>)>>;
+
+    /// Puts back the data sources a restore replaced, when restoring the data sources of another manager
+    /// failed.
+    async fn undo_restore(&self, previous: Vec<(String, Option<DataSourceSnapshot>)>);
+
+    /// Tells the manager the server has launched.
+    async fn start(&self);
+
+    /// Returns the data sources the manager polls.
+    async fn polled_sources(&self) -> Vec<PolledSource>;
+
+    /// Polls a registered data source, logging the value like uploaded data.
+    async fn poll(&self, id: &str) -> Result<Sample<DataType>>;
+
+    /// Logs the values the manager computes periodically, returning the outcome for each data source.
+    async fn tick(&self) -> Vec<(String, Result<Sample<DataType>>)>;
+
+    async fn health(&self) -> PluginHealth;
+
+    /// Lets the manager release its resources, as the server is shutting down.
+    async fn shutdown(&self);
 }
 
 pub struct IIntegerManagerAndData {
     manager: IIntegerDataManager,
     logged_data: RwLock<HashMap<String, IIntegerLoggedData>>,
-    max_logged_data_size: usize
+    max_logged_data_size: usize,
+    rollup_config: RollupConfig,
+    /// Unit assigned to data sources registering without one.
+    unit: Option<String>,
+    events: EventBus
 }
 
 pub struct UIntegerManagerAndData {
     manager: UIntegerDataManager,
     logged_data: RwLock<HashMap<String, UIntegerLoggedData>>,
-    max_logged_data_size: usize
+    max_logged_data_size: usize,
+    rollup_config: RollupConfig,
+    /// Unit assigned to data sources registering without one.
+    unit: Option<String>,
+    events: EventBus
 }
 
 pub struct FloatManagerAndData {
     manager: FloatDataManager,
     logged_data: RwLock<HashMap<String, FloatLoggedData>>,
-    max_logged_data_size: usize
+    max_logged_data_size: usize,
+    rollup_config: RollupConfig,
+    /// Unit assigned to data sources registering without one.
+    unit: Option<String>,
+    events: EventBus
 }
 
 macro_rules! manager_and_data_impl {
     ($impl_for:ident, $data_manager:ty, $default_val:literal, $data_type:path) => {
         impl $impl_for {
-            pub fn new(manager: $data_manager, max_logged_data_size: usize) -> $impl_for {
+            /// Creates the manager and its data. `unit` overrides the unit declared by the manager, if any.
+            pub fn new(
+                manager: $data_manager,
+                max_logged_data_size: usize,
+                rollup_config: RollupConfig,
+                unit: Option<String>,
+                events: EventBus
+            ) -> $impl_for {
+                let unit = unit.or_else(|| manager.unit().map(str::to_string));
+
                 $impl_for {
                     manager,
                     logged_data: RwLock::new(HashMap::new()),
-                    max_logged_data_size
+                    max_logged_data_size,
+                    rollup_config,
+                    unit,
+                    events
+                }
+            }
+
+            fn check_restore_locked<L>(
+                &self,
+                logged_data: &HashMap<String, L>,
+                data_sources: &[DataSourceSnapshot],
+                replace: bool
+            ) -> Result<()> {
+                for snapshot in data_sources {
+                    if !replace && logged_data.contains_key(&snapshot.id) {
+                        return Err(
+                            ManagerAndDataError::DataSourceManager(
+                                FlorustServerPluginError::DataSourceAlreadyExists(snapshot.id.clone())
+                            )
+                        );
+                    }
+
+                    snapshot_samples(snapshot, |value| match value {
+                        $data_type(value) => Some(value),
+                        _ => None,
+                    })?;
+                }
+
+                Ok(())
+            }
+
+            /// Registers or deregisters a data source with the manager, if it was the other way around, passing
+            /// along the data it registered with, if any.
+            async fn sync_registration(&self, id: &str, registered: bool, data: Option<&[u8]>, was_registered: bool) -> Result<()> {
+                let result = match (was_registered, registered, data) {
+                    (false, true, Some(data)) => self.manager.register_with_data(id.to_string(), data).await,
+                    (false, true, None) => self.manager.register(id.to_string()).await,
+                    (true, false, _) => self.manager.deregister(id).await,
+                    _ => Ok(()),
+                };
+
+                result.map_err(|err| {
+                    ManagerAndDataError::DataSourceManager(
+                        FlorustServerPluginError::DataSourceManager(err)
+                    )
+                })
+            }
+
+            /// Assigns the manager's unit to metadata without units.
+            fn with_default_unit(&self, mut metadata: DataSourceMetadata) -> DataSourceMetadata {
+                if metadata.units.is_none() {
+                    metadata.units = self.unit.clone();
+                }
+
+                metadata
+            }
+
+            /// Wraps an error of the manager, telling whoever listens to events about rejected data.
+            fn manager_error(&self, id: &str, error: DataSourceManagerError) -> ManagerAndDataError {
+                if let DataSourceManagerError::InvalidData(reason) = &error {
+                    self.events.emit(ServerEvent::DataRejected {
+                        manager_id: self.manager_id().to_string(),
+                        data_source_id: id.to_string(),
+                        error: reason.clone(),
+                    });
+                }
+
+                ManagerAndDataError::DataSourceManager(
+                    FlorustServerPluginError::DataSourceManager(error)
+                )
+            }
+
+            fn registered_event(&self, id: &str, metadata: &DataSourceMetadata) -> ServerEvent {
+                ServerEvent::DataSourceRegistered {
+                    manager_id: self.manager_id().to_string(),
+                    data_source_id: id.to_string(),
+                    metadata: metadata.clone(),
                 }
             }
         }
@@ -113,13 +808,15 @@ macro_rules! manager_and_data_impl {
                 self.manager.manager_id()
             }
 
-            async fn register(&self, id: String) -> Result<()> {
+            async fn register(&self, id: String, metadata: DataSourceMetadata, expected_interval_secs: Option<u64>) -> Result<()> {
+                let metadata = self.with_default_unit(metadata);
+                let event = self.registered_event(&id, &metadata);
                 let mut lock = self.logged_data.write().await;
                 match lock.get(&id) {
                     Some(data_source) => {
                         let mut data_source = data_source.write().await;
 
-                        if data_source.is_registered() {
+                        if data_source.status.is_registered() {
                             return Err(
                                 ManagerAndDataError::DataSourceManager(
                                     FlorustServerPluginError::DataSourceAlreadyExists(id)
@@ -132,7 +829,7 @@ macro_rules! manager_and_data_impl {
                                 FlorustServerPluginError::DataSourceManager(err)
                             )
                         })?;
-                        *data_source = DataSourceStatus::RegisteredNoData;
+                        *data_source = DataSource::new(&self.rollup_config, metadata, expected_interval_secs);
                     }
                     None => {
                         self.manager.register(id.clone()).await.map_err(|err| {
@@ -140,20 +837,29 @@ macro_rules! manager_and_data_impl {
                                 FlorustServerPluginError::DataSourceManager(err)
                             )
                         })?;
-                        lock.insert(id, RwLock::new(DataSourceStatus::RegisteredNoData));
+                        lock.insert(id, RwLock::new(DataSource::new(&self.rollup_config, metadata, expected_interval_secs)));
                     }
                 }
 
+                self.events.emit(event);
                 Ok(())
             }
 
-            async fn register_with_data(&self, id: String, data: &[u8]) -> Result<()> {
+            async fn register_with_data(
+                &self,
+                id: String,
+                data: &[u8],
+                metadata: DataSourceMetadata,
+                expected_interval_secs: Option<u64>
+            ) -> Result<()> {
+                let metadata = self.with_default_unit(metadata);
+                let event = self.registered_event(&id, &metadata);
                 let mut lock = self.logged_data.write().await;
                 match lock.get(&id) {
                     Some(data_source) => {
                         let mut data_source = data_source.write().await;
 
-                        if data_source.is_registered() {
+                        if data_source.status.is_registered() {
                             return Err(
                                 ManagerAndDataError::DataSourceManager(
                                     FlorustServerPluginError::DataSourceAlreadyExists(id)
@@ -166,7 +872,8 @@ macro_rules! manager_and_data_impl {
                                 FlorustServerPluginError::DataSourceManager(err)
                             )
                         })?;
-                        *data_source = DataSourceStatus::RegisteredNoData;
+                        *data_source = DataSource::new(&self.rollup_config, metadata, expected_interval_secs);
+                        data_source.registration_data = Some(data.to_vec());
                     }
                     None => {
                         self.manager.register_with_data(id.clone(), data).await.map_err(|err| {
@@ -174,10 +881,13 @@ macro_rules! manager_and_data_impl {
                                 FlorustServerPluginError::DataSourceManager(err)
                             )
                         })?;
-                        lock.insert(id, RwLock::new(DataSourceStatus::RegisteredNoData));
+                        let mut data_source = DataSource::new(&self.rollup_config, metadata, expected_interval_secs);
+                        data_source.registration_data = Some(data.to_vec());
+                        lock.insert(id, RwLock::new(data_source));
                     }
                 }
 
+                self.events.emit(event);
                 Ok(())
             }
 
@@ -192,7 +902,7 @@ macro_rules! manager_and_data_impl {
                     )?
                     .write().await;
 
-                if !status.is_registered() {
+                if !status.status.is_registered() {
                     return Err(
                         ManagerAndDataError::DataSourceManager(
                             FlorustServerPluginError::DataSourceAlreadyDeregistered(id.to_string())
@@ -207,14 +917,20 @@ macro_rules! manager_and_data_impl {
                         )
                     })?;
 
-                let tmp = std::mem::replace(&mut *status, DataSourceStatus::RegisteredNoData);
-                *status = match tmp {
+                status.deregistered_at = Some(now_millis());
+                status.stale = false;
+                self.events.emit(ServerEvent::DataSourceDeregistered {
+                    manager_id: self.manager_id().to_string(),
+                    data_source_id: id.to_string(),
+                });
+                let tmp = std::mem::replace(&mut status.status, DataSourceStatus::RegisteredNoData);
+                status.status = match tmp {
                     DataSourceStatus::Registered(data) => DataSourceStatus::Deregistered(data),
                     DataSourceStatus::RegisteredNoData => DataSourceStatus::RegisteredNoData,
                     DataSourceStatus::Deregistered(_) => unreachable!("DataSourceStatus is Deregistered despite check saying it isn't.")
                 };
 
-                if let DataSourceStatus::RegisteredNoData = *status {
+                if let DataSourceStatus::RegisteredNoData = status.status {
                     drop(status);
                     lock.remove(id);
                 }
@@ -233,7 +949,7 @@ macro_rules! manager_and_data_impl {
                     )?
                     .write().await;
 
-                if !status.is_registered() {
+                if !status.status.is_registered() {
                     return Err(
                         ManagerAndDataError::DataSourceManager(
                             FlorustServerPluginError::DataSourceAlreadyDeregistered(id.to_string())
@@ -248,14 +964,20 @@ macro_rules! manager_and_data_impl {
                         )
                     })?;
 
-                let tmp = std::mem::replace(&mut *status, DataSourceStatus::RegisteredNoData);
-                *status = match tmp {
+                status.deregistered_at = Some(now_millis());
+                status.stale = false;
+                self.events.emit(ServerEvent::DataSourceDeregistered {
+                    manager_id: self.manager_id().to_string(),
+                    data_source_id: id.to_string(),
+                });
+                let tmp = std::mem::replace(&mut status.status, DataSourceStatus::RegisteredNoData);
+                status.status = match tmp {
                     DataSourceStatus::Registered(data) => DataSourceStatus::Deregistered(data),
                     DataSourceStatus::RegisteredNoData => DataSourceStatus::RegisteredNoData,
                     DataSourceStatus::Deregistered(_) => unreachable!("DataSourceStatus is Deregistered despite check saying it isn't.")
                 };
 
-                if let DataSourceStatus::RegisteredNoData = *status {
+                if let DataSourceStatus::RegisteredNoData = status.status {
                     drop(status);
                     lock.remove(id);
                 }
@@ -263,7 +985,7 @@ macro_rules! manager_and_data_impl {
                 Ok(())
             }
 
-            async fn update_data(&self, id: &str , data: &[u8]) -> Result<()> {
+            async fn update_data(&self, id: &str , data: &[u8]) -> Result<Sample<DataType>> {
                 let lock = self.logged_data.read().await;
 
                 let mut data_source = lock
@@ -276,35 +998,14 @@ macro_rules! manager_and_data_impl {
                     .write()
                     .await;
 
-                let val = self.manager.update_data(id, data).await.map_err(|e| {
-                    ManagerAndDataError::DataSourceManager(
-                        FlorustServerPluginError::DataSourceManager(e)
-                    )
-                })?;
-
-                match &mut *data_source {
-                    DataSourceStatus::RegisteredNoData => {
-                        let mut logged_data = CircularVec::new(self.max_logged_data_size, $default_val);
-                        logged_data.append(val);
-                        *data_source = DataSourceStatus::Registered(logged_data);
-                    },
-                    DataSourceStatus::Registered(logged_data) => {
-                        logged_data.append(val);
-                    },
-                    DataSourceStatus::Deregistered(_) => return Err(
-                        ManagerAndDataError::DataSourceManager(
-                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
-                        )
-                    ),
-                }
-
-                Ok(())
+                let val = self.manager.update_data(id, data).await.map_err(|e| self.manager_error(id, e))?;
+                data_source.log(id, val, self.max_logged_data_size, $default_val, $data_type)
             }
 
             async fn get_data(&self, id: &str, index: usize) -> Result<DataType> {
                 Ok(
                     $data_type(
-                        *self.logged_data.read().await
+                        self.logged_data.read().await
                             .get(id)
                             .ok_or(
                                 ManagerAndDataError::DataSourceManager(
@@ -312,16 +1013,870 @@ macro_rules! manager_and_data_impl {
                                 )
                             )?
                             .read().await
+                            .status
                             .data_or_err(|| ManagerAndDataError::NoData)?
                             .get(index)
                             .ok_or(ManagerAndDataError::IndexOutOfBounds)?
+                            .value
                     )
                 )
             }
-        }
-    };
-}
 
-manager_and_data_impl!(IIntegerManagerAndData, IIntegerDataManager, 0, DataType::IInteger);
-manager_and_data_impl!(UIntegerManagerAndData, UIntegerDataManager, 0, DataType::UInteger);
-manager_and_data_impl!(FloatManagerAndData, FloatDataManager, 0.0, DataType::Float);
+            async fn get_range(&self, id: &str, from: u64, to: u64) -> Result<Series> {
+                Ok(
+                    self.logged_data.read().await
+                        .get(id)
+                        .ok_or(
+                            ManagerAndDataError::DataSourceManager(
+                                FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                            )
+                        )?
+                        .read().await
+                        .range(from, to, self.rollup_config.max_points, $data_type)
+                )
+            }
+
+            async fn get_full_range(&self, id: &str, from: u64, to: u64) -> Result<Series> {
+                Ok(
+                    self.logged_data.read().await
+                        .get(id)
+                        .ok_or(
+                            ManagerAndDataError::DataSourceManager(
+                                FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                            )
+                        )?
+                        .read().await
+                        .range(from, to, usize::MAX, $data_type)
+                )
+            }
+
+            async fn oldest_timestamp(&self, id: &str) -> Result<Option<u64>> {
+                Ok(
+                    self.logged_data.read().await
+                        .get(id)
+                        .ok_or(
+                            ManagerAndDataError::DataSourceManager(
+                                FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                            )
+                        )?
+                        .read().await
+                        .oldest_timestamp()
+                )
+            }
+
+            async fn get_samples(&self, id: &str, from: u64, to: u64) -> Result<Vec<Sample<DataType>>> {
+                let samples = self.logged_data.read().await
+                    .get(id)
+                    .ok_or(
+                        ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                        )
+                    )?
+                    .read().await
+                    .status
+                    .samples_in_range(from, to);
+
+                Ok(into_data_types(samples, $data_type))
+            }
+
+            async fn import(&self, id: &str, rows: Vec<ImportedRow>, options: &ImportOptions) -> Result<ImportReport> {
+                let lock = self.logged_data.read().await;
+                let mut data_source = lock
+                    .get(id)
+                    .ok_or(
+                        ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                        )
+                    )?
+                    .write()
+                    .await;
+
+                // Decoding goes through the manager, which may keep state between uploads, so data is only
+                // decoded once the rest of the import is known to be valid, and never on a dry run. Until then,
+                // rows holding data stand in with the default value, as only their timestamps matter.
+                let mut samples = Vec::with_capacity(rows.len());
+                let mut to_decode = Vec::new();
+                let mut errors = Vec::new();
+                for row in rows {
+                    match row.value {
+                        ImportedValue::Value(value) => match value.parse() {
+                            Ok(value) => samples.push((row.line, Sample { timestamp: row.timestamp, value })),
+                            Err(_) => errors.push(RowError {
+                                line: row.line,
+                                error: format!("{} isn't a valid value for this data source", value),
+                            }),
+                        },
+                        ImportedValue::Data(data) => {
+                            to_decode.push((samples.len(), data));
+                            samples.push((row.line, Sample { timestamp: row.timestamp, value: $default_val }));
+                        },
+                    }
+                }
+
+                let default = Sample { timestamp: 0, value: $default_val };
+                let mut options = ImportOptions {
+                    on_duplicate: options.on_duplicate,
+                    dry_run: options.dry_run || !errors.is_empty(),
+                };
+                if !options.dry_run && !to_decode.is_empty() {
+                    let check = data_source.import(
+                        samples.clone(),
+                        &ImportOptions { on_duplicate: options.on_duplicate, dry_run: true },
+                        self.max_logged_data_size,
+                        default,
+                        $data_type
+                    );
+
+                    if check.errors.is_empty() {
+                        for (index, data) in to_decode {
+                            match self.manager.update_data(id, &data).await {
+                                Ok(value) => samples[index].1.value = value,
+                                Err(DataSourceManagerError::InvalidData(error) | DataSourceManagerError::PollFailed(error)) =>
+                                    errors.push(RowError { line: samples[index].0, error }),
+                            }
+                        }
+                        options.dry_run = !errors.is_empty();
+                    }
+                }
+
+                let mut report = data_source.import(
+                    samples,
+                    &options,
+                    self.max_logged_data_size,
+                    default,
+                    $data_type
+                );
+                report.errors.append(&mut errors);
+
+                Ok(report)
+            }
+
+            async fn is_registered(&self, id: &str) -> bool {
+                match self.logged_data.read().await.get(id) {
+                    Some(data_source) => data_source.read().await.status.is_registered(),
+                    None => false
+                }
+            }
+
+            async fn data_sources(&self) -> Vec<(String, DataSourceMetadata)> {
+                let lock = self.logged_data.read().await;
+
+                let mut data_sources = Vec::with_capacity(lock.len());
+                for (id, data_source) in lock.iter() {
+                    data_sources.push((id.clone(), data_source.read().await.metadata.clone()));
+                }
+
+                data_sources
+            }
+
+            async fn set_metadata(&self, id: &str, metadata: DataSourceMetadata) -> Result<()> {
+                self.logged_data.read().await
+                    .get(id)
+                    .ok_or(
+                        ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                        )
+                    )?
+                    .write().await
+                    .metadata = metadata;
+
+                Ok(())
+            }
+
+            async fn info(&self, id: &str) -> Result<DataSourceInfo> {
+                let lock = self.logged_data.read().await;
+                let data_source = lock
+                    .get(id)
+                    .ok_or(
+                        ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                        )
+                    )?
+                    .read().await;
+
+                Ok(DataSourceInfo {
+                    registered: data_source.status.is_registered(),
+                    logged_samples: data_source.status.logged_samples(),
+                    dropped_samples: data_source.dropped_samples,
+                    metadata: data_source.metadata.clone(),
+                    last_seen: data_source.last_seen,
+                    expected_interval_secs: data_source.expected_interval_secs,
+                    stale: data_source.stale,
+                })
+            }
+
+            async fn enqueue_command(&self, id: &str, command: NewCommand, config: &CommandsConfig) -> Result<Command> {
+                let lock = self.logged_data.read().await;
+                let mut data_source = lock
+                    .get(id)
+                    .ok_or(
+                        ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                        )
+                    )?
+                    .write().await;
+
+                if !data_source.status.is_registered() {
+                    return Err(
+                        ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                        )
+                    );
+                }
+
+                Ok(data_source.commands.enqueue(command, config))
+            }
+
+            async fn take_commands(&self, id: &str, config: &CommandsConfig) -> Result<Vec<Command>> {
+                Ok(
+                    self.logged_data.read().await
+                        .get(id)
+                        .ok_or(
+                            ManagerAndDataError::DataSourceManager(
+                                FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                            )
+                        )?
+                        .write().await
+                        .commands
+                        .take(now_millis(), config)
+                )
+            }
+
+            async fn command_notify(&self, id: &str) -> Result<Arc<Notify>> {
+                Ok(
+                    self.logged_data.read().await
+                        .get(id)
+                        .ok_or(
+                            ManagerAndDataError::DataSourceManager(
+                                FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                            )
+                        )?
+                        .read().await
+                        .commands
+                        .notify()
+                )
+            }
+
+            async fn acknowledge_command(&self, id: &str, command_id: u64, ack: CommandAck, config: &CommandsConfig) -> Result<Command> {
+                self.logged_data.read().await
+                    .get(id)
+                    .ok_or(
+                        ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                        )
+                    )?
+                    .write().await
+                    .commands
+                    .acknowledge(command_id, ack, now_millis(), config)
+            }
+
+            async fn commands(&self, id: &str) -> Result<Vec<Command>> {
+                Ok(
+                    self.logged_data.read().await
+                        .get(id)
+                        .ok_or(
+                            ManagerAndDataError::DataSourceManager(
+                                FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                            )
+                        )?
+                        .read().await
+                        .commands
+                        .history()
+                )
+            }
+
+            async fn record_dropped(&self, id: &str) {
+                if let Some(data_source) = self.logged_data.read().await.get(id) {
+                    data_source.write().await.dropped_samples += 1;
+                }
+            }
+
+            async fn check_stale(&self, now: u64, tolerance: f64) -> Vec<(String, bool)> {
+                let lock = self.logged_data.read().await;
+
+                let mut changed = Vec::new();
+                for (id, data_source) in lock.iter() {
+                    if let Some(stale) = data_source.write().await.check_stale(now, tolerance) {
+                        if stale {
+                            self.events.emit(ServerEvent::DataSourceStale {
+                                manager_id: self.manager_id().to_string(),
+                                data_source_id: id.clone(),
+                            });
+                        }
+
+                        changed.push((id.clone(), stale));
+                    }
+                }
+
+                changed
+            }
+
+            async fn apply_retention(&self, policy: &RetentionConfig, now: u64) {
+                // Data sources are locked one at a time, so uploads to the others carry on during the sweep.
+                let ids: Vec<String> = self.logged_data.read().await.keys().cloned().collect();
+
+                let mut expired = Vec::new();
+                for id in ids {
+                    let lock = self.logged_data.read().await;
+                    let Some(data_source) = lock.get(&id) else {
+                        continue;
+                    };
+
+                    let mut data_source = data_source.write().await;
+                    data_source.apply_retention(policy, now);
+                    if data_source.is_expired(policy, now) {
+                        expired.push(id);
+                    }
+                }
+
+                if expired.is_empty() {
+                    return;
+                }
+
+                // The data sources may have registered again since they were checked.
+                let mut lock = self.logged_data.write().await;
+                for id in expired {
+                    if lock.get_mut(&id).is_some_and(|data_source| data_source.get_mut().is_expired(policy, now)) {
+                        lock.remove(&id);
+                    }
+                }
+            }
+
+            async fn purge(&self, id: &str) -> Result<()> {
+                let mut lock = self.logged_data.write().await;
+                let mut data_source = lock
+                    .get(id)
+                    .ok_or(
+                        ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                        )
+                    )?
+                    .write().await;
+
+                if data_source.status.is_registered() {
+                    data_source.status = DataSourceStatus::RegisteredNoData;
+                    data_source.rollups = RollupTiers::new(&self.rollup_config);
+                    data_source.truncated = false;
+                    data_source.dropped_samples = 0;
+                    data_source.last_seen = None;
+                }
+                else {
+                    drop(data_source);
+                    lock.remove(id);
+                }
+
+                Ok(())
+            }
+
+            async fn snapshot(&self) -> Vec<DataSourceSnapshot> {
+                let lock = self.logged_data.read().await;
+
+                let mut data_sources = Vec::with_capacity(lock.len());
+                for (id, data_source) in lock.iter() {
+                    data_sources.push(data_source.read().await.snapshot(id, $data_type));
+                }
+
+                data_sources
+            }
+
+            async fn check_restore(&self, data_sources: &[DataSourceSnapshot], replace: bool) -> Result<()> {
+                self.check_restore_locked(&*self.logged_data.read().await, data_sources, replace)
+            }
+
+            async fn restore(&self, data_sources: Vec<DataSourceSnapshot>, replace: bool) -> Result<Vec<(String, Option<DataSourceSnapshot>)>> {
+                // The lock is held from the check on, so the data sources can't change in between.
+                let mut lock = self.logged_data.write().await;
+                self.check_restore_locked(&lock, &data_sources, replace)?;
+
+                let mut restored = Vec::with_capacity(data_sources.len());
+                for snapshot in data_sources {
+                    let id = snapshot.id.clone();
+                    let data_source = DataSource::restore(
+                        snapshot,
+                        &self.rollup_config,
+                        self.max_logged_data_size,
+                        Sample { timestamp: 0, value: $default_val },
+                        |value| match value {
+                            $data_type(value) => Some(value),
+                            _ => None,
+                        }
+                    )?;
+                    restored.push((id, data_source));
+                }
+
+                // The manager is the only part which may still fail, so its registrations are undone if it
+                // does, before anything else changed.
+                let mut synced: Vec<(&str, bool, Option<Vec<u8>>)> = Vec::new();
+                for (id, data_source) in &restored {
+                    let (was_registered, previous_data) = match lock.get_mut(id) {
+                        Some(previous) => {
+                            let previous = previous.get_mut();
+                            (previous.status.is_registered(), previous.registration_data.clone())
+                        },
+                        None => (false, None),
+                    };
+
+                    let registered = data_source.status.is_registered();
+                    if let Err(err) = self.sync_registration(id, registered, data_source.registration_data.as_deref(), was_registered).await {
+                        for (id, was_registered, previous_data) in synced.into_iter().rev() {
+                            if let Err(err) = self.sync_registration(id, was_registered, previous_data.as_deref(), !was_registered).await {
+                                warn!("Failed to undo the restore of {}/{}: {}", self.manager.manager_id(), id, err);
+                            }
+                        }
+                        return Err(err);
+                    }
+                    if registered != was_registered {
+                        synced.push((id, was_registered, previous_data));
+                    }
+                }
+
+                let mut previous = Vec::with_capacity(restored.len());
+                for (id, data_source) in restored {
+                    let replaced = lock.insert(id.clone(), RwLock::new(data_source))
+                        .map(|replaced| replaced.into_inner().snapshot(&id, $data_type));
+                    previous.push((id, replaced));
+                }
+
+                Ok(previous)
+            }
+
+            async fn undo_restore(&self, previous: Vec<(String, Option<DataSourceSnapshot>)>) {
+                let mut lock = self.logged_data.write().await;
+
+                for (id, snapshot) in previous.into_iter().rev() {
+                    let Some(restored) = lock.remove(&id) else {
+                        continue;
+                    };
+                    let restored = restored.into_inner();
+
+                    let (registered, registration_data) = match &snapshot {
+                        Some(snapshot) => (snapshot.registered, snapshot.registration_data.as_deref()),
+                        None => (false, None),
+                    };
+                    if let Err(err) = self.sync_registration(&id, registered, registration_data, restored.status.is_registered()).await {
+                        warn!("Failed to undo the restore of {}/{}: {}", self.manager.manager_id(), id, err);
+                    }
+
+                    let Some(snapshot) = snapshot else {
+                        continue;
+                    };
+                    let data_source = DataSource::restore(
+                        snapshot,
+                        &self.rollup_config,
+                        self.max_logged_data_size,
+                        Sample { timestamp: 0, value: $default_val },
+                        |value| match value {
+                            $data_type(value) => Some(value),
+                            _ => None,
+                        }
+                    );
+                    match data_source {
+                        Ok(data_source) => {
+                            lock.insert(id, RwLock::new(data_source));
+                        },
+                        Err(err) => warn!("Failed to undo the restore of {}/{}: {}", self.manager.manager_id(), id, err),
+                    }
+                }
+            }
+
+            async fn start(&self) {
+                self.manager.on_start().await;
+            }
+
+            async fn polled_sources(&self) -> Vec<PolledSource> {
+                self.manager.polled_sources().await
+            }
+
+            async fn poll(&self, id: &str) -> Result<Sample<DataType>> {
+                let not_registered = || ManagerAndDataError::DataSourceManager(
+                    FlorustServerPluginError::DataSourceDoesntExist(id.to_string())
+                );
+
+                // Polling may take a while, so the data source is only locked once the value is in.
+                if !self.is_registered(id).await {
+                    return Err(not_registered());
+                }
+
+                let val = self.manager.poll(id).await.map_err(|e| self.manager_error(id, e))?;
+
+                let lock = self.logged_data.read().await;
+                let mut data_source = lock
+                    .get(id)
+                    .ok_or_else(not_registered)?
+                    .write()
+                    .await;
+
+                data_source.log(id, val, self.max_logged_data_size, $default_val, $data_type)
+            }
+
+            async fn tick(&self) -> Vec<(String, Result<Sample<DataType>>)> {
+                let values = self.manager.tick().await;
+                let lock = self.logged_data.read().await;
+
+                let mut logged = Vec::with_capacity(values.len());
+                for (id, value) in values {
+                    let result = match lock.get(&id) {
+                        Some(data_source) => data_source.write().await
+                            .log(&id, value, self.max_logged_data_size, $default_val, $data_type),
+                        None => Err(ManagerAndDataError::DataSourceManager(
+                            FlorustServerPluginError::DataSourceDoesntExist(id.clone())
+                        )),
+                    };
+                    logged.push((id, result));
+                }
+
+                logged
+            }
+
+            async fn health(&self) -> PluginHealth {
+                self.manager.health().await
+            }
+
+            async fn shutdown(&self) {
+                self.manager.on_shutdown().await;
+            }
+        }
+    };
+}
+
+manager_and_data_impl!(IIntegerManagerAndData, IIntegerDataManager, 0, DataType::IInteger);
+manager_and_data_impl!(UIntegerManagerAndData, UIntegerDataManager, 0, DataType::UInteger);
+manager_and_data_impl!(FloatManagerAndData, FloatDataManager, 0.0, DataType::Float);
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}};
+
+    use florust_common::{DataSourceMetadata, server::{self, DataSourceManager, DataSourceManagerError}};
+    use rocket::async_trait;
+
+    use super::{DataSource, DataSourceStatus, DataType, FloatManagerAndData, ManagerAndData, Sample, Series, now_millis};
+    use crate::{events::EventBus, import::{DuplicatePolicy, ImportOptions, ImportReport, ImportedRow, ImportedValue}, retention::RetentionConfig, rollup::RollupConfig, snapshot::DataSourceSnapshot};
+
+    /// What the manager was asked to do.
+    #[derive(Default)]
+    struct Calls {
+        decoded: AtomicUsize,
+        registrations: Mutex<Vec<String>>,
+    }
+
+    impl Calls {
+        fn registrations(&self) -> Vec<String> {
+            self.registrations.lock().unwrap().clone()
+        }
+    }
+
+    /// Decodes uploads as big endian floats, and refuses to register data sources whose id starts with
+    /// `broken`.
+    struct TestManager(Arc<Calls>);
+
+    impl TestManager {
+        fn registration(&self, call: String, id: &str) -> server::Result<()> {
+            if id.starts_with("broken") {
+                return Err(DataSourceManagerError::InvalidData(format!("{} is broken", id)));
+            }
+
+            self.0.registrations.lock().unwrap().push(call);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl DataSourceManager<f64> for TestManager {
+        fn manager_id(&self) -> &'static str {
+            "Test"
+        }
+
+        async fn register(&self, id: String) -> server::Result<()> {
+            self.registration(format!("register {}", id), &id)
+        }
+
+        async fn register_with_data(&self, id: String, data: &[u8]) -> server::Result<()> {
+            self.registration(format!("register {} with {:?}", id, data), &id)
+        }
+
+        async fn deregister(&self, id: &str) -> server::Result<()> {
+            self.registration(format!("deregister {}", id), id)
+        }
+
+        async fn deregister_with_data(&self, id: &str, data: &[u8]) -> server::Result<()> {
+            self.registration(format!("deregister {} with {:?}", id, data), id)
+        }
+
+        async fn update_data(&self, _id: &str, data: &[u8]) -> server::Result<f64> {
+            self.0.decoded.fetch_add(1, Ordering::SeqCst);
+            data.try_into()
+                .map(f64::from_be_bytes)
+                .map_err(|_| DataSourceManagerError::InvalidData("expected 8 bytes".to_string()))
+        }
+    }
+
+    async fn manager_and_data(calls: &Arc<Calls>) -> FloatManagerAndData {
+        let manager_and_data = FloatManagerAndData::new(
+            Box::new(TestManager(calls.clone())),
+            100,
+            RollupConfig::default(),
+            None,
+            EventBus::new()
+        );
+        manager_and_data.register("probe".to_string(), DataSourceMetadata::default(), None).await.unwrap();
+
+        manager_and_data
+    }
+
+    fn copy(snapshot: &DataSourceSnapshot) -> DataSourceSnapshot {
+        serde_json::from_value(serde_json::to_value(snapshot).unwrap()).unwrap()
+    }
+
+    fn data_row(line: u64, value: f64) -> ImportedRow {
+        ImportedRow { line, timestamp: line * 1000, value: ImportedValue::Data(value.to_be_bytes().to_vec()) }
+    }
+
+    fn options(dry_run: bool) -> ImportOptions {
+        ImportOptions { on_duplicate: DuplicatePolicy::Error, dry_run }
+    }
+
+    #[rocket::async_test]
+    async fn dry_runs_dont_decode() {
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = manager_and_data(&calls).await;
+
+        let report = manager_and_data.import("probe", vec![data_row(1, 1.5), data_row(2, 2.5)], &options(true)).await.unwrap();
+        assert_eq!(report.imported, 2);
+        assert_eq!(calls.decoded.load(Ordering::SeqCst), 0);
+        assert!(manager_and_data.get_samples("probe", 0, u64::MAX).await.unwrap().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn invalid_imports_dont_decode() {
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = manager_and_data(&calls).await;
+
+        let invalid_value = ImportedRow { line: 2, timestamp: 2000, value: ImportedValue::Value("warm".to_string()) };
+        let report = manager_and_data.import("probe", vec![data_row(1, 1.5), invalid_value], &options(false)).await.unwrap();
+        assert_eq!(report.errors.len(), 1);
+
+        let duplicate = ImportedRow { line: 2, timestamp: 1000, value: ImportedValue::Value("2.5".to_string()) };
+        let report = manager_and_data.import("probe", vec![data_row(1, 1.5), duplicate], &options(false)).await.unwrap();
+        assert_eq!(report.errors.len(), 1);
+
+        assert_eq!(calls.decoded.load(Ordering::SeqCst), 0);
+    }
+
+    #[rocket::async_test]
+    async fn valid_imports_are_decoded() {
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = manager_and_data(&calls).await;
+
+        let value = ImportedRow { line: 2, timestamp: 500, value: ImportedValue::Value("0.5".to_string()) };
+        let report = manager_and_data.import("probe", vec![data_row(1, 1.5), value, data_row(3, 3.5)], &options(false)).await.unwrap();
+        assert!(report.errors.is_empty());
+        assert_eq!(report.imported, 3);
+        assert_eq!(calls.decoded.load(Ordering::SeqCst), 2);
+
+        let samples = manager_and_data.get_samples("probe", 0, u64::MAX).await.unwrap();
+        assert_eq!(samples.len(), 3);
+    }
+
+    #[rocket::async_test]
+    async fn restores_register_with_the_data_sources_registered_with() {
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = manager_and_data(&calls).await;
+        manager_and_data.register_with_data("pump".to_string(), &[1, 2], DataSourceMetadata::default(), None).await.unwrap();
+        let mut snapshot = manager_and_data.snapshot().await;
+        snapshot.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let restored = FloatManagerAndData::new(
+            Box::new(TestManager(calls.clone())),
+            100,
+            RollupConfig::default(),
+            None,
+            EventBus::new()
+        );
+        restored.restore(snapshot, false).await.unwrap();
+
+        assert_eq!(calls.registrations()[2..], ["register probe", "register pump with [1, 2]"]);
+    }
+
+    #[rocket::async_test]
+    async fn failed_restores_change_nothing() {
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = manager_and_data(&calls).await;
+        manager_and_data.register("gauge".to_string(), DataSourceMetadata::default(), None).await.unwrap();
+        let mut snapshot = manager_and_data.snapshot().await;
+        snapshot.sort_by(|a, b| a.id.cmp(&b.id));
+        manager_and_data.deregister("probe").await.unwrap();
+
+        // The gauge is deregistered, and the probe registered again, before the broken data source fails.
+        for data_source in &mut snapshot {
+            data_source.registered = data_source.id == "probe";
+        }
+        let mut broken = copy(&snapshot[0]);
+        broken.id = "broken".to_string();
+        broken.registered = true;
+        snapshot.push(broken);
+
+        assert!(manager_and_data.restore(snapshot, true).await.is_err());
+        assert_eq!(
+            calls.registrations()[3..],
+            ["deregister gauge", "register probe", "deregister probe", "register gauge"]
+        );
+        assert!(!manager_and_data.is_registered("probe").await);
+        assert!(manager_and_data.is_registered("gauge").await);
+        assert!(manager_and_data.info("broken").await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn restores_are_undone() {
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = manager_and_data(&calls).await;
+        manager_and_data.update_data("probe", &1.5f64.to_be_bytes()).await.unwrap();
+        let snapshot = manager_and_data.snapshot().await;
+
+        let mut renamed = copy(&snapshot[0]);
+        renamed.id = "gauge".to_string();
+        manager_and_data.deregister("probe").await.unwrap();
+        let previous = manager_and_data.restore(vec![copy(&snapshot[0]), renamed], true).await.unwrap();
+        assert!(manager_and_data.is_registered("probe").await);
+        assert!(manager_and_data.is_registered("gauge").await);
+
+        manager_and_data.undo_restore(previous).await;
+        assert!(!manager_and_data.is_registered("probe").await);
+        assert_eq!(manager_and_data.info("probe").await.unwrap().logged_samples, 1);
+        assert!(manager_and_data.info("gauge").await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn deregistered_data_sources_are_kept_while_their_rollups_are() {
+        const DAY: u64 = 24 * 60 * 60 * 1000;
+
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = manager_and_data(&calls).await;
+        manager_and_data.update_data("probe", &1.5f64.to_be_bytes()).await.unwrap();
+        manager_and_data.deregister("probe").await.unwrap();
+
+        let policy = RetentionConfig { max_age_secs: Some(60), ..Default::default() };
+        let now = now_millis();
+        manager_and_data.apply_retention(&policy, now + 2 * DAY).await;
+        let info = manager_and_data.info("probe").await.unwrap();
+        assert_eq!(info.logged_samples, 0);
+
+        // Day rollups are kept for 5 years by default.
+        manager_and_data.apply_retention(&policy, now + 6 * 365 * DAY).await;
+        assert!(manager_and_data.info("probe").await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn deregistered_data_sources_are_removed_once_kept_for_long_enough() {
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = manager_and_data(&calls).await;
+        manager_and_data.update_data("probe", &1.5f64.to_be_bytes()).await.unwrap();
+        manager_and_data.register("gauge".to_string(), DataSourceMetadata::default(), None).await.unwrap();
+        manager_and_data.deregister("probe").await.unwrap();
+
+        let policy = RetentionConfig { keep_deregistered_for_secs: Some(60), ..Default::default() };
+        manager_and_data.apply_retention(&policy, now_millis() + 61 * 1000).await;
+        assert!(manager_and_data.info("probe").await.is_err());
+        assert!(manager_and_data.is_registered("gauge").await);
+    }
+
+    #[rocket::async_test]
+    async fn purged_data_sources_start_over() {
+        let calls = Arc::new(Calls::default());
+        let manager_and_data = FloatManagerAndData::new(
+            Box::new(TestManager(calls.clone())),
+            2,
+            RollupConfig::default(),
+            None,
+            EventBus::new()
+        );
+        manager_and_data.register("probe".to_string(), DataSourceMetadata::default(), None).await.unwrap();
+        for value in [1.5f64, 2.5, 3.5] {
+            manager_and_data.update_data("probe", &value.to_be_bytes()).await.unwrap();
+        }
+        manager_and_data.record_dropped("probe").await;
+
+        manager_and_data.purge("probe").await.unwrap();
+        let info = manager_and_data.info("probe").await.unwrap();
+        assert!(info.registered);
+        assert_eq!((info.logged_samples, info.dropped_samples, info.last_seen), (0, 0, None));
+
+        // Were the data source still truncated, the new sample would only be served from the rollups.
+        manager_and_data.update_data("probe", &4.5f64.to_be_bytes()).await.unwrap();
+        match manager_and_data.get_range("probe", 0, u64::MAX).await.unwrap() {
+            Series::Raw(samples) => assert_eq!(samples.len(), 1),
+            Series::Rollup { .. } => panic!("expected the logged samples"),
+        }
+    }
+
+    fn samples(rows: &[(u64, f64)]) -> Vec<(u64, Sample<f64>)> {
+        rows.iter()
+            .enumerate()
+            .map(|(index, (timestamp, value))| (index as u64 + 1, Sample { timestamp: *timestamp, value: *value }))
+            .collect()
+    }
+
+    fn import_into(data_source: &mut DataSource<f64>, rows: &[(u64, f64)], on_duplicate: DuplicatePolicy, max_size: usize) -> ImportReport {
+        let options = ImportOptions { on_duplicate, dry_run: false };
+        data_source.import(samples(rows), &options, max_size, Sample { timestamp: 0, value: 0.0 }, DataType::Float)
+    }
+
+    /// A data source which logged a sample at 1000 and at 2000.
+    fn logged_data_source() -> DataSource<f64> {
+        let mut data_source = DataSource::new(&RollupConfig::default(), DataSourceMetadata::default(), None);
+        import_into(&mut data_source, &[(2000, 2.0), (1000, 1.0)], DuplicatePolicy::Error, 10);
+        data_source
+    }
+
+    fn logged(data_source: &DataSource<f64>) -> Vec<(u64, f64)> {
+        data_source.status.samples_in_range(0, u64::MAX).iter().map(|sample| (sample.timestamp, sample.value)).collect()
+    }
+
+    /// Rows reusing the timestamp of a logged sample, and of each other.
+    const DUPLICATES: [(u64, f64); 3] = [(2000, 20.0), (3000, 3.0), (3000, 30.0)];
+
+    #[test]
+    fn imports_skip_duplicates_by_default() {
+        let mut data_source = logged_data_source();
+
+        let report = import_into(&mut data_source, &DUPLICATES, DuplicatePolicy::Skip, 10);
+        assert_eq!((report.imported, report.skipped, report.replaced), (1, 2, 0));
+        assert_eq!(logged(&data_source), [(1000, 1.0), (2000, 2.0), (3000, 3.0)]);
+    }
+
+    #[test]
+    fn imports_may_replace_duplicates() {
+        let mut data_source = logged_data_source();
+
+        let report = import_into(&mut data_source, &DUPLICATES, DuplicatePolicy::Replace, 10);
+        assert_eq!((report.imported, report.skipped, report.replaced), (2, 1, 1));
+        assert_eq!(logged(&data_source), [(1000, 1.0), (2000, 20.0), (3000, 30.0)]);
+    }
+
+    #[test]
+    fn imports_may_reject_duplicates() {
+        let mut data_source = logged_data_source();
+
+        let report = import_into(&mut data_source, &DUPLICATES, DuplicatePolicy::Error, 10);
+        let lines: Vec<u64> = report.errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, [3, 1]);
+        assert_eq!(logged(&data_source), [(1000, 1.0), (2000, 2.0)]);
+    }
+
+    #[test]
+    fn imports_are_merged_in_order_and_evict_the_oldest_samples() {
+        let mut data_source = logged_data_source();
+        let rows = [(5000, 5.0), (500, 0.5), (1500, 1.5)];
+
+        let options = ImportOptions { on_duplicate: DuplicatePolicy::Skip, dry_run: true };
+        let report = data_source.import(samples(&rows), &options, 3, Sample { timestamp: 0, value: 0.0 }, DataType::Float);
+        assert_eq!((report.imported, report.evicted), (3, 2));
+        assert_eq!(logged(&data_source), [(1000, 1.0), (2000, 2.0)]);
+        assert!(!data_source.truncated);
+
+        import_into(&mut data_source, &rows, DuplicatePolicy::Skip, 3);
+        assert_eq!(logged(&data_source), [(1500, 1.5), (2000, 2.0), (5000, 5.0)]);
+        assert!(data_source.truncated);
+        assert!(matches!(data_source.status, DataSourceStatus::Registered(_)));
+    }
+}
@@ -0,0 +1,746 @@
+use std::{collections::{BTreeMap, HashMap, VecDeque}, process::Stdio, sync::Arc, time::Duration};
+
+use chrono::{Local, NaiveTime};
+use hmac::{Hmac, Mac};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{info, warn};
+use rocket::{async_trait, fairing::AdHoc, serde::{Serialize, Deserialize}, tokio::{self, sync::broadcast::{Receiver, error::RecvError}, time::{interval, sleep}}};
+use sha2::Sha256;
+use tera::{Context, Tera};
+use thiserror::Error;
+
+use crate::{events::{EventBus, ServerEvent}, manager_and_data::now_millis};
+
+#[derive(Error, Debug)]
+pub enum NotificationError {
+    #[error("Failed to render template: {0}")]
+    Template(#[from] tera::Error),
+    #[error("Failed to send webhook: {0}")]
+    Webhook(#[from] reqwest::Error),
+    #[error("Webhook responded with status {0}")]
+    WebhookStatus(u16),
+    #[error("Failed to send email: {0}")]
+    Email(String),
+    #[error("Failed to run command: {0}")]
+    Command(#[from] std::io::Error),
+    #[error("Command exited with {0}")]
+    CommandStatus(std::process::ExitStatus),
+}
+
+/// A destination notifications about server events are delivered to.
+#[async_trait]
+pub trait NotificationSink: Send + Sync {
+    /// Delivers a notification about the event. Failed deliveries are retried by the caller.
+    async fn send(&self, event: &ServerEvent) -> Result<(), NotificationError>;
+}
+
+/// Renders a template with the fields of the event, along with its `kind` and `summary`.
+fn render(template: &str, event: &ServerEvent) -> Result<String, NotificationError> {
+    let mut context = Context::from_serialize(event)?;
+    context.insert("kind", event.kind());
+    context.insert("summary", &event.summary());
+
+    Ok(Tera::one_off(template, &context, false)?)
+}
+
+/// Signs a webhook request, as the hex encoded HMAC-SHA256 of the timestamp and body, joined by a `.`, keyed
+/// with the webhook's secret. Signing the timestamp lets receivers reject replayed requests.
+fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// POSTs the event to a URL, as JSON unless a body template is given. If the webhook has a secret, requests
+/// are signed, see [`sign`].
+pub struct WebhookSink {
+    url: String,
+    headers: BTreeMap<String, String>,
+    body: Option<String>,
+    secret: Option<String>,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl NotificationSink for WebhookSink {
+    async fn send(&self, event: &ServerEvent) -> Result<(), NotificationError> {
+        let mut request = self.client.post(&self.url)
+            .header("X-Florust-Event", event.kind());
+
+        let body = match &self.body {
+            Some(template) => render(template, event)?,
+            None => {
+                request = request.header("Content-Type", "application/json");
+                serde_json::to_string(event).unwrap_or_default()
+            },
+        };
+
+        if let Some(secret) = &self.secret {
+            let timestamp = now_millis();
+            request = request
+                .header("X-Florust-Timestamp", timestamp)
+                .header("X-Florust-Signature", format!("sha256={}", sign(secret, timestamp, &body)));
+        }
+
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.body(body).send().await?;
+        if !response.status().is_success() {
+            return Err(NotificationError::WebhookStatus(response.status().as_u16()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Emails the event through an SMTP relay, without TLS or authentication, as offered by a local mail
+/// transfer agent.
+pub struct EmailSink {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+    to: Vec<String>,
+    subject: String,
+    body: String,
+}
+
+#[async_trait]
+impl NotificationSink for EmailSink {
+    async fn send(&self, event: &ServerEvent) -> Result<(), NotificationError> {
+        let parse_address = |address: &str| address.parse().map_err(|err| {
+            NotificationError::Email(format!("invalid address {}: {}", address, err))
+        });
+
+        let mut message = Message::builder()
+            .from(parse_address(&self.from)?)
+            .subject(render(&self.subject, event)?);
+        for to in &self.to {
+            message = message.to(parse_address(to)?);
+        }
+
+        let message = message.body(render(&self.body, event)?)
+            .map_err(|err| NotificationError::Email(err.to_string()))?;
+        self.transport.send(message).await
+            .map_err(|err| NotificationError::Email(err.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Runs a local command for the event. The event is passed as JSON in the `FLORUST_EVENT` environment
+/// variable, along with its kind in `FLORUST_EVENT_KIND` and its summary in `FLORUST_EVENT_SUMMARY`.
+pub struct CommandSink {
+    program: String,
+    args: Vec<String>,
+}
+
+#[async_trait]
+impl NotificationSink for CommandSink {
+    async fn send(&self, event: &ServerEvent) -> Result<(), NotificationError> {
+        let json = serde_json::to_string(event).unwrap_or_default();
+
+        let status = tokio::process::Command::new(&self.program)
+            .args(&self.args)
+            .env("FLORUST_EVENT", json)
+            .env("FLORUST_EVENT_KIND", event.kind())
+            .env("FLORUST_EVENT_SUMMARY", event.summary())
+            .stdin(Stdio::null())
+            .status()
+            .await?;
+
+        if !status.success() {
+            return Err(NotificationError::CommandStatus(status));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_smtp_port() -> u16 { 25 }
+
+fn default_subject() -> String { "[Florust] {{ summary }}".to_string() }
+
+fn default_email_body() -> String { "{{ summary }}".to_string() }
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum SinkKind {
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        /// Tera template of the body, the event is sent as JSON if none is given.
+        body: Option<String>,
+        /// Key requests are signed with, requests aren't signed if none is given.
+        secret: Option<String>,
+    },
+    Email {
+        server: String,
+        #[serde(default = "default_smtp_port")]
+        port: u16,
+        from: String,
+        to: Vec<String>,
+        /// Tera template of the subject.
+        #[serde(default = "default_subject")]
+        subject: String,
+        /// Tera template of the body.
+        #[serde(default = "default_email_body")]
+        body: String,
+    },
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+impl SinkKind {
+    fn build(&self) -> Box<dyn NotificationSink> {
+        match self.clone() {
+            SinkKind::Webhook { url, headers, body, secret } => Box::new(WebhookSink {
+                url,
+                headers,
+                body,
+                secret,
+                client: reqwest::Client::new(),
+            }),
+            SinkKind::Email { server, port, from, to, subject, body } => Box::new(EmailSink {
+                transport: AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(server).port(port).build(),
+                from,
+                to,
+                subject,
+                body,
+            }),
+            SinkKind::Command { program, args } => Box::new(CommandSink { program, args }),
+        }
+    }
+}
+
+/// (De)serializes times of day as `HH:MM`, or `HH:MM:SS`.
+mod time_of_day {
+    use chrono::NaiveTime;
+    use rocket::serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time.format("%H:%M:%S"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&time, "%H:%M:%S")
+            .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M"))
+            .map_err(|err| D::Error::custom(format!("invalid time of day {}: {}", time, err)))
+    }
+}
+
+/// A time of day window, in local time, during which notifications are held back. The window may wrap
+/// around midnight, like 22:00 to 07:00.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct QuietHours {
+    #[serde(with = "time_of_day")]
+    pub start: NaiveTime,
+    #[serde(with = "time_of_day")]
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        }
+        else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+fn default_dedup() -> bool { true }
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    /// Kinds of events delivered to the sink, every kind if empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Events happening during quiet hours are delivered once the quiet hours end.
+    pub quiet_hours: Option<QuietHours>,
+    /// Whether repeats of an event are dropped, systems tracking the state of data sources want every event.
+    #[serde(default = "default_dedup")]
+    pub dedup: bool,
+}
+
+fn default_dedup_window_secs() -> u64 { 300 }
+
+fn default_max_attempts() -> u32 { 5 }
+
+fn default_initial_backoff_ms() -> u64 { 1000 }
+
+fn default_max_backoff_ms() -> u64 { 60 * 1000 }
+
+fn default_max_deferred() -> usize { 100 }
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// Repeats of an event within this many seconds of the first are dropped.
+    #[serde(default = "default_dedup_window_secs")]
+    pub dedup_window_secs: u64,
+    /// Attempts made to deliver a notification to a sink before giving up.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed retry.
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Events a sink holds back during quiet hours, the oldest are dropped once it holds back more.
+    #[serde(default = "default_max_deferred")]
+    pub max_deferred: usize,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            sinks: Vec::new(),
+            dedup_window_secs: default_dedup_window_secs(),
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            max_deferred: default_max_deferred(),
+        }
+    }
+}
+
+struct Sink {
+    sink: Arc<dyn NotificationSink>,
+    config: SinkConfig,
+    /// Events held back during quiet hours, oldest first.
+    deferred: VecDeque<ServerEvent>,
+    /// Events dropped since the quiet hours began, because too many were held back.
+    dropped: usize,
+}
+
+impl Sink {
+    fn wants(&self, event: &ServerEvent) -> bool {
+        self.config.events.is_empty() || self.config.events.iter().any(|kind| kind == event.kind())
+    }
+
+    fn is_quiet(&self) -> bool {
+        self.config.quiet_hours.is_some_and(|quiet_hours| quiet_hours.contains(Local::now().time()))
+    }
+
+    /// Holds back an event until the quiet hours end, dropping the oldest held back events once there are more
+    /// than `max_deferred` of them.
+    fn defer(&mut self, event: ServerEvent, max_deferred: usize) {
+        self.deferred.push_back(event);
+        while self.deferred.len() > max_deferred {
+            self.deferred.pop_front();
+            self.dropped += 1;
+        }
+    }
+}
+
+/// When an event was first seen, and the data source it's about, for deduplication.
+struct Seen {
+    at: u64,
+    data_source: Option<(String, String)>,
+}
+
+/// Delivers server events to the configured sinks.
+pub struct Notifier {
+    events: Receiver<ServerEvent>,
+    sinks: Vec<Sink>,
+    config: NotificationsConfig,
+    /// Recently delivered events, by their deduplication key.
+    recent: HashMap<String, Seen>,
+}
+
+impl Notifier {
+    /// Creates the notifier, subscribing to the event bus right away, so no event emitted from now on is
+    /// missed, even before the notifier is started.
+    pub fn new(config: NotificationsConfig, events: &EventBus) -> Notifier {
+        Notifier {
+            events: events.subscribe(),
+            sinks: config.sinks.iter()
+                .map(|sink| Sink {
+                    sink: Arc::from(sink.kind.build()),
+                    config: sink.clone(),
+                    deferred: VecDeque::new(),
+                    dropped: 0,
+                })
+                .collect(),
+            config,
+            recent: HashMap::new(),
+        }
+    }
+
+    /// Whether the event was already seen within the deduplication window. Registering or deregistering a
+    /// data source forgets the other events seen for it, so it being deregistered again, or going stale again,
+    /// isn't mistaken for a repeat.
+    fn is_duplicate(&mut self, event: &ServerEvent, now: u64) -> bool {
+        let window = self.config.dedup_window_secs.saturating_mul(1000);
+        self.recent.retain(|_, seen| now.saturating_sub(seen.at) < window);
+
+        let key = event.dedup_key();
+        if self.recent.contains_key(&key) {
+            return true;
+        }
+
+        let data_source = event.data_source().map(|(manager_id, data_source_id)| {
+            (manager_id.to_string(), data_source_id.to_string())
+        });
+        if matches!(event, ServerEvent::DataSourceRegistered { .. } | ServerEvent::DataSourceDeregistered { .. }) {
+            self.recent.retain(|_, seen| seen.data_source != data_source);
+        }
+
+        self.recent.insert(key, Seen { at: now, data_source });
+        false
+    }
+
+    /// Delivers an event to a sink in the background, retrying with exponential backoff.
+    fn deliver(&self, sink: Arc<dyn NotificationSink>, event: ServerEvent) {
+        let config = self.config.clone();
+
+        tokio::spawn(async move {
+            let mut backoff = Duration::from_millis(config.initial_backoff_ms);
+            for attempt in 1..=config.max_attempts.max(1) {
+                match sink.send(&event).await {
+                    Ok(()) => return,
+                    Err(err) if attempt < config.max_attempts => {
+                        warn!("Failed to deliver notification ({}), retrying in {:?}: {}", event.kind(), backoff, err);
+                        sleep(backoff).await;
+                        backoff = (backoff * 2).min(Duration::from_millis(config.max_backoff_ms));
+                    },
+                    Err(err) => warn!("Giving up on delivering notification ({}): {}", event.kind(), err),
+                }
+            }
+        });
+    }
+
+    fn dispatch(&mut self, event: ServerEvent) {
+        let duplicate = self.is_duplicate(&event, now_millis());
+
+        for index in 0..self.sinks.len() {
+            let sink = &mut self.sinks[index];
+            if !sink.wants(&event) || (duplicate && sink.config.dedup) {
+                continue;
+            }
+
+            if sink.is_quiet() {
+                sink.defer(event.clone(), self.config.max_deferred);
+            }
+            else {
+                self.deliver(Arc::clone(&self.sinks[index].sink), event.clone());
+            }
+        }
+    }
+
+    /// Delivers the events sinks held back during quiet hours which have since ended.
+    fn flush_deferred(&mut self) {
+        for index in 0..self.sinks.len() {
+            if self.sinks[index].is_quiet() || self.sinks[index].deferred.is_empty() {
+                continue;
+            }
+
+            let deferred = std::mem::take(&mut self.sinks[index].deferred);
+            let dropped = std::mem::take(&mut self.sinks[index].dropped);
+            if dropped > 0 {
+                warn!("Too many notifications were held back during quiet hours, dropped the {} oldest", dropped);
+            }
+            info!("Quiet hours ended, delivering {} held back notifications", deferred.len());
+            for event in deferred {
+                self.deliver(Arc::clone(&self.sinks[index].sink), event);
+            }
+        }
+    }
+
+    async fn run(mut self) {
+        let mut quiet_hours_check = interval(Duration::from_secs(60));
+
+        loop {
+            tokio::select! {
+                event = self.events.recv() => match event {
+                    Ok(event) => self.dispatch(event),
+                    Err(RecvError::Lagged(missed)) => warn!("Notifier fell behind, {} events weren't notified", missed),
+                    Err(RecvError::Closed) => return,
+                },
+                _ = quiet_hours_check.tick() => self.flush_deferred(),
+            }
+        }
+    }
+}
+
+/// Fairing that starts delivering notifications once the server launched.
+pub fn notifier(notifier: Notifier) -> AdHoc {
+    AdHoc::on_liftoff("Notifier", |_| Box::pin(async move {
+        if notifier.sinks.is_empty() {
+            return;
+        }
+
+        info!("Starting notifier, delivering to {} sinks", notifier.sinks.len());
+        tokio::spawn(notifier.run());
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener, thread};
+
+    use super::*;
+
+    fn deregistered(data_source_id: &str) -> ServerEvent {
+        ServerEvent::DataSourceDeregistered {
+            manager_id: "manager".to_string(),
+            data_source_id: data_source_id.to_string(),
+        }
+    }
+
+    fn registered(data_source_id: &str) -> ServerEvent {
+        ServerEvent::DataSourceRegistered {
+            manager_id: "manager".to_string(),
+            data_source_id: data_source_id.to_string(),
+            metadata: Default::default(),
+        }
+    }
+
+    fn plugin_load_failed() -> ServerEvent {
+        ServerEvent::PluginLoadFailed { plugin: "plugin".to_string(), error: "error".to_string() }
+    }
+
+    fn notifier(config: NotificationsConfig) -> Notifier {
+        Notifier::new(config, &EventBus::new())
+    }
+
+    #[test]
+    fn repeats_are_duplicates_within_the_window() {
+        let mut notifier = notifier(NotificationsConfig { dedup_window_secs: 10, ..Default::default() });
+
+        assert!(!notifier.is_duplicate(&plugin_load_failed(), 0));
+        assert!(notifier.is_duplicate(&plugin_load_failed(), 5_000));
+        assert!(!notifier.is_duplicate(&deregistered("other"), 5_000));
+        assert!(!notifier.is_duplicate(&plugin_load_failed(), 10_000));
+    }
+
+    #[test]
+    fn registering_again_isnt_a_repeat() {
+        let mut notifier = notifier(NotificationsConfig::default());
+
+        assert!(!notifier.is_duplicate(&deregistered("source"), 0));
+        assert!(!notifier.is_duplicate(&registered("source"), 1_000));
+        assert!(!notifier.is_duplicate(&deregistered("source"), 2_000));
+        assert!(notifier.is_duplicate(&deregistered("source"), 3_000));
+        assert!(!notifier.is_duplicate(&registered("other"), 3_000));
+        assert!(notifier.is_duplicate(&deregistered("source"), 4_000));
+    }
+
+    #[test]
+    fn quiet_hours_may_wrap_around_midnight() {
+        let time = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+        let day = QuietHours { start: time(9), end: time(17) };
+        let night = QuietHours { start: time(22), end: time(7) };
+
+        assert!(day.contains(time(9)) && day.contains(time(16)));
+        assert!(!day.contains(time(17)) && !day.contains(time(8)));
+        assert!(night.contains(time(23)) && night.contains(time(0)) && night.contains(time(6)));
+        assert!(!night.contains(time(7)) && !night.contains(time(21)));
+    }
+
+    #[test]
+    fn only_the_latest_deferred_events_are_kept() {
+        let config = SinkConfig {
+            kind: SinkKind::Command { program: "true".to_string(), args: Vec::new() },
+            events: Vec::new(),
+            quiet_hours: None,
+            dedup: true,
+        };
+        let mut sink = Sink {
+            sink: Arc::from(config.kind.build()),
+            config,
+            deferred: VecDeque::new(),
+            dropped: 0,
+        };
+
+        for data_source_id in ["a", "b", "c"] {
+            sink.defer(deregistered(data_source_id), 2);
+        }
+
+        let kept: Vec<_> = sink.deferred.iter().map(|event| event.data_source().unwrap().1).collect();
+        assert_eq!(kept, ["b", "c"]);
+        assert_eq!(sink.dropped, 1);
+    }
+
+    /// Accepts a single HTTP request, responding with the status, and returns the request's head and body.
+    fn http_stand_in(status: u16) -> (String, thread::JoinHandle<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line.to_lowercase());
+            }
+
+            let length = head.lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .map_or(0, |length| length.trim().parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            write!(reader.get_mut(), "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+
+        (url, handle)
+    }
+
+    fn webhook(url: String, body: Option<String>, secret: Option<String>) -> WebhookSink {
+        WebhookSink {
+            url,
+            headers: BTreeMap::from([("X-Extra".to_string(), "extra".to_string())]),
+            body,
+            secret,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn webhooks_post_the_event() {
+        let (url, stand_in) = http_stand_in(200);
+        webhook(url, None, None).send(&plugin_load_failed()).await.unwrap();
+
+        let (head, body) = stand_in.join().unwrap();
+        assert!(head.starts_with("post /hook "));
+        assert!(head.contains("x-florust-event: plugin_load_failed\r\n"));
+        assert!(head.contains("content-type: application/json\r\n"));
+        assert!(head.contains("x-extra: extra\r\n"));
+        assert!(!head.contains("x-florust-signature"));
+        assert_eq!(body, serde_json::to_string(&plugin_load_failed()).unwrap());
+    }
+
+    #[rocket::async_test]
+    async fn webhooks_sign_rendered_bodies() {
+        let (url, stand_in) = http_stand_in(200);
+        let sink = webhook(url, Some("{{ kind }}: {{ summary }}".to_string()), Some("secret".to_string()));
+        sink.send(&plugin_load_failed()).await.unwrap();
+
+        let (head, body) = stand_in.join().unwrap();
+        assert_eq!(body, "plugin_load_failed: Plugin plugin failed to load: error");
+
+        let header = |name: &str| head.lines()
+            .find_map(|line| line.strip_prefix(name))
+            .unwrap()
+            .to_string();
+        let timestamp: u64 = header("x-florust-timestamp: ").parse().unwrap();
+        assert_eq!(header("x-florust-signature: "), format!("sha256={}", sign("secret", timestamp, &body)));
+    }
+
+    #[rocket::async_test]
+    async fn webhooks_fail_on_error_statuses() {
+        let (url, stand_in) = http_stand_in(500);
+        let result = webhook(url, None, None).send(&plugin_load_failed()).await;
+
+        stand_in.join().unwrap();
+        assert!(matches!(result, Err(NotificationError::WebhookStatus(500))));
+    }
+
+    /// Accepts a single SMTP session, and returns the envelope's recipients and the message's data.
+    fn smtp_stand_in() -> (u16, thread::JoinHandle<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let (mut recipients, mut data) = (Vec::new(), String::new());
+
+            writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+
+                let command = line.trim_end().to_uppercase();
+                if command.starts_with("RCPT TO:") {
+                    recipients.push(line.trim_end()[8..].to_string());
+                }
+
+                let reply: &[u8] = if command.starts_with("DATA") {
+                    writer.write_all(b"354 Go ahead\r\n").unwrap();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line == ".\r\n" {
+                            break;
+                        }
+                        data.push_str(&line);
+                    }
+                    b"250 Queued\r\n"
+                }
+                else if command.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").unwrap();
+                    break;
+                }
+                else {
+                    b"250 OK\r\n"
+                };
+                writer.write_all(reply).unwrap();
+            }
+
+            (recipients, data)
+        });
+
+        (port, handle)
+    }
+
+    #[rocket::async_test]
+    async fn emails_are_sent_through_the_relay() {
+        let (port, stand_in) = smtp_stand_in();
+        let sink = EmailSink {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1").port(port).build(),
+            from: "florust@example.com".to_string(),
+            to: vec!["a@example.com".to_string(), "b@example.com".to_string()],
+            subject: default_subject(),
+            body: "Kind: {{ kind }}".to_string(),
+        };
+        sink.send(&plugin_load_failed()).await.unwrap();
+        drop(sink);
+
+        let (recipients, data) = stand_in.join().unwrap();
+        assert_eq!(recipients, ["<a@example.com>", "<b@example.com>"]);
+        assert!(data.contains("Subject: [Florust] Plugin plugin failed to load: error\r\n"));
+        assert!(data.contains("From: florust@example.com\r\n"));
+        assert!(data.ends_with("\r\n\r\nKind: plugin_load_failed\r\n"));
+    }
+
+    #[rocket::async_test]
+    async fn commands_get_the_event_in_their_environment() {
+        let sink = |script: &str| CommandSink {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+        };
+
+        sink(r#"test "$FLORUST_EVENT_KIND" = plugin_load_failed && test -n "$FLORUST_EVENT""#)
+            .send(&plugin_load_failed()).await.unwrap();
+        let result = sink("exit 3").send(&plugin_load_failed()).await;
+        assert!(matches!(result, Err(NotificationError::CommandStatus(status)) if status.code() == Some(3)));
+    }
+}
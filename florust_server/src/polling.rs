@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use florust_common::server::PolledSource;
+use log::{info, warn};
+use rocket::{serde::{Serialize, Deserialize}, tokio::{self, time::sleep}};
+
+use crate::{BackgroundState, FlorustState, observe_sample, acl::SourcePattern};
+
+fn default_interval_secs() -> u64 { 60 }
+fn default_max_backoff_secs() -> u64 { 600 }
+
+/// Poll interval of the data sources matching a pattern, overriding the interval declared by their manager.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PollIntervalConfig {
+    /// Pattern of the data sources the interval applies to, see [`SourcePattern`].
+    pub sources: String,
+    pub interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PollingConfig {
+    /// Interval of polled data sources whose manager doesn't declare one, in seconds.
+    #[serde(default = "default_interval_secs")]
+    pub default_interval_secs: u64,
+    /// Longest time between polls of a data source which keeps failing, in seconds.
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    /// Intervals overriding those declared by managers. The first matching entry is used.
+    #[serde(default)]
+    pub intervals: Vec<PollIntervalConfig>,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        PollingConfig {
+            default_interval_secs: default_interval_secs(),
+            max_backoff_secs: default_max_backoff_secs(),
+            intervals: Vec::new(),
+        }
+    }
+}
+
+impl PollingConfig {
+    /// Returns how often a data source is polled, in seconds.
+    fn interval_secs(&self, manager_id: &str, source: &PolledSource) -> u64 {
+        self.intervals.iter()
+            .find(|interval| SourcePattern::new(&interval.sources).matches(manager_id, &source.id))
+            .map(|interval| interval.interval_secs)
+            .or(source.interval_secs)
+            .unwrap_or(self.default_interval_secs)
+            .max(1)
+    }
+}
+
+/// Delay before polling a data source again, after `failures` polls in a row failed. The delay doubles with
+/// every failure, up to `max_backoff_secs`, but is never shorter than the interval.
+fn backoff(interval_secs: u64, failures: u32, max_backoff_secs: u64) -> Duration {
+    let backoff = interval_secs.saturating_mul(1 << failures.min(16)).min(max_backoff_secs);
+    Duration::from_secs(backoff.max(interval_secs))
+}
+
+/// Registers the data sources every manager polls, if they aren't already, and spawns a background task per
+/// data source polling it on its interval. The tasks stop once the server stops accepting uploads.
+pub async fn spawn_pollers(state: &FlorustState) {
+    for (manager_id, manager) in state.managers_and_data.iter() {
+        for source in manager.polled_sources().await {
+            let interval_secs = state.polling.interval_secs(manager_id, &source);
+
+            if !manager.is_registered(&source.id).await {
+                let registered = manager.register(source.id.clone(), source.metadata.clone(), Some(interval_secs)).await;
+                if let Err(err) = registered {
+                    warn!("Failed to register polled data source {}/{}: {}", manager_id, source.id, err);
+                    continue;
+                }
+            }
+
+            info!("Polling {}/{} every {} seconds", manager_id, source.id, interval_secs);
+            tokio::spawn(poll_source(state.background(), manager_id, source.id, interval_secs, state.polling.max_backoff_secs));
+        }
+    }
+}
+
+async fn poll_source(state: BackgroundState, manager_id: &'static str, data_source_id: String, interval_secs: u64, max_backoff_secs: u64) {
+    let Some(manager) = state.managers_and_data.get(manager_id) else {
+        return;
+    };
+
+    let mut failures = 0;
+    loop {
+        let Ok(upload) = state.uploads.enter().await else {
+            return;
+        };
+
+        let polled = match manager.poll(&data_source_id).await {
+            Ok(sample) => {
+                observe_sample(&state.alerts, &state.automations, manager, &data_source_id, sample).await;
+                Ok(())
+            },
+            Err(err) => Err(err),
+        };
+        drop(upload);
+
+        match polled {
+            Ok(()) if failures > 0 => {
+                info!("Polled {}/{} again, after {} failed polls", manager_id, data_source_id, failures);
+                failures = 0;
+            },
+            Ok(()) => (),
+            Err(err) => {
+                if failures == 0 {
+                    warn!("Failed to poll {}/{}, polling it less often until it succeeds: {}", manager_id, data_source_id, err);
+                }
+                failures += 1;
+            },
+        }
+
+        sleep(backoff(interval_secs, failures, max_backoff_secs)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use florust_common::server::PolledSource;
+
+    use super::{PollIntervalConfig, PollingConfig, backoff};
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let delays: Vec<u64> = (0..6).map(|failures| backoff(30, failures, 600).as_secs()).collect();
+        assert_eq!(delays, [30, 60, 120, 240, 480, 600]);
+        assert_eq!(backoff(30, u32::MAX, 600), Duration::from_secs(600));
+        assert_eq!(backoff(u64::MAX, 3, u64::MAX), Duration::from_secs(u64::MAX));
+    }
+
+    #[test]
+    fn backoff_is_never_shorter_than_the_interval() {
+        assert_eq!(backoff(120, 0, 60), Duration::from_secs(120));
+        assert_eq!(backoff(120, 4, 60), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn configured_intervals_override_declared_ones() {
+        let config = PollingConfig {
+            default_interval_secs: 45,
+            intervals: vec![
+                PollIntervalConfig { sources: "Sysfs/w1-*".to_string(), interval_secs: 10 },
+                PollIntervalConfig { sources: "Sysfs".to_string(), interval_secs: 0 },
+            ],
+            ..Default::default()
+        };
+        let source = |id: &str, interval_secs| PolledSource { id: id.to_string(), metadata: Default::default(), interval_secs };
+
+        assert_eq!(config.interval_secs("Sysfs", &source("w1-28-0000", Some(300))), 10);
+        assert_eq!(config.interval_secs("Sysfs", &source("hwmon-lm75", Some(300))), 1);
+        assert_eq!(config.interval_secs("Modbus", &source("tank", Some(300))), 300);
+        assert_eq!(config.interval_secs("Modbus", &source("tank", None)), 45);
+    }
+}
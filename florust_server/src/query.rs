@@ -0,0 +1,770 @@
+//! A small query language for combining the data of several data sources.
+//!
+//! A query is an arithmetic expression over series. `select(manager="float", source="pot-*", bed="3")`
+//! selects the data of every matching data source, any key besides `manager` and `source` matching a tag.
+//! Functions like `avg(...)` or `derivative(...)` transform series, and `+`, `-`, `*` and `/` combine series
+//! with each other or with numbers. Every series is split into the same time buckets before evaluation, so
+//! series are always joined on their buckets.
+
+use std::{collections::BTreeMap, iter::Peekable, str::CharIndices};
+
+use rocket::serde::{Serialize, Deserialize};
+use wildmatch::WildMatch;
+
+use crate::{aggregate::MAX_BUCKETS, manager_and_data::{ManagerAndDataError, Sample}};
+
+/// Number of buckets of queries which don't say where their range starts.
+pub const DEFAULT_BUCKETS: u64 = 60;
+
+/// Longest query accepted, in bytes.
+const MAX_QUERY_LENGTH: usize = 1024;
+
+/// How deeply parentheses, negations and function calls may be nested, as the parser recurses into them.
+const MAX_DEPTH: usize = 64;
+
+fn invalid(message: impl Into<String>) -> ManagerAndDataError {
+    ManagerAndDataError::InvalidQuery(message.into())
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(f64),
+    LParen,
+    RParen,
+    Comma,
+    Eq,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, ManagerAndDataError> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<CharIndices> = query.char_indices().peekable();
+
+    while let Some((position, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '=' => Token::Eq,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => string.push(c),
+                        None => return Err(invalid(format!("unterminated string starting at {}", position))),
+                    }
+                }
+                Token::Str(string)
+            },
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = position + c.len_utf8();
+                while let Some((next, c)) = chars.peek().copied() {
+                    if !(c.is_ascii_digit() || c == '.') {
+                        break;
+                    }
+                    end = next + c.len_utf8();
+                    chars.next();
+                }
+
+                let number = &query[position..end];
+                Token::Number(number.parse().map_err(|_| invalid(format!("invalid number: {}", number)))?)
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = position + c.len_utf8();
+                while let Some((next, c)) = chars.peek().copied() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = next + c.len_utf8();
+                    chars.next();
+                }
+                Token::Ident(query[position..end].to_string())
+            },
+            c => return Err(invalid(format!("unexpected character '{}' at {}", c, position))),
+        };
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Selects data sources by glob patterns on their manager id, data source id and tags.
+pub struct Selector {
+    manager: WildMatch,
+    source: WildMatch,
+    tags: Vec<(String, WildMatch)>,
+}
+
+impl Selector {
+    pub fn new(manager: &str, source: &str) -> Selector {
+        Selector {
+            manager: WildMatch::new(manager),
+            source: WildMatch::new(source),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Requires data sources to have a tag whose value matches the glob pattern.
+    pub fn with_tag(mut self, key: &str, value: &str) -> Selector {
+        self.tags.push((key.to_string(), WildMatch::new(value)));
+        self
+    }
+
+    pub fn matches_manager(&self, manager_id: &str) -> bool {
+        self.manager.matches(manager_id)
+    }
+
+    pub fn matches_source(&self, data_source_id: &str) -> bool {
+        self.source.matches(data_source_id)
+    }
+
+    pub fn matches_tags(&self, tags: &BTreeMap<String, String>) -> bool {
+        self.tags.iter().all(|(key, pattern)| tags.get(key).is_some_and(|value| pattern.matches(value)))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Function {
+    /// Mean of every series, per bucket.
+    Avg,
+    /// Sum of every series, per bucket.
+    Sum,
+    /// Smallest value of every series, per bucket.
+    Min,
+    /// Largest value of every series, per bucket.
+    Max,
+    /// Change per second between consecutive buckets.
+    Derivative,
+    /// Like [`Function::Derivative`], but treats decreases as counter resets, so it never goes negative.
+    Rate,
+    /// Mean over the current and the previous buckets, for a window of the given number of buckets.
+    MovingAvg(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Operator {
+    fn apply(&self, left: f64, right: f64) -> Option<f64> {
+        let value = match self {
+            Operator::Add => left + right,
+            Operator::Sub => left - right,
+            Operator::Mul => left * right,
+            Operator::Div => left / right,
+        };
+
+        value.is_finite().then_some(value)
+    }
+}
+
+enum Expr {
+    Number(f64),
+    /// Index of a selector in [`Query::selectors`].
+    Select(usize),
+    Call(Function, Box<Expr>),
+    Binary(Box<Expr>, Operator, Box<Expr>),
+    Negate(Box<Expr>),
+}
+
+/// A parsed query, along with the selectors whose data it needs.
+pub struct Query {
+    expr: Expr,
+    selectors: Vec<Selector>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    selectors: Vec<Selector>,
+    /// How deeply the expression being parsed is nested.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ManagerAndDataError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(invalid(format!("expected {:?}, found {:?}", expected, token))),
+            None => Err(invalid(format!("expected {:?}, found end of query", expected))),
+        }
+    }
+
+    /// Parses an expression nested in another, failing if it's nested too deeply.
+    fn nested(&mut self, parse: impl FnOnce(&mut Parser) -> Result<Expr, ManagerAndDataError>) -> Result<Expr, ManagerAndDataError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(invalid(format!("query is nested more than {} levels deep", MAX_DEPTH)));
+        }
+
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn expr(&mut self) -> Result<Expr, ManagerAndDataError> {
+        let mut expr = self.term()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Plus) => Operator::Add,
+                Some(Token::Minus) => Operator::Sub,
+                _ => return Ok(expr),
+            };
+            self.next();
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, ManagerAndDataError> {
+        let mut expr = self.factor()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Star) => Operator::Mul,
+                Some(Token::Slash) => Operator::Div,
+                _ => return Ok(expr),
+            };
+            self.next();
+            expr = Expr::Binary(Box::new(expr), operator, Box::new(self.factor()?));
+        }
+    }
+
+    fn factor(&mut self) -> Result<Expr, ManagerAndDataError> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Minus) => Ok(Expr::Negate(Box::new(self.nested(Parser::factor)?))),
+            Some(Token::LParen) => {
+                let expr = self.nested(Parser::expr)?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            },
+            Some(Token::Ident(name)) => {
+                self.expect(Token::LParen)?;
+                let expr = if name == "select" {
+                    self.select()?
+                }
+                else {
+                    self.nested(|parser| parser.call(&name))?
+                };
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            },
+            Some(token) => Err(invalid(format!("unexpected {:?}", token))),
+            None => Err(invalid("unexpected end of query")),
+        }
+    }
+
+    fn select(&mut self) -> Result<Expr, ManagerAndDataError> {
+        let mut selector = Selector::new("*", "*");
+
+        while let Some(Token::Ident(_)) = self.peek() {
+            let Some(Token::Ident(key)) = self.next() else {
+                unreachable!("token changed after peeking it");
+            };
+            self.expect(Token::Eq)?;
+            let value = match self.next() {
+                Some(Token::Str(value)) => value,
+                _ => return Err(invalid(format!("expected a string value for selector key {}", key))),
+            };
+
+            match key.as_str() {
+                "manager" => selector.manager = WildMatch::new(&value),
+                "source" => selector.source = WildMatch::new(&value),
+                _ => selector = selector.with_tag(&key, &value),
+            }
+
+            if self.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.next();
+        }
+
+        self.selectors.push(selector);
+        Ok(Expr::Select(self.selectors.len() - 1))
+    }
+
+    fn call(&mut self, name: &str) -> Result<Expr, ManagerAndDataError> {
+        let function = match name {
+            "avg" => Function::Avg,
+            "sum" => Function::Sum,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "derivative" => Function::Derivative,
+            "rate" => Function::Rate,
+            "moving_avg" => {
+                let expr = self.expr()?;
+                self.expect(Token::Comma)?;
+                let window = match self.next() {
+                    Some(Token::Number(window)) if window >= 1.0 && window.fract() == 0.0 => window as usize,
+                    _ => return Err(invalid("moving_avg expects a positive whole number of buckets as its window")),
+                };
+                return Ok(Expr::Call(Function::MovingAvg(window), Box::new(expr)));
+            },
+            _ => return Err(invalid(format!("unknown function: {}", name))),
+        };
+
+        Ok(Expr::Call(function, Box::new(self.expr()?)))
+    }
+}
+
+/// Parses a query, see the [module documentation](self) for its syntax.
+pub fn parse(query: &str) -> Result<Query, ManagerAndDataError> {
+    if query.len() > MAX_QUERY_LENGTH {
+        return Err(invalid(format!("query is longer than {} bytes", MAX_QUERY_LENGTH)));
+    }
+
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        position: 0,
+        selectors: Vec::new(),
+        depth: 0,
+    };
+
+    let expr = parser.expr()?;
+    if let Some(token) = parser.peek() {
+        return Err(invalid(format!("unexpected {:?} after the end of the query", token)));
+    }
+
+    Ok(Query {
+        expr,
+        selectors: parser.selectors,
+    })
+}
+
+/// The time buckets a query is evaluated over, `step` milliseconds wide, starting at `from` and covering
+/// `to`.
+#[derive(Clone, Copy)]
+pub struct Buckets {
+    from: u64,
+    step: u64,
+    count: usize,
+}
+
+impl Buckets {
+    pub fn new(from: u64, to: u64, step: u64) -> Result<Buckets, ManagerAndDataError> {
+        if from > to {
+            return Err(invalid("from must not be after to"));
+        }
+        if step == 0 {
+            return Err(invalid("step must be positive"));
+        }
+
+        let count = (to - from) / step + 1;
+        if count > MAX_BUCKETS {
+            return Err(invalid(format!("query would produce more than {} buckets", MAX_BUCKETS)));
+        }
+
+        Ok(Buckets { from, step, count: count as usize })
+    }
+
+    /// Splits samples into the buckets, and reduces each bucket to the mean of its samples.
+    pub fn series(&self, manager_id: &str, data_source_id: &str, samples: &[Sample<f64>]) -> LabelledSeries {
+        let mut sums = vec![(0.0, 0u64); self.count];
+        for sample in samples {
+            let Some(offset) = sample.timestamp.checked_sub(self.from) else {
+                continue;
+            };
+            if let Some((sum, count)) = sums.get_mut((offset / self.step) as usize) {
+                *sum += sample.value;
+                *count += 1;
+            }
+        }
+
+        LabelledSeries {
+            manager: Some(manager_id.to_string()),
+            source: Some(data_source_id.to_string()),
+            values: sums.into_iter()
+                .map(|(sum, count)| (count > 0).then(|| sum / count as f64))
+                .collect(),
+        }
+    }
+
+    fn timestamp(&self, index: usize) -> u64 {
+        self.from + index as u64 * self.step
+    }
+}
+
+/// A series of bucket values, labelled with the data source it came from. Series which combine several
+/// data sources have no labels.
+pub struct LabelledSeries {
+    manager: Option<String>,
+    source: Option<String>,
+    values: Vec<Option<f64>>,
+}
+
+impl LabelledSeries {
+    fn unlabelled(values: Vec<Option<f64>>) -> LabelledSeries {
+        LabelledSeries { manager: None, source: None, values }
+    }
+
+    fn same_labels(&self, other: &LabelledSeries) -> bool {
+        self.manager == other.manager && self.source == other.source
+    }
+}
+
+/// The value of a bucket of a query result, `None` if the bucket had no data.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct QueryPoint {
+    /// Start of the bucket, in milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub value: Option<f64>,
+}
+
+/// A series produced by a query.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct QuerySeries {
+    pub manager: Option<String>,
+    pub source: Option<String>,
+    pub points: Vec<QueryPoint>,
+}
+
+enum Value {
+    Scalar(f64),
+    Series(Vec<LabelledSeries>),
+}
+
+fn reduce(series: &[LabelledSeries], count: usize, reducer: fn(&[f64]) -> f64) -> LabelledSeries {
+    let values = (0..count)
+        .map(|index| {
+            let values: Vec<f64> = series.iter().filter_map(|series| series.values[index]).collect();
+            (!values.is_empty()).then(|| reducer(&values))
+        })
+        .collect();
+
+    LabelledSeries::unlabelled(values)
+}
+
+fn derivative(values: &[Option<f64>], seconds: f64, counter: bool) -> Vec<Option<f64>> {
+    let mut derivative = vec![None; values.len()];
+    for index in 1..values.len() {
+        if let (Some(previous), Some(current)) = (values[index - 1], values[index]) {
+            let change = if counter && current < previous {
+                current
+            }
+            else {
+                current - previous
+            };
+            derivative[index] = Some(change / seconds);
+        }
+    }
+
+    derivative
+}
+
+fn moving_average(values: &[Option<f64>], window: usize) -> Vec<Option<f64>> {
+    (0..values.len())
+        .map(|index| {
+            let window: Vec<f64> = values[(index + 1).saturating_sub(window)..=index].iter().flatten().copied().collect();
+            (!window.is_empty()).then(|| window.iter().sum::<f64>() / window.len() as f64)
+        })
+        .collect()
+}
+
+/// Combines two series bucket by bucket, labelling the result like `labels`.
+fn combine(left: &LabelledSeries, operator: Operator, right: &LabelledSeries, labels: &LabelledSeries) -> LabelledSeries {
+    LabelledSeries {
+        manager: labels.manager.clone(),
+        source: labels.source.clone(),
+        values: left.values.iter()
+            .zip(&right.values)
+            .map(|(left, right)| operator.apply((*left)?, (*right)?))
+            .collect(),
+    }
+}
+
+fn binary(left: Value, operator: Operator, right: Value) -> Result<Value, ManagerAndDataError> {
+    let map = |series: Vec<LabelledSeries>, op: &dyn Fn(f64) -> Option<f64>| -> Vec<LabelledSeries> {
+        series.into_iter()
+            .map(|series| LabelledSeries {
+                values: series.values.iter().map(|value| value.and_then(op)).collect(),
+                ..series
+            })
+            .collect()
+    };
+
+    let value = match (left, right) {
+        (Value::Scalar(left), Value::Scalar(right)) => Value::Scalar(
+            operator.apply(left, right).ok_or_else(|| invalid("arithmetic between numbers isn't finite"))?
+        ),
+        (Value::Scalar(left), Value::Series(right)) => Value::Series(map(right, &|value| operator.apply(left, value))),
+        (Value::Series(left), Value::Scalar(right)) => Value::Series(map(left, &|value| operator.apply(value, right))),
+        (Value::Series(left), Value::Series(right)) => {
+            let series = match (left.as_slice(), right.as_slice()) {
+                ([left], [right]) if left.same_labels(right) => vec![combine(left, operator, right, left)],
+                ([left], [right]) => vec![combine(left, operator, right, &LabelledSeries::unlabelled(Vec::new()))],
+                // A single series is combined with each series on the other side.
+                ([single], many) => many.iter().map(|other| combine(single, operator, other, other)).collect(),
+                (many, [single]) => many.iter().map(|other| combine(other, operator, single, other)).collect(),
+                // Otherwise series are matched by the data source they came from.
+                (left, right) => left.iter()
+                    .filter_map(|left| {
+                        let right = right.iter().find(|right| right.same_labels(left))?;
+                        Some(combine(left, operator, right, left))
+                    })
+                    .collect(),
+            };
+            Value::Series(series)
+        },
+    };
+
+    Ok(value)
+}
+
+impl Query {
+    pub fn selectors(&self) -> &[Selector] {
+        &self.selectors
+    }
+
+    /// Evaluates the query, given the series selected by each of [`Query::selectors`], in the same order.
+    pub fn evaluate(&self, buckets: &Buckets, selected: Vec<Vec<LabelledSeries>>) -> Result<Vec<QuerySeries>, ManagerAndDataError> {
+        let mut selected: Vec<Option<Vec<LabelledSeries>>> = selected.into_iter().map(Some).collect();
+
+        let series = match evaluate(&self.expr, buckets, &mut selected)? {
+            Value::Scalar(value) => vec![LabelledSeries::unlabelled(vec![Some(value); buckets.count])],
+            Value::Series(series) => series,
+        };
+
+        Ok(
+            series.into_iter()
+                .map(|series| QuerySeries {
+                    manager: series.manager,
+                    source: series.source,
+                    points: series.values.into_iter()
+                        .enumerate()
+                        .map(|(index, value)| QueryPoint { timestamp: buckets.timestamp(index), value })
+                        .collect(),
+                })
+                .collect()
+        )
+    }
+}
+
+fn evaluate(expr: &Expr, buckets: &Buckets, selected: &mut [Option<Vec<LabelledSeries>>]) -> Result<Value, ManagerAndDataError> {
+    let value = match expr {
+        Expr::Number(number) => Value::Scalar(*number),
+        Expr::Select(index) => Value::Series(
+            selected.get_mut(*index)
+                .and_then(Option::take)
+                .ok_or_else(|| invalid("selector has no data"))?
+        ),
+        Expr::Negate(expr) => binary(Value::Scalar(-1.0), Operator::Mul, evaluate(expr, buckets, selected)?)?,
+        Expr::Binary(left, operator, right) => {
+            let left = evaluate(left, buckets, selected)?;
+            binary(left, *operator, evaluate(right, buckets, selected)?)?
+        },
+        Expr::Call(function, expr) => {
+            let Value::Series(series) = evaluate(expr, buckets, selected)? else {
+                return Err(invalid("functions can only be applied to series"));
+            };
+            let seconds = buckets.step as f64 / 1000.0;
+
+            let series = match function {
+                Function::Avg => vec![reduce(&series, buckets.count, |values| values.iter().sum::<f64>() / values.len() as f64)],
+                Function::Sum => vec![reduce(&series, buckets.count, |values| values.iter().sum())],
+                Function::Min => vec![reduce(&series, buckets.count, |values| values.iter().copied().fold(f64::INFINITY, f64::min))],
+                Function::Max => vec![reduce(&series, buckets.count, |values| values.iter().copied().fold(f64::NEG_INFINITY, f64::max))],
+                Function::Derivative | Function::Rate => series.into_iter()
+                    .map(|series| LabelledSeries {
+                        values: derivative(&series.values, seconds, *function == Function::Rate),
+                        ..series
+                    })
+                    .collect(),
+                Function::MovingAvg(window) => series.into_iter()
+                    .map(|series| LabelledSeries {
+                        values: moving_average(&series.values, *window),
+                        ..series
+                    })
+                    .collect(),
+            };
+            Value::Series(series)
+        },
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{Buckets, LabelledSeries, QuerySeries, Token, parse, tokenize};
+    use crate::manager_and_data::Sample;
+
+    /// Three one second buckets.
+    fn buckets() -> Buckets {
+        Buckets::new(0, 2000, 1000).unwrap()
+    }
+
+    fn series(source: &str, values: &[(u64, f64)]) -> LabelledSeries {
+        let samples: Vec<Sample<f64>> = values.iter()
+            .map(|(timestamp, value)| Sample { timestamp: *timestamp, value: *value })
+            .collect();
+        buckets().series("manager", source, &samples)
+    }
+
+    fn values(series: &QuerySeries) -> Vec<Option<f64>> {
+        series.points.iter().map(|point| point.value).collect()
+    }
+
+    fn evaluate(query: &str, selected: Vec<Vec<LabelledSeries>>) -> Vec<QuerySeries> {
+        parse(query).unwrap().evaluate(&buckets(), selected).unwrap()
+    }
+
+    #[test]
+    fn queries_are_tokenized() {
+        assert_eq!(
+            tokenize(r#"avg(select(bed="3")) * 1.5"#).unwrap(),
+            [
+                Token::Ident("avg".to_string()), Token::LParen, Token::Ident("select".to_string()), Token::LParen,
+                Token::Ident("bed".to_string()), Token::Eq, Token::Str("3".to_string()), Token::RParen, Token::RParen,
+                Token::Star, Token::Number(1.5),
+            ]
+        );
+        assert!(tokenize(r#"select(source="pot"#).is_err());
+        assert!(tokenize("1.2.3").is_err());
+        assert!(tokenize("1 % 2").is_err());
+    }
+
+    #[test]
+    fn selectors_match_managers_sources_and_tags() {
+        let query = parse(r#"select(manager="float", source="pot-*", bed="3") + select()"#).unwrap();
+        let [selector, everything] = query.selectors() else {
+            panic!("expected two selectors");
+        };
+
+        let tags = BTreeMap::from([("bed".to_string(), "3".to_string())]);
+        assert!(selector.matches_manager("float") && !selector.matches_manager("int"));
+        assert!(selector.matches_source("pot-1") && !selector.matches_source("bed-1"));
+        assert!(selector.matches_tags(&tags) && !selector.matches_tags(&BTreeMap::new()));
+        assert!(everything.matches_manager("int") && everything.matches_source("bed-1") && everything.matches_tags(&BTreeMap::new()));
+    }
+
+    #[test]
+    fn invalid_queries_are_rejected() {
+        for query in [
+            "",
+            "select(",
+            "select(source=pot)",
+            "median(select())",
+            "moving_avg(select(), 0)",
+            "moving_avg(select(), 1.5)",
+            "1 + ",
+            "select() select()",
+        ] {
+            assert!(parse(query).is_err(), "{} should be rejected", query);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_queries_are_rejected() {
+        let nested = |open: &str, close: &str, depth| format!("{}select(){}", open.repeat(depth), close.repeat(depth));
+
+        assert!(parse(&nested("-", "", 64)).is_ok());
+        assert!(parse(&nested("-", "", 65)).is_err());
+        assert!(parse(&nested("(", ")", 64)).is_ok());
+        assert!(parse(&nested("(", ")", 65)).is_err());
+        assert!(parse(&nested("avg(", ")", 64)).is_ok());
+        assert!(parse(&nested("avg(", ")", 65)).is_err());
+        assert!(parse(&format!("{}1", "-".repeat(8000))).is_err());
+        assert!(parse(&"(".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn long_queries_are_rejected() {
+        let sum = |terms| vec!["1"; terms].join("+");
+
+        assert_eq!(values(&evaluate(&sum(512), Vec::new())[0]), [Some(512.0); 3]);
+        assert!(parse(&sum(513)).is_err());
+    }
+
+    #[test]
+    fn samples_are_averaged_per_bucket() {
+        let series = series("pot", &[(0, 1.0), (500, 3.0), (2500, 5.0), (3000, 7.0)]);
+        assert_eq!(series.values, [Some(2.0), None, Some(5.0)]);
+        assert!(Buckets::new(1, 0, 1000).is_err());
+        assert!(Buckets::new(0, 1000, 0).is_err());
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        let result = evaluate("-select() + 2 * 3 - 4 / (1 + 1)", vec![vec![series("pot", &[(0, 1.0), (2000, 2.0)])]]);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].source.as_deref(), Some("pot"));
+        assert_eq!(values(&result[0]), [Some(3.0), None, Some(2.0)]);
+
+        assert_eq!(values(&evaluate("2 * 3", Vec::new())[0]), [Some(6.0); 3]);
+        assert!(parse("1 / 0").unwrap().evaluate(&buckets(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn series_are_matched_by_their_data_source() {
+        let left = vec![series("a", &[(0, 4.0)]), series("b", &[(0, 6.0)])];
+        let right = vec![series("b", &[(0, 3.0)]), series("a", &[(0, 2.0)]), series("c", &[(0, 1.0)])];
+        let result = evaluate("select() / select()", vec![left, right]);
+
+        let result: Vec<_> = result.iter().map(|series| (series.source.as_deref(), values(series)[0])).collect();
+        assert_eq!(result, [(Some("a"), Some(2.0)), (Some("b"), Some(2.0))]);
+    }
+
+    #[test]
+    fn single_series_are_combined_with_every_other() {
+        let result = evaluate(
+            "select() - select()",
+            vec![vec![series("a", &[(0, 4.0)]), series("b", &[(0, 6.0)])], vec![series("offset", &[(0, 1.0)])]]
+        );
+
+        let result: Vec<_> = result.iter().map(|series| (series.source.as_deref(), values(series)[0])).collect();
+        assert_eq!(result, [(Some("a"), Some(3.0)), (Some("b"), Some(5.0))]);
+    }
+
+    #[test]
+    fn functions_reduce_and_transform_series() {
+        let selected = || vec![vec![
+            series("a", &[(0, 1.0), (1000, 4.0), (2000, 2.0)]),
+            series("b", &[(0, 3.0), (2000, 6.0)]),
+        ]];
+
+        let reduced = evaluate("avg(select())", selected());
+        assert_eq!(reduced.len(), 1);
+        assert_eq!(reduced[0].source, None);
+        assert_eq!(values(&reduced[0]), [Some(2.0), Some(4.0), Some(4.0)]);
+        assert_eq!(values(&evaluate("sum(select())", selected())[0]), [Some(4.0), Some(4.0), Some(8.0)]);
+        assert_eq!(values(&evaluate("min(select())", selected())[0]), [Some(1.0), Some(4.0), Some(2.0)]);
+        assert_eq!(values(&evaluate("max(select())", selected())[0]), [Some(3.0), Some(4.0), Some(6.0)]);
+
+        let derivative = evaluate("derivative(select())", selected());
+        assert_eq!(values(&derivative[0]), [None, Some(3.0), Some(-2.0)]);
+        assert_eq!(values(&derivative[1]), [None, None, None]);
+        assert_eq!(values(&evaluate("rate(select())", selected())[0]), [None, Some(3.0), Some(2.0)]);
+        assert_eq!(values(&evaluate("moving_avg(select(), 2)", selected())[1]), [Some(3.0), Some(3.0), Some(6.0)]);
+
+        assert!(parse("avg(2)").unwrap().evaluate(&buckets(), Vec::new()).is_err());
+    }
+}
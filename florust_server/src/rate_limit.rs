@@ -0,0 +1,340 @@
+use std::{collections::HashMap, net::IpAddr, time::{Duration, Instant}};
+
+use rocket::{serde::{Serialize, Deserialize}, tokio::sync::Mutex};
+
+use crate::acl::SourcePattern;
+
+/// Most characters a byte of uploaded data takes in a request body, like `255, ` in JSON, or `data=255&` in a
+/// form.
+const MAX_ENCODED_BYTE_SIZE: usize = 9;
+/// Room left in request bodies for what surrounds the uploaded data, like `{"data": []}`.
+const BODY_OVERHEAD: usize = 1024;
+/// How often buckets which are full again, and therefore no different from new buckets, are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Configuration of a token bucket. The bucket holds at most `burst` tokens, and is refilled with `rate`
+/// tokens every second. Every uploaded sample takes a token from the bucket.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct BucketConfig {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl BucketConfig {
+    fn validate(&self) -> Result<(), String> {
+        if !(self.rate.is_finite() && self.rate > 0.0) {
+            return Err(format!("rate must be a positive number, but is {}", self.rate));
+        }
+        if !(self.burst.is_finite() && self.burst >= 1.0) {
+            return Err(format!("burst must be at least 1, but is {}", self.burst));
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits applied to data sources matching a pattern, overriding the default limits.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SourceLimitsConfig {
+    /// Pattern of the data sources the limits apply to, see [`SourcePattern`].
+    pub sources: String,
+    pub rate: Option<BucketConfig>,
+    pub max_payload_size: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct LimitsConfig {
+    /// Default rate limit of each data source.
+    pub source: Option<BucketConfig>,
+    /// Rate limit of each client, by IP address, across all data sources.
+    pub client: Option<BucketConfig>,
+    /// Default maximum size in bytes of uploaded data.
+    pub max_payload_size: Option<usize>,
+    /// Limits overriding the defaults for specific data sources. The first matching entry is used.
+    #[serde(default)]
+    pub overrides: Vec<SourceLimitsConfig>,
+}
+
+impl LimitsConfig {
+    /// Checks that every rate limit can be enforced, which it can't if it never refills, or never holds a
+    /// whole token.
+    pub fn validate(&self) -> Result<(), String> {
+        let rates = [("source", self.source), ("client", self.client)].into_iter()
+            .chain(self.overrides.iter().map(|limits| (limits.sources.as_str(), limits.rate)));
+
+        for (name, rate) in rates {
+            if let Some(Err(err)) = rate.map(|rate| rate.validate()) {
+                return Err(format!("invalid rate limit of {}: {}", name, err));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Why an upload was rejected by the [`RateLimiter`].
+pub enum LimitExceeded {
+    Rate {
+        retry_after: Duration
+    },
+    PayloadSize {
+        max_size: usize
+    },
+}
+
+struct TokenBucket {
+    config: BucketConfig,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(config: BucketConfig, now: Instant) -> TokenBucket {
+        TokenBucket {
+            config,
+            tokens: config.burst,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.config.rate).min(self.config.burst);
+        self.last_refill = now;
+    }
+
+    /// Refills the bucket, and checks whether it holds a token, returning how long it will take for a token
+    /// to become available otherwise.
+    fn check(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            Ok(())
+        }
+        else {
+            Err(Duration::try_from_secs_f64((1.0 - self.tokens) / self.config.rate).unwrap_or(Duration::MAX))
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.config.burst
+    }
+}
+
+struct Buckets {
+    source: HashMap<(String, String), TokenBucket>,
+    client: HashMap<IpAddr, TokenBucket>,
+    last_pruned: Instant,
+}
+
+impl Buckets {
+    /// Drops the buckets which are full again, once every [`PRUNE_INTERVAL`], so the buckets of data sources
+    /// and clients which are gone don't pile up.
+    fn prune(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.last_pruned) < PRUNE_INTERVAL {
+            return;
+        }
+
+        let is_idle = |bucket: &mut TokenBucket| {
+            bucket.refill(now);
+            bucket.is_full()
+        };
+        self.source.retain(|_, bucket| !is_idle(bucket));
+        self.client.retain(|_, bucket| !is_idle(bucket));
+        self.last_pruned = now;
+    }
+}
+
+struct SourceLimits {
+    pattern: SourcePattern,
+    rate: Option<BucketConfig>,
+    max_payload_size: Option<usize>,
+}
+
+/// Enforces the per data source, and per client limits on uploaded data.
+pub struct RateLimiter {
+    source: Option<BucketConfig>,
+    client: Option<BucketConfig>,
+    max_payload_size: Option<usize>,
+    overrides: Vec<SourceLimits>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: LimitsConfig) -> RateLimiter {
+        RateLimiter {
+            source: config.source,
+            client: config.client,
+            max_payload_size: config.max_payload_size,
+            overrides: config.overrides
+                .into_iter()
+                .map(|limits| SourceLimits {
+                    pattern: SourcePattern::new(&limits.sources),
+                    rate: limits.rate,
+                    max_payload_size: limits.max_payload_size,
+                })
+                .collect(),
+            buckets: Mutex::new(Buckets {
+                source: HashMap::new(),
+                client: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+        }
+    }
+
+    fn override_for(&self, manager_id: &str, data_source_id: &str) -> Option<&SourceLimits> {
+        self.overrides
+            .iter()
+            .find(|limits| limits.pattern.matches(manager_id, data_source_id))
+    }
+
+    fn max_payload_size(&self, manager_id: &str, data_source_id: &str) -> Option<usize> {
+        self.override_for(manager_id, data_source_id)
+            .and_then(|limits| limits.max_payload_size)
+            .or(self.max_payload_size)
+    }
+
+    /// Returns the size of the largest request body which may hold an upload of a data source, so larger
+    /// bodies are rejected before being parsed.
+    pub fn max_body_size(&self, manager_id: &str, data_source_id: &str) -> Option<usize> {
+        self.max_payload_size(manager_id, data_source_id)
+            .map(|max_size| max_size.saturating_mul(MAX_ENCODED_BYTE_SIZE).saturating_add(BODY_OVERHEAD))
+    }
+
+    /// Checks an upload of `size` bytes against the limits, taking a token from the data source's and the
+    /// client's buckets if it is allowed by both.
+    pub async fn check(&self, manager_id: &str, data_source_id: &str, client: Option<IpAddr>, size: usize) -> Result<(), LimitExceeded> {
+        self.check_at(manager_id, data_source_id, client, size, Instant::now()).await
+    }
+
+    async fn check_at(&self, manager_id: &str, data_source_id: &str, client: Option<IpAddr>, size: usize, now: Instant) -> Result<(), LimitExceeded> {
+        if let Some(max_size) = self.max_payload_size(manager_id, data_source_id) {
+            if size > max_size {
+                return Err(LimitExceeded::PayloadSize { max_size });
+            }
+        }
+
+        let mut buckets = self.buckets.lock().await;
+        buckets.prune(now);
+        let Buckets { source, client: clients, .. } = &mut *buckets;
+
+        let client_bucket = match (self.client, client) {
+            (Some(config), Some(client)) => Some(clients.entry(client).or_insert_with(|| TokenBucket::new(config, now))),
+            _ => None,
+        };
+        let source_rate = self.override_for(manager_id, data_source_id)
+            .and_then(|limits| limits.rate)
+            .or(self.source);
+        let source_bucket = source_rate.map(|config| {
+            source.entry((manager_id.to_string(), data_source_id.to_string()))
+                .or_insert_with(|| TokenBucket::new(config, now))
+        });
+
+        // Both buckets are checked before either is taken from, so an upload rejected by one doesn't use up
+        // a token of the other.
+        let mut buckets: Vec<&mut TokenBucket> = client_bucket.into_iter().chain(source_bucket).collect();
+        for bucket in buckets.iter_mut() {
+            bucket.check(now).map_err(|retry_after| LimitExceeded::Rate { retry_after })?;
+        }
+        for bucket in buckets {
+            bucket.tokens -= 1.0;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{IpAddr, Ipv4Addr}, time::{Duration, Instant}};
+
+    use super::{BucketConfig, LimitExceeded, LimitsConfig, PRUNE_INTERVAL, RateLimiter, SourceLimitsConfig};
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn bucket(rate: f64, burst: f64) -> Option<BucketConfig> {
+        Some(BucketConfig { rate, burst })
+    }
+
+    fn retry_after(result: Result<(), LimitExceeded>) -> Option<Duration> {
+        match result {
+            Err(LimitExceeded::Rate { retry_after }) => Some(retry_after),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn rates_which_cant_be_enforced_are_rejected() {
+        for (rate, burst) in [(0.0, 1.0), (-1.0, 1.0), (f64::NAN, 1.0), (f64::INFINITY, 1.0), (1.0, 0.5), (1.0, f64::NAN)] {
+            let config = LimitsConfig { source: bucket(rate, burst), ..Default::default() };
+            assert!(config.validate().is_err(), "rate {} and burst {} were accepted", rate, burst);
+        }
+
+        let config = LimitsConfig {
+            overrides: vec![SourceLimitsConfig { sources: "*/*".to_string(), rate: bucket(0.0, 1.0), max_payload_size: None }],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        assert!(LimitsConfig { source: bucket(0.5, 1.0), client: bucket(10.0, 20.0), ..Default::default() }.validate().is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn buckets_refill_at_their_rate() {
+        let limiter = RateLimiter::new(LimitsConfig { source: bucket(2.0, 2.0), ..Default::default() });
+        let now = Instant::now();
+
+        assert!(limiter.check_at("m", "s", None, 1, now).await.is_ok());
+        assert!(limiter.check_at("m", "s", None, 1, now).await.is_ok());
+        assert_eq!(retry_after(limiter.check_at("m", "s", None, 1, now).await), Some(Duration::from_millis(500)));
+        assert!(limiter.check_at("m", "s", None, 1, now + Duration::from_millis(500)).await.is_ok());
+
+        // Other data sources have buckets of their own.
+        assert!(limiter.check_at("m", "other", None, 1, now).await.is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn rejected_uploads_take_no_token() {
+        let limiter = RateLimiter::new(LimitsConfig {
+            source: bucket(1.0, 1.0),
+            client: bucket(1.0, 2.0),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        assert!(limiter.check_at("m", "s", Some(CLIENT), 1, now).await.is_ok());
+        // The data source's bucket is empty, so the client's token is left alone.
+        assert!(retry_after(limiter.check_at("m", "s", Some(CLIENT), 1, now).await).is_some());
+        assert!(limiter.check_at("m", "other", Some(CLIENT), 1, now).await.is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn payloads_are_limited_per_data_source() {
+        let limiter = RateLimiter::new(LimitsConfig {
+            max_payload_size: Some(8),
+            overrides: vec![SourceLimitsConfig { sources: "m/big".to_string(), rate: None, max_payload_size: Some(64) }],
+            ..Default::default()
+        });
+
+        assert!(matches!(limiter.check("m", "s", None, 9).await, Err(LimitExceeded::PayloadSize { max_size: 8 })));
+        assert!(limiter.check("m", "big", None, 9).await.is_ok());
+        assert!(limiter.max_body_size("m", "s") < limiter.max_body_size("m", "big"));
+    }
+
+    #[rocket::async_test]
+    async fn full_buckets_are_pruned() {
+        let limiter = RateLimiter::new(LimitsConfig { source: bucket(1.0, 1.0), ..Default::default() });
+        let now = Instant::now();
+
+        assert!(limiter.check_at("m", "s", None, 1, now).await.is_ok());
+        assert!(limiter.check_at("m", "other", None, 1, now).await.is_ok());
+        assert_eq!(limiter.buckets.lock().await.source.len(), 2);
+
+        // Both buckets are full again by then, and are dropped, before the bucket of the upload is created again.
+        assert!(limiter.check_at("m", "other", None, 1, now + PRUNE_INTERVAL).await.is_ok());
+        assert_eq!(limiter.buckets.lock().await.source.len(), 1);
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use log::info;
+use rocket::{fairing::AdHoc, serde::{Serialize, Deserialize}, tokio::{self, time::interval}};
+
+use crate::{FlorustState, manager_and_data::now_millis};
+
+fn default_reap_interval_secs() -> u64 { 60 }
+
+/// How long logged data is kept around. Every limit is optional, data is kept until it is pushed out of
+/// its data source's circular buffer if none are set.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct RetentionConfig {
+    /// Samples older than this many seconds are removed.
+    pub max_age_secs: Option<u64>,
+    /// At most this many samples are kept per data source.
+    pub max_samples: Option<usize>,
+    /// Deregistered data sources, and their data, are removed this many seconds after being deregistered.
+    pub keep_deregistered_for_secs: Option<u64>,
+    /// How often, in seconds, the reaper applies the retention policy.
+    #[serde(default = "default_reap_interval_secs")]
+    pub reap_interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            max_age_secs: None,
+            max_samples: None,
+            keep_deregistered_for_secs: None,
+            reap_interval_secs: default_reap_interval_secs(),
+        }
+    }
+}
+
+impl RetentionConfig {
+    fn is_unlimited(&self) -> bool {
+        self.max_age_secs.is_none() && self.max_samples.is_none() && self.keep_deregistered_for_secs.is_none()
+    }
+}
+
+/// Fairing that spawns the reaper, a background task periodically applying the retention policy to the
+/// data of every manager.
+pub fn reaper() -> AdHoc {
+    AdHoc::on_liftoff("Retention reaper", |rocket| Box::pin(async move {
+        let Some(state) = rocket.state::<FlorustState>() else {
+            return;
+        };
+
+        let policy = state.retention;
+        if policy.is_unlimited() {
+            return;
+        }
+
+        let managers_and_data = state.managers_and_data.clone();
+        info!("Starting retention reaper, running every {} seconds", policy.reap_interval_secs);
+
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(policy.reap_interval_secs.max(1)));
+            loop {
+                interval.tick().await;
+
+                let now = now_millis();
+                for manager_and_data in managers_and_data.values() {
+                    manager_and_data.apply_retention(&policy, now).await;
+                }
+            }
+        });
+    }))
+}
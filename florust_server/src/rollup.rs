@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+
+use rocket::serde::{Serialize, Deserialize};
+
+use crate::{circular_vec::CircularVec, units::Conversion};
+
+/// The resolutions samples are rolled up into.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Resolution {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    /// Length of a bucket of this resolution, in milliseconds.
+    pub fn millis(&self) -> u64 {
+        match self {
+            Resolution::Minute => 60 * 1000,
+            Resolution::Hour => 60 * 60 * 1000,
+            Resolution::Day => 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+fn default_minute_buckets() -> usize { 24 * 60 }
+
+fn default_hour_buckets() -> usize { 30 * 24 }
+
+fn default_day_buckets() -> usize { 5 * 365 }
+
+fn default_max_points() -> usize { 1000 }
+
+/// How many buckets of each resolution are kept per data source. Setting a count to 0 disables that
+/// resolution.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct RollupConfig {
+    #[serde(default = "default_minute_buckets")]
+    pub minute_buckets: usize,
+    #[serde(default = "default_hour_buckets")]
+    pub hour_buckets: usize,
+    #[serde(default = "default_day_buckets")]
+    pub day_buckets: usize,
+    /// Range queries are answered from the finest resolution that returns at most this many points.
+    #[serde(default = "default_max_points")]
+    pub max_points: usize,
+}
+
+impl Default for RollupConfig {
+    fn default() -> Self {
+        RollupConfig {
+            minute_buckets: default_minute_buckets(),
+            hour_buckets: default_hour_buckets(),
+            day_buckets: default_day_buckets(),
+            max_points: default_max_points(),
+        }
+    }
+}
+
+/// Summary of the samples logged during a bucket of time.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct Rollup {
+    /// Start of the bucket, in milliseconds since the unix epoch.
+    pub start: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub count: u64,
+    /// The most recent sample of the bucket.
+    pub last: f64,
+}
+
+impl Rollup {
+    fn new(start: u64, value: f64) -> Rollup {
+        Rollup {
+            start,
+            min: value,
+            max: value,
+            mean: value,
+            count: 1,
+            last: value,
+        }
+    }
+
+    /// Converts the values of the rollup to another unit. Conversions never flip the order of values, so the
+    /// minimum and maximum stay the minimum and maximum.
+    pub fn convert(self, conversion: &Conversion) -> Rollup {
+        Rollup {
+            min: conversion.apply(self.min),
+            max: conversion.apply(self.max),
+            mean: conversion.apply(self.mean),
+            last: conversion.apply(self.last),
+            ..self
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.mean += (value - self.mean) / self.count as f64;
+        self.last = value;
+    }
+}
+
+/// The buckets of a resolution, as saved in a snapshot.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RollupTierSnapshot {
+    pub resolution: Resolution,
+    /// Oldest first.
+    pub buckets: Vec<Rollup>,
+    /// Whether buckets older than the oldest of `buckets` were dropped.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+struct RollupTier {
+    resolution: Resolution,
+    buckets: CircularVec<Rollup>,
+    /// Whether buckets older than the oldest one were dropped, because the tier overflowed.
+    truncated: bool,
+}
+
+impl RollupTier {
+    fn add(&mut self, timestamp: u64, value: f64) {
+        let start = timestamp - timestamp % self.resolution.millis();
+
+        match self.buckets.back_mut() {
+            // Samples are logged in order, so a sample older than the current bucket can only be the result
+            // of the clock going backwards, in which case it is folded into the current bucket.
+            Some(bucket) if bucket.start >= start => bucket.add(value),
+            _ => {
+                if self.buckets.len() == self.buckets.capacity() {
+                    self.truncated = true;
+                }
+                self.buckets.append(Rollup::new(start, value));
+            },
+        }
+    }
+
+    /// Adds samples to the buckets they fall in, wherever those are in the tier, creating the missing ones.
+    /// The oldest buckets are dropped if the tier overflows.
+    fn import(&mut self, samples: &[(u64, f64)]) {
+        let mut buckets: BTreeMap<u64, Rollup> = self.buckets.iter().map(|bucket| (bucket.start, *bucket)).collect();
+        for &(timestamp, value) in samples {
+            let start = timestamp - timestamp % self.resolution.millis();
+            buckets.entry(start)
+                .and_modify(|bucket| bucket.add(value))
+                .or_insert_with(|| Rollup::new(start, value));
+        }
+
+        if buckets.len() > self.buckets.capacity() {
+            self.truncated = true;
+        }
+
+        self.buckets = CircularVec::new(self.buckets.capacity(), Rollup::new(0, 0.0));
+        for bucket in buckets.into_values() {
+            self.buckets.append(bucket);
+        }
+    }
+
+    /// Whether the tier holds every bucket from `from` on, which it does unless buckets after `from` were
+    /// dropped.
+    fn covers(&self, from: u64) -> bool {
+        !self.truncated || self.buckets.front().is_some_and(|bucket| bucket.start <= from)
+    }
+
+    fn range(&self, from: u64, to: u64) -> Vec<Rollup> {
+        self.buckets
+            .iter()
+            .filter(|bucket| bucket.start + self.resolution.millis() > from && bucket.start <= to)
+            .copied()
+            .collect()
+    }
+}
+
+/// The rollups of a single data source, from finest to coarsest resolution.
+pub struct RollupTiers {
+    tiers: Vec<RollupTier>,
+}
+
+impl RollupTiers {
+    pub fn new(config: &RollupConfig) -> RollupTiers {
+        let tiers = [
+            (Resolution::Minute, config.minute_buckets),
+            (Resolution::Hour, config.hour_buckets),
+            (Resolution::Day, config.day_buckets),
+        ];
+
+        RollupTiers {
+            tiers: tiers.into_iter()
+                .filter(|(_, buckets)| *buckets > 0)
+                .map(|(resolution, buckets)| RollupTier {
+                    resolution,
+                    buckets: CircularVec::new(buckets, Rollup::new(0, 0.0)),
+                    truncated: false,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn add(&mut self, timestamp: u64, value: f64) {
+        for tier in &mut self.tiers {
+            tier.add(timestamp, value);
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<RollupTierSnapshot> {
+        self.tiers
+            .iter()
+            .map(|tier| RollupTierSnapshot {
+                resolution: tier.resolution,
+                buckets: tier.buckets.iter().copied().collect(),
+                truncated: tier.truncated,
+            })
+            .collect()
+    }
+
+    /// Creates tiers as configured, filled with the buckets of a snapshot. Buckets of resolutions which are
+    /// disabled are dropped, as are the oldest buckets if the tiers are smaller than they were.
+    pub fn restore(config: &RollupConfig, snapshot: Vec<RollupTierSnapshot>) -> RollupTiers {
+        let mut tiers = RollupTiers::new(config);
+        for tier_snapshot in snapshot {
+            if let Some(tier) = tiers.tiers.iter_mut().find(|tier| tier.resolution == tier_snapshot.resolution) {
+                tier.truncated = tier_snapshot.truncated || tier_snapshot.buckets.len() > tier.buckets.capacity();
+                for bucket in tier_snapshot.buckets {
+                    tier.buckets.append(bucket);
+                }
+            }
+        }
+
+        tiers
+    }
+
+    /// Returns the start of the oldest bucket of any resolution.
+    pub fn oldest(&self) -> Option<u64> {
+        self.tiers.iter().filter_map(|tier| tier.buckets.front().map(|bucket| bucket.start)).min()
+    }
+
+    /// Whether every bucket aged out of the tier keeping buckets for the longest, which is the case once
+    /// that tier could have filled with buckets since its most recent one.
+    pub fn expired(&self, now: u64) -> bool {
+        let span = |tier: &RollupTier| tier.buckets.capacity() as u64 * tier.resolution.millis();
+
+        let Some(longest) = self.tiers.iter().max_by_key(|tier| span(tier)) else {
+            return true;
+        };
+        match longest.buckets.back() {
+            Some(bucket) => now.saturating_sub(bucket.start) >= span(longest),
+            None => true,
+        }
+    }
+
+    /// Adds samples which may be older than the most recent one, like imported samples, sorted by timestamp.
+    pub fn import(&mut self, samples: &[(u64, f64)]) {
+        for tier in &mut self.tiers {
+            tier.import(samples);
+        }
+    }
+
+    /// Picks the finest resolution that still covers `from`, and has at most `max_points` buckets between
+    /// `from` and `to`. Falls back to the coarsest resolution if none does, returns [`None`] if every
+    /// resolution is disabled.
+    pub fn range(&self, from: u64, to: u64, max_points: usize) -> Option<(Resolution, Vec<Rollup>)> {
+        let span = to.saturating_sub(from);
+
+        let tier = self.tiers
+            .iter()
+            .find(|tier| {
+                tier.covers(from)
+                    && span / tier.resolution.millis() <= max_points as u64
+            })
+            .or(self.tiers.last())?;
+
+        Some((tier.resolution, tier.range(from, to)))
+    }
+}
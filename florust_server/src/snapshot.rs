@@ -0,0 +1,83 @@
+use florust_common::DataSourceMetadata;
+use rocket::serde::{Serialize, Deserialize};
+
+use crate::{auth::TokenSnapshot, command_queue::Command, manager_and_data::{DataType, ManagerAndDataError, Result, Sample}, rollup::RollupTierSnapshot};
+
+/// Version of the snapshot format, bumped whenever it changes in a way older servers can't restore.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The registered data sources and logged data of every manager, to be restored on another server.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Snapshot {
+    pub version: u32,
+    /// When the snapshot was taken, in milliseconds since the unix epoch.
+    pub created_at: u64,
+    pub managers: Vec<ManagerSnapshot>,
+    /// Hashes of the tokens issued to data sources, so data sources keep using the same tokens.
+    pub tokens: Vec<TokenSnapshot>,
+}
+
+/// Read before the rest of a snapshot, so snapshots of other versions are rejected with a clear error,
+/// rather than failing to parse.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SnapshotVersion {
+    version: u32,
+}
+
+impl Snapshot {
+    /// Parses a snapshot, failing if it was taken by a server using another version of the format.
+    pub fn parse(snapshot: &[u8]) -> Result<Snapshot> {
+        let SnapshotVersion { version } = serde_json::from_slice(snapshot)
+            .map_err(|err| ManagerAndDataError::InvalidSnapshot(err.to_string()))?;
+        if version != SNAPSHOT_VERSION {
+            return Err(ManagerAndDataError::InvalidSnapshot(
+                format!("snapshot is of version {}, but this server only restores version {}", version, SNAPSHOT_VERSION)
+            ));
+        }
+
+        serde_json::from_slice(snapshot).map_err(|err| ManagerAndDataError::InvalidSnapshot(err.to_string()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ManagerSnapshot {
+    pub manager_id: String,
+    pub data_sources: Vec<DataSourceSnapshot>,
+}
+
+/// A data source, along with everything Florust keeps for it.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DataSourceSnapshot {
+    pub id: String,
+    pub registered: bool,
+    pub metadata: DataSourceMetadata,
+    /// Logged samples, oldest first.
+    pub samples: Vec<Sample<DataType>>,
+    pub rollups: Vec<RollupTierSnapshot>,
+    /// Whether samples older than the oldest of `samples` were dropped.
+    #[serde(default)]
+    pub truncated: bool,
+    pub dropped_samples: u64,
+    /// Timestamps are in milliseconds since the unix epoch.
+    pub registered_at: u64,
+    pub deregistered_at: Option<u64>,
+    pub last_seen: Option<u64>,
+    pub expected_interval_secs: Option<u64>,
+    /// Commands of the data source, completed ones first, oldest first.
+    pub commands: Vec<Command>,
+    /// Data the data source registered with, passed to the manager again when the data source is restored.
+    #[serde(default)]
+    pub registration_data: Option<Vec<u8>>,
+}
+
+/// What a restore brought back.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RestoreSummary {
+    pub data_sources: usize,
+    pub samples: usize,
+}
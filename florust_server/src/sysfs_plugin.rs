@@ -0,0 +1,375 @@
+//! Built-in plugin reading the hardware sensors Linux exposes through sysfs: 1-wire temperature probes, like
+//! the DS18B20, under `/sys/bus/w1/devices`, and the sensors of hwmon drivers, like those of I²C chips, under
+//! `/sys/class/hwmon`. See `docs/plugins/sysfs.md`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use florust_common::{DataSourceMetadata, server::{self, DataSourceManager, DataSourceManagerError, PluginHealth, PolledSource}};
+use log::{info, warn};
+use rocket::{async_trait, serde::{Serialize, Deserialize}, tokio};
+
+fn default_manager_id() -> String { "FlorustSysfs".to_string() }
+fn default_root() -> PathBuf { PathBuf::from("/sys") }
+
+/// Family codes of the 1-wire temperature probes whose `w1_slave` file reads as the DS18B20's.
+const W1_THERMOMETER_FAMILIES: [&str; 5] = ["10", "22", "28", "3b", "42"];
+
+/// Inputs of hwmon sensors which are read, along with their unit, and the divisor turning their raw value
+/// into that unit.
+const HWMON_INPUTS: [(&str, &str, f64); 6] = [
+    ("temp", "°C", 1000.0),
+    ("humidity", "%", 1000.0),
+    ("in", "V", 1000.0),
+    ("curr", "A", 1000.0),
+    ("power", "W", 1_000_000.0),
+    ("fan", "RPM", 1.0),
+];
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct SysfsConfig {
+    #[serde(default = "default_manager_id")]
+    pub manager_id: String,
+    /// Where sysfs is mounted.
+    #[serde(default = "default_root")]
+    pub root: PathBuf,
+    /// How often sensors are polled, in seconds, unless the polling configuration says otherwise.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+}
+
+impl Default for SysfsConfig {
+    fn default() -> Self {
+        SysfsConfig {
+            manager_id: default_manager_id(),
+            root: default_root(),
+            interval_secs: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SensorKind {
+    /// A 1-wire probe, whose `w1_slave` file holds a CRC check and the temperature in millidegrees.
+    W1,
+    /// An input of a hwmon sensor, whose file holds an integer, divided by the divisor.
+    Hwmon { divisor: f64 },
+}
+
+struct Sensor {
+    path: PathBuf,
+    kind: SensorKind,
+    metadata: DataSourceMetadata,
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|contents| contents.trim().to_string())
+}
+
+/// Lists the entries of a directory, by name, sorted so data source ids don't depend on the order the kernel
+/// lists them in.
+fn sorted_entries(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut entries: Vec<_> = fs::read_dir(dir).into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.path())))
+        .collect();
+    entries.sort();
+
+    entries
+}
+
+/// Finds the 1-wire temperature probes, whose data sources are named after their serial number, like
+/// `28-0316a2794aff`.
+fn discover_w1(root: &Path, sensors: &mut BTreeMap<String, Sensor>) {
+    for (name, path) in sorted_entries(&root.join("bus/w1/devices")) {
+        let is_thermometer = name.split_once('-')
+            .is_some_and(|(family, _)| W1_THERMOMETER_FAMILIES.contains(&family.to_lowercase().as_str()));
+        let path = path.join("w1_slave");
+        if !is_thermometer || !path.is_file() {
+            continue;
+        }
+
+        let metadata = DataSourceMetadata {
+            display_name: None,
+            units: Some("°C".to_string()),
+            tags: BTreeMap::from([("bus".to_string(), "w1".to_string())]),
+        };
+        sensors.insert(name, Sensor { path, kind: SensorKind::W1, metadata });
+    }
+}
+
+/// Finds the inputs of the hwmon sensors, whose data sources are named after the sensor's driver and the
+/// input, like `cpu_thermal-temp1`, or after the sensor as well, like `lm75-hwmon2-temp1`, when more than one
+/// sensor has the same driver.
+fn discover_hwmon(root: &Path, sensors: &mut BTreeMap<String, Sensor>) {
+    let hwmons: Vec<_> = sorted_entries(&root.join("class/hwmon")).into_iter()
+        .map(|(hwmon, path)| (read_trimmed(&path.join("name")).unwrap_or_else(|| hwmon.clone()), hwmon, path))
+        .collect();
+
+    for (name, hwmon, path) in &hwmons {
+        let shared = hwmons.iter().filter(|(other, _, _)| other == name).count() > 1;
+
+        for (file, input_path) in sorted_entries(path) {
+            let Some(input) = file.strip_suffix("_input") else {
+                continue;
+            };
+            let Some((prefix, unit, divisor)) = HWMON_INPUTS.iter()
+                .find(|(prefix, _, _)| input.strip_prefix(prefix).is_some_and(|index| index.parse::<u32>().is_ok())) else {
+                continue;
+            };
+
+            let id = if shared { format!("{}-{}-{}", name, hwmon, input) } else { format!("{}-{}", name, input) };
+            let metadata = DataSourceMetadata {
+                display_name: read_trimmed(&path.join(format!("{}_label", input))),
+                units: Some(unit.to_string()),
+                tags: BTreeMap::from([
+                    ("bus".to_string(), "hwmon".to_string()),
+                    ("chip".to_string(), name.clone()),
+                    ("sensor".to_string(), prefix.to_string()),
+                ]),
+            };
+            sensors.insert(id, Sensor { path: input_path, kind: SensorKind::Hwmon { divisor: *divisor }, metadata });
+        }
+    }
+}
+
+/// Reads the value of a sensor from the contents of its file.
+fn parse(kind: SensorKind, contents: &str) -> Result<f64, String> {
+    match kind {
+        SensorKind::W1 => {
+            let mut lines = contents.lines();
+            if !lines.next().is_some_and(|line| line.trim_end().ends_with("YES")) {
+                return Err("CRC check failed".to_string());
+            }
+
+            lines.next()
+                .and_then(|line| line.split_once("t="))
+                .and_then(|(_, millidegrees)| millidegrees.trim().parse::<i64>().ok())
+                .map(|millidegrees| millidegrees as f64 / 1000.0)
+                .ok_or_else(|| "no temperature".to_string())
+        },
+        SensorKind::Hwmon { divisor } => contents.trim().parse::<i64>()
+            .map(|value| value as f64 / divisor)
+            .map_err(|_| format!("{} isn't a number", contents.trim())),
+    }
+}
+
+pub struct SysfsDataManager {
+    manager_id: &'static str,
+    config: SysfsConfig,
+    /// Sensors found when the server launched, by the id of their data source.
+    sensors: BTreeMap<String, Sensor>,
+    /// Why the last poll of each sensor failed, for those whose last poll failed.
+    failing: Mutex<HashMap<String, String>>,
+}
+
+impl SysfsDataManager {
+    /// Creates the manager, finding the sensors right away, so their data sources can be registered when a
+    /// snapshot is restored, before they are polled.
+    pub fn new(config: SysfsConfig) -> SysfsDataManager {
+        let mut sensors = BTreeMap::new();
+        discover_w1(&config.root, &mut sensors);
+        discover_hwmon(&config.root, &mut sensors);
+
+        if sensors.is_empty() {
+            warn!("No sensors found in {}", config.root.display());
+        }
+        else {
+            info!("Found {} sensors in {}", sensors.len(), config.root.display());
+        }
+
+        SysfsDataManager {
+            // The id must outlive the manager, which is only created once.
+            manager_id: Box::leak(config.manager_id.clone().into_boxed_str()),
+            config,
+            sensors,
+            failing: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn sensor(&self, id: &str) -> server::Result<(PathBuf, SensorKind)> {
+        self.sensors.get(id)
+            .map(|sensor| (sensor.path.clone(), sensor.kind))
+            .ok_or_else(|| DataSourceManagerError::InvalidData(format!("{} isn't a sensor found in sysfs", id)))
+    }
+
+    fn record(&self, id: &str, error: Option<String>) {
+        if let Ok(mut failing) = self.failing.lock() {
+            match error {
+                Some(error) => failing.insert(id.to_string(), error),
+                None => failing.remove(id),
+            };
+        }
+    }
+}
+
+#[async_trait]
+impl DataSourceManager<f64> for SysfsDataManager {
+    fn manager_id(&self) -> &'static str {
+        self.manager_id
+    }
+
+    async fn register(&self, id: String) -> server::Result<()> {
+        self.sensor(&id).map(|_| ())
+    }
+
+    async fn register_with_data(&self, id: String, _data: &[u8]) -> server::Result<()> {
+        self.sensor(&id).map(|_| ())
+    }
+
+    async fn deregister(&self, _id: &str) -> server::Result<()> {
+        Ok(())
+    }
+
+    async fn deregister_with_data(&self, _id: &str, _data: &[u8]) -> server::Result<()> {
+        Ok(())
+    }
+
+    async fn update_data(&self, id: &str, _data: &[u8]) -> server::Result<f64> {
+        Err(DataSourceManagerError::InvalidData(format!("{} is read from sysfs, and can't be uploaded", id)))
+    }
+
+    /// Each sensor found is a polled data source. Sensors appearing after the server launched are only found
+    /// once it is restarted.
+    async fn polled_sources(&self) -> Vec<PolledSource> {
+        self.sensors.iter()
+            .map(|(id, sensor)| PolledSource {
+                id: id.clone(),
+                metadata: sensor.metadata.clone(),
+                interval_secs: self.config.interval_secs,
+            })
+            .collect()
+    }
+
+    async fn poll(&self, id: &str) -> server::Result<f64> {
+        let (path, kind) = self.sensor(id)?;
+
+        // Reading a 1-wire probe blocks while it measures, which takes up to 750ms.
+        let value = tokio::fs::read_to_string(&path).await
+            .map_err(|err| err.to_string())
+            .and_then(|contents| parse(kind, &contents));
+
+        self.record(id, value.as_ref().err().cloned());
+        value.map_err(|err| DataSourceManagerError::PollFailed(format!("{}: {}", path.display(), err)))
+    }
+
+    async fn health(&self) -> PluginHealth {
+        if self.sensors.is_empty() {
+            return PluginHealth::Degraded(format!("no sensors found in {}", self.config.root.display()));
+        }
+
+        let Ok(failing) = self.failing.lock() else {
+            return PluginHealth::Healthy;
+        };
+        if failing.is_empty() {
+            return PluginHealth::Healthy;
+        }
+
+        let mut failing: Vec<_> = failing.iter().map(|(id, error)| format!("{}: {}", id, error)).collect();
+        failing.sort();
+        PluginHealth::Degraded(failing.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, fs, path::{Path, PathBuf}};
+
+    use super::{SensorKind, discover_hwmon, discover_w1, parse};
+
+    /// A sysfs tree in a directory of its own, removed once dropped.
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new(name: &str) -> Tree {
+            let root = std::env::temp_dir().join(format!("florust-sysfs-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            Tree(root)
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        fn root(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn w1_thermometers_are_found() {
+        let tree = Tree::new("w1");
+        tree.write("bus/w1/devices/28-0316a2794aff/w1_slave", "");
+        tree.write("bus/w1/devices/10-000802b4d5ef/w1_slave", "");
+        // An EEPROM, which isn't a thermometer.
+        tree.write("bus/w1/devices/2d-00000a1b2c3d/w1_slave", "");
+        tree.write("bus/w1/devices/w1_bus_master1/uevent", "");
+
+        let mut sensors = BTreeMap::new();
+        discover_w1(tree.root(), &mut sensors);
+
+        assert_eq!(sensors.keys().collect::<Vec<_>>(), ["10-000802b4d5ef", "28-0316a2794aff"]);
+        let sensor = &sensors["28-0316a2794aff"];
+        assert_eq!(sensor.path, tree.root().join("bus/w1/devices/28-0316a2794aff/w1_slave"));
+        assert_eq!(sensor.metadata.units.as_deref(), Some("°C"));
+    }
+
+    #[test]
+    fn hwmon_inputs_are_found() {
+        let tree = Tree::new("hwmon");
+        tree.write("class/hwmon/hwmon0/name", "cpu_thermal\n");
+        tree.write("class/hwmon/hwmon0/temp1_input", "");
+        tree.write("class/hwmon/hwmon0/temp1_label", "CPU\n");
+        tree.write("class/hwmon/hwmon0/temp1_crit", "");
+        tree.write("class/hwmon/hwmon0/fan1_input", "");
+        // Two sensors of the same driver, which are told apart by their hwmon.
+        tree.write("class/hwmon/hwmon1/name", "lm75\n");
+        tree.write("class/hwmon/hwmon1/temp1_input", "");
+        tree.write("class/hwmon/hwmon2/name", "lm75\n");
+        tree.write("class/hwmon/hwmon2/temp1_input", "");
+
+        let mut sensors = BTreeMap::new();
+        discover_hwmon(tree.root(), &mut sensors);
+
+        assert_eq!(
+            sensors.keys().collect::<Vec<_>>(),
+            ["cpu_thermal-fan1", "cpu_thermal-temp1", "lm75-hwmon1-temp1", "lm75-hwmon2-temp1"]
+        );
+        let temp = &sensors["cpu_thermal-temp1"];
+        assert_eq!(temp.metadata.display_name.as_deref(), Some("CPU"));
+        assert_eq!(temp.metadata.tags["chip"], "cpu_thermal");
+        assert_eq!(sensors["cpu_thermal-fan1"].metadata.units.as_deref(), Some("RPM"));
+    }
+
+    #[test]
+    fn w1_readings_are_parsed() {
+        let reading = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse(SensorKind::W1, reading), Ok(23.125));
+
+        let failed = "72 01 4b 46 7f ff 0e 10 57 : crc=00 NO\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert!(parse(SensorKind::W1, failed).is_err());
+
+        let no_temperature = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57\n";
+        assert!(parse(SensorKind::W1, no_temperature).is_err());
+    }
+
+    #[test]
+    fn hwmon_readings_are_parsed() {
+        assert_eq!(parse(SensorKind::Hwmon { divisor: 1000.0 }, "-1500\n"), Ok(-1.5));
+        assert!(parse(SensorKind::Hwmon { divisor: 1000.0 }, "N/A\n").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::manager_and_data::ManagerAndDataError;
+
+/// The physical quantities Florust knows how to convert between units of.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Quantity {
+    Temperature,
+    Length,
+    Pressure,
+    Volume,
+    Ratio,
+}
+
+/// A unit, defined by how its values convert to the base unit of its quantity: `base = value * scale + offset`.
+struct Unit {
+    names: &'static [&'static str],
+    quantity: Quantity,
+    scale: f64,
+    offset: f64,
+}
+
+const fn unit(names: &'static [&'static str], quantity: Quantity, scale: f64, offset: f64) -> Unit {
+    Unit { names, quantity, scale, offset }
+}
+
+const UNITS: &[Unit] = &[
+    // Temperatures convert to kelvin.
+    unit(&["K", "kelvin"], Quantity::Temperature, 1.0, 0.0),
+    unit(&["°C", "C", "degC", "celsius"], Quantity::Temperature, 1.0, 273.15),
+    unit(&["°F", "F", "degF", "fahrenheit"], Quantity::Temperature, 5.0 / 9.0, 273.15 - 32.0 * 5.0 / 9.0),
+    // Lengths convert to metres.
+    unit(&["mm", "millimetre", "millimeter"], Quantity::Length, 0.001, 0.0),
+    unit(&["cm", "centimetre", "centimeter"], Quantity::Length, 0.01, 0.0),
+    unit(&["m", "metre", "meter"], Quantity::Length, 1.0, 0.0),
+    unit(&["km", "kilometre", "kilometer"], Quantity::Length, 1000.0, 0.0),
+    unit(&["in", "inch"], Quantity::Length, 0.0254, 0.0),
+    unit(&["ft", "foot"], Quantity::Length, 0.3048, 0.0),
+    unit(&["yd", "yard"], Quantity::Length, 0.9144, 0.0),
+    unit(&["mi", "mile"], Quantity::Length, 1609.344, 0.0),
+    // Pressures convert to pascals.
+    unit(&["Pa", "pascal"], Quantity::Pressure, 1.0, 0.0),
+    unit(&["hPa"], Quantity::Pressure, 100.0, 0.0),
+    unit(&["kPa"], Quantity::Pressure, 1000.0, 0.0),
+    unit(&["mbar"], Quantity::Pressure, 100.0, 0.0),
+    unit(&["bar"], Quantity::Pressure, 100_000.0, 0.0),
+    unit(&["psi"], Quantity::Pressure, 6894.757293168, 0.0),
+    unit(&["atm"], Quantity::Pressure, 101_325.0, 0.0),
+    unit(&["mmHg"], Quantity::Pressure, 133.322387415, 0.0),
+    unit(&["inHg"], Quantity::Pressure, 3386.389, 0.0),
+    // Volumes convert to cubic metres.
+    unit(&["ml", "mL", "millilitre", "milliliter"], Quantity::Volume, 1e-6, 0.0),
+    unit(&["l", "L", "litre", "liter"], Quantity::Volume, 0.001, 0.0),
+    unit(&["m³", "m3"], Quantity::Volume, 1.0, 0.0),
+    unit(&["gal", "gallon"], Quantity::Volume, 0.003_785_411_784, 0.0),
+    unit(&["fl_oz", "fl oz"], Quantity::Volume, 2.957_352_956_25e-5, 0.0),
+    // Ratios convert to fractions of one.
+    unit(&["ratio", "fraction"], Quantity::Ratio, 1.0, 0.0),
+    unit(&["%", "percent"], Quantity::Ratio, 0.01, 0.0),
+    unit(&["‰", "permille"], Quantity::Ratio, 0.001, 0.0),
+];
+
+fn find_unit(name: &str) -> Result<&'static Unit, ManagerAndDataError> {
+    UNITS.iter()
+        .find(|unit| unit.names.contains(&name))
+        .ok_or_else(|| ManagerAndDataError::InvalidUnit(format!("unknown unit: {}", name)))
+}
+
+/// A linear conversion between two units of the same quantity.
+#[derive(Clone, Copy, Debug)]
+pub struct Conversion {
+    scale: f64,
+    offset: f64,
+}
+
+impl Conversion {
+    /// Finds the conversion from values in `from` to values in `to`. Fails if either unit is unknown, or the
+    /// units measure different quantities.
+    pub fn between(from: &str, to: &str) -> Result<Conversion, ManagerAndDataError> {
+        if from == to {
+            return Ok(Conversion { scale: 1.0, offset: 0.0 });
+        }
+
+        let (from_unit, to_unit) = (find_unit(from)?, find_unit(to)?);
+        if from_unit.quantity != to_unit.quantity {
+            return Err(ManagerAndDataError::InvalidUnit(format!("can't convert from {} to {}", from, to)));
+        }
+
+        Ok(Conversion {
+            scale: from_unit.scale / to_unit.scale,
+            offset: (from_unit.offset - to_unit.offset) / to_unit.scale,
+        })
+    }
+
+    pub fn apply(&self, value: f64) -> f64 {
+        value * self.scale + self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Conversion, UNITS};
+
+    fn convert(value: f64, from: &str, to: &str) -> f64 {
+        Conversion::between(from, to).unwrap().apply(value)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn temperatures_convert_with_offsets() {
+        assert_close(convert(100.0, "°C", "°F"), 212.0);
+        assert_close(convert(32.0, "fahrenheit", "celsius"), 0.0);
+        assert_close(convert(-40.0, "C", "F"), -40.0);
+        assert_close(convert(0.0, "K", "degC"), -273.15);
+    }
+
+    #[test]
+    fn other_quantities_convert_by_scale() {
+        assert_close(convert(1.0, "in", "mm"), 25.4);
+        assert_close(convert(1.0, "atm", "hPa"), 1013.25);
+        assert_close(convert(1.0, "gal", "l"), 3.785_411_784);
+        assert_close(convert(45.0, "%", "ratio"), 0.45);
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        for from in UNITS {
+            for to in UNITS.iter().filter(|to| to.quantity == from.quantity) {
+                let (from, to) = (from.names[0], to.names[0]);
+                let value = convert(convert(21.5, from, to), to, from);
+                assert!((value - 21.5).abs() < 1e-6, "{} to {} and back gave {}", from, to, value);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_and_mismatched_units_are_rejected() {
+        assert!(Conversion::between("°C", "furlong").is_err());
+        assert!(Conversion::between("parsec", "m").is_err());
+        assert!(Conversion::between("°C", "m").is_err());
+        assert!(Conversion::between("%", "psi").is_err());
+        // Identical units convert even if unknown, like custom units of plugins.
+        assert_close(convert(7.0, "lux", "lux"), 7.0);
+    }
+}